@@ -0,0 +1,53 @@
+// measures how fast to_initrd encodes entries which are already in memory
+//
+// many small files mostly stress building the entry table and padding,
+// while a few large files mostly stress copying data into the image
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+
+use gen_initrd::{to_initrd, Entry, EntryType, InitrdOptions, ALIGN};
+
+// sizes vary between min_size and 2 * min_size so names and data are not all equally aligned
+fn synthetic_entries(names: &[String], min_size: usize) -> Vec<Entry<'_>> {
+	names.iter().enumerate().map(|(i, name)| {
+		let size = min_size + (i * 7919) % (min_size + 1);
+
+		Entry {
+			typ: EntryType::Any,
+			name,
+			data: vec![(i % 256) as u8; size],
+			uid: 0,
+			gid: 0,
+			align: ALIGN,
+			source: None,
+			tags: Vec::new(),
+			compressed: None,
+		}
+	}).collect()
+}
+
+fn bench_encode(c: &mut Criterion) {
+	let mut group = c.benchmark_group("to_initrd");
+
+	let scenarios = [
+		("many-small-files", 10_000, 64),
+		("few-large-files", 4, 16 * 1024 * 1024),
+	];
+
+	for (scenario, count, min_size) in scenarios.iter() {
+		let names: Vec<String> = (0..*count).map(|i| format!("files/file-{}", i)).collect();
+		let entries = synthetic_entries(&names, *min_size);
+
+		let total_size: usize = entries.iter().map(|entry| entry.data.len()).sum();
+		group.throughput(Throughput::Bytes(total_size as u64));
+
+		group.bench_with_input(BenchmarkId::from_parameter(scenario), &entries, |b, entries| {
+			b.iter(|| to_initrd(entries, &InitrdOptions::default()))
+		});
+	}
+
+	group.finish();
+}
+
+criterion_group!(benches, bench_encode);
+criterion_main!(benches);
@@ -0,0 +1,46 @@
+#ifndef GEN_INITRD_H
+#define GEN_INITRD_H
+
+/* generated by cbindgen from src/ffi.rs, do not edit */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+typedef enum GenInitrdStatus {
+  GEN_INITRD_STATUS_OK = 0,
+  GEN_INITRD_STATUS_NULL = -1,
+  GEN_INITRD_STATUS_TRUNCATED = -2,
+  GEN_INITRD_STATUS_BAD_MAGIC = -3,
+  GEN_INITRD_STATUS_UNKNOWN_FLAGS = -4,
+  GEN_INITRD_STATUS_BAD_TRAILER = -5,
+  GEN_INITRD_STATUS_NAME_OUT_OF_BOUNDS = -6,
+  GEN_INITRD_STATUS_DATA_OUT_OF_BOUNDS = -7,
+  GEN_INITRD_STATUS_NAME_NOT_UTF8 = -8,
+  GEN_INITRD_STATUS_BAD_TRIM_LENGTH = -9,
+  GEN_INITRD_STATUS_BAD_INDEX = -10,
+  GEN_INITRD_STATUS_BAD_COMPRESSION = -11,
+} GenInitrdStatus;
+
+typedef struct GenInitrdEntry {
+  uint64_t typ;
+  const uint8_t *name;
+  size_t name_len;
+  const uint8_t *data;
+  size_t data_len;
+  uint64_t len;
+  uint64_t compression;
+} GenInitrdEntry;
+
+enum GenInitrdStatus gen_initrd_validate(const uint8_t *image, size_t len);
+
+enum GenInitrdStatus gen_initrd_entry_count(const uint8_t *image, size_t len, uint64_t *count);
+
+enum GenInitrdStatus gen_initrd_get_entry(const uint8_t *image,
+                                          size_t len,
+                                          uint64_t index,
+                                          struct GenInitrdEntry *entry);
+
+#endif  /* GEN_INITRD_H */
@@ -0,0 +1,54 @@
+// --audit-log, which appends a line describing every successful build to a file
+//
+//	2026-10-14T18:33:07Z	build/initrd.img	<sha256 of the initrd>	12 entries	gen-initrd 0.1.0
+//
+// fields are tab separated and the time is utc
+
+use std::fs::OpenOptions;
+use std::io::{self, Write};
+use std::process::exit;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::exit_code;
+use crate::lock::sha256_hex;
+
+// converts days since the unix epoch to a year, month, and day in the proleptic gregorian calendar
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+	let days = days + 719_468;
+	let era = days.div_euclid(146_097);
+	let day_of_era = days.rem_euclid(146_097);
+	let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+	let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+	let shifted_month = (5 * day_of_year + 2) / 153;
+	let day = (day_of_year - (153 * shifted_month + 2) / 5 + 1) as u32;
+	let month = if shifted_month < 10 { shifted_month + 3 } else { shifted_month - 9 } as u32;
+	let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+	(year, month, day)
+}
+
+// current utc time in rfc 3339 format
+fn timestamp() -> String {
+	let secs = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |time| time.as_secs()) as i64;
+	let (year, month, day) = civil_from_days(secs.div_euclid(86400));
+	let time = secs.rem_euclid(86400);
+	format!("{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z", year, month, day, time / 3600, time / 60 % 60, time % 60)
+}
+
+fn append(path: &str, line: &str) -> io::Result<()> {
+	let mut file = OpenOptions::new().append(true).create(true).open(path)?;
+	file.write_all(line.as_bytes())
+}
+
+// appends a line for a build of initrd with entry_count entries to output to the log at path,
+// a failed write is only a warning unless strict is set
+pub fn record(path: &str, output: &str, initrd: &[u8], entry_count: usize, strict: bool) {
+	let line = format!("{}\t{}\t{}\t{} entries\tgen-initrd {}\n", timestamp(), output, sha256_hex(initrd), entry_count, env!("CARGO_PKG_VERSION"));
+
+	if let Err(err) = append(path, &line) {
+		if strict {
+			error!("Could not append to audit log {}: {}", path, err);
+			exit(exit_code::WRITE_FAILED);
+		}
+		warning!("could not append to audit log {}: {}", path, err);
+	}
+}
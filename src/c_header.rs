@@ -0,0 +1,44 @@
+// --format c-header, which writes the initrd as a c array so it can be included directly in a kernel build
+//
+//	static const unsigned char initrd[] __attribute__((aligned(4096))) = {
+//		0x36, 0xe8, 0x92, 0x4b, 0xaa, 0x98, 0xf2, 0x39, 0x06, 0x00, 0x00, 0x00,
+//		...
+//	};
+//	static const unsigned long initrd_len = 4096;
+
+use std::fmt::Write;
+
+// bytes written on each line of the array
+const BYTES_PER_LINE: usize = 12;
+
+// returns true if symbol can be used as the name of a c variable
+pub fn valid_symbol(symbol: &str) -> bool {
+	let mut chars = symbol.chars();
+	chars.next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+		&& chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+// align is the largest alignment of any entry, so entries stay aligned in memory
+pub fn generate(initrd: &[u8], symbol: &str, align: u64) -> String {
+	let guard = format!("{}_H", symbol.to_ascii_uppercase());
+	let mut out = String::new();
+
+	// writing to a string can not fail
+	writeln!(out, "// generated by gen-initrd").unwrap();
+	writeln!(out, "#ifndef {}", guard).unwrap();
+	writeln!(out, "#define {}", guard).unwrap();
+	writeln!(out).unwrap();
+	writeln!(out, "static const unsigned char {}[] __attribute__((aligned({}))) = {{", symbol, align).unwrap();
+
+	for line in initrd.chunks(BYTES_PER_LINE) {
+		let bytes: Vec<String> = line.iter().map(|byte| format!("{:#04x},", byte)).collect();
+		writeln!(out, "\t{}", bytes.join(" ")).unwrap();
+	}
+
+	writeln!(out, "}};").unwrap();
+	writeln!(out, "static const unsigned long {}_len = {};", symbol, initrd.len()).unwrap();
+	writeln!(out).unwrap();
+	writeln!(out, "#endif").unwrap();
+
+	out
+}
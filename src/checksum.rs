@@ -0,0 +1,324 @@
+// checksum of a whole image, written when FLAG_CHECKSUM is set
+//
+// the checksum covers every byte of the image with HeaderExt.checksum read as zero, and is computed last so it also
+// covers the merkle tree and trailer, it is stored little endian at the start of HeaderExt.checksum with the rest
+// left zero, so crc32 takes 4 bytes, xxhash 8, and sha256 all 32
+//
+// it only detects corruption, anyone changing the image can compute it again, which a signature prevents
+
+use std::cmp;
+use std::convert::TryInto;
+use std::fmt;
+use std::io::{self, Cursor, Read, Seek, SeekFrom};
+use std::mem::size_of;
+use std::ops::Range;
+
+use sha2::{Digest, Sha256};
+
+use crate::{Hash, HeaderExt};
+
+// stored in HeaderExt.checksum_algorithm
+#[repr(u64)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChecksumAlgorithm {
+	Crc32 = 1,
+	// xxh64 with a seed of 0
+	XxHash = 2,
+	Sha256 = 3,
+}
+
+impl ChecksumAlgorithm {
+	pub const ALL: [ChecksumAlgorithm; 3] = [
+		Self::Crc32,
+		Self::XxHash,
+		Self::Sha256,
+	];
+
+	pub fn from_name(name: &str) -> Option<Self> {
+		Self::ALL.iter().copied().find(|algorithm| algorithm.name() == name)
+	}
+
+	pub fn from_u64(algorithm: u64) -> Option<Self> {
+		Self::ALL.iter().copied().find(|known| *known as u64 == algorithm)
+	}
+
+	pub fn name(&self) -> &'static str {
+		match self {
+			Self::Crc32 => "crc32",
+			Self::XxHash => "xxhash",
+			Self::Sha256 => "sha256",
+		}
+	}
+}
+
+// the checksum of an image and its algorithm, read from the extended header
+#[derive(Debug, Clone, Copy)]
+pub struct ChecksumHeader {
+	// a ChecksumAlgorithm, which may be one added by a newer version
+	pub algorithm: u64,
+	pub checksum: Hash,
+}
+
+impl ChecksumHeader {
+	// the checksum as lowercase hex, crc32 and xxhash as the integers they are and sha256 like sha256sum
+	pub fn to_hex(&self) -> String {
+		match ChecksumAlgorithm::from_u64(self.algorithm) {
+			Some(ChecksumAlgorithm::Crc32) => format!("{:08x}", u32::from_le_bytes(self.checksum.0[..4].try_into().unwrap())),
+			Some(ChecksumAlgorithm::XxHash) => format!("{:016x}", read_u64(&self.checksum.0)),
+			_ => self.checksum.to_string(),
+		}
+	}
+}
+
+// position of HeaderExt.checksum in the image, which is read as zero
+fn checksum_range() -> Range<usize> {
+	HeaderExt::CHECKSUM_OFFSET..HeaderExt::CHECKSUM_OFFSET + size_of::<Hash>()
+}
+
+// crc32 with the polynomial used by zlib and gzip
+pub fn crc32(bytes: &[u8]) -> u32 {
+	let mut hasher = Hasher::new(ChecksumAlgorithm::Crc32);
+	hasher.update(bytes);
+	u32::from_le_bytes(hasher.finish().0[..4].try_into().unwrap())
+}
+
+const PRIME64_1: u64 = 0x9e3779b185ebca87;
+const PRIME64_2: u64 = 0xc2b2ae3d27d4eb4f;
+const PRIME64_3: u64 = 0x165667b19e3779f9;
+const PRIME64_4: u64 = 0x85ebca77c2b2ae63;
+const PRIME64_5: u64 = 0x27d4eb2f165667c5;
+
+fn xxh64_round(acc: u64, input: u64) -> u64 {
+	acc.wrapping_add(input.wrapping_mul(PRIME64_2)).rotate_left(31).wrapping_mul(PRIME64_1)
+}
+
+fn xxh64_merge_round(acc: u64, val: u64) -> u64 {
+	(acc ^ xxh64_round(0, val)).wrapping_mul(PRIME64_1).wrapping_add(PRIME64_4)
+}
+
+fn read_u64(bytes: &[u8]) -> u64 {
+	u64::from_le_bytes(bytes[..8].try_into().unwrap())
+}
+
+// xxh64 with a seed of 0, fed 32 byte stripes at a time
+struct XxHash64 {
+	acc: [u64; 4],
+	// bytes which do not yet make a whole stripe
+	buffer: Vec<u8>,
+	total_len: u64,
+}
+
+impl XxHash64 {
+	fn new() -> Self {
+		XxHash64 {
+			acc: [PRIME64_1.wrapping_add(PRIME64_2), PRIME64_2, 0, PRIME64_1.wrapping_neg()],
+			buffer: Vec::with_capacity(32),
+			total_len: 0,
+		}
+	}
+
+	fn update(&mut self, mut bytes: &[u8]) {
+		self.total_len += bytes.len() as u64;
+
+		while !bytes.is_empty() {
+			let take = cmp::min(32 - self.buffer.len(), bytes.len());
+			self.buffer.extend_from_slice(&bytes[..take]);
+			bytes = &bytes[take..];
+
+			if self.buffer.len() == 32 {
+				for (i, acc) in self.acc.iter_mut().enumerate() {
+					*acc = xxh64_round(*acc, read_u64(&self.buffer[i * 8..]));
+				}
+				self.buffer.clear();
+			}
+		}
+	}
+
+	fn finish(&self) -> u64 {
+		let [v1, v2, v3, v4] = self.acc;
+
+		let mut hash = if self.total_len >= 32 {
+			let hash = v1.rotate_left(1).wrapping_add(v2.rotate_left(7)).wrapping_add(v3.rotate_left(12)).wrapping_add(v4.rotate_left(18));
+			self.acc.iter().fold(hash, |hash, acc| xxh64_merge_round(hash, *acc))
+		} else {
+			PRIME64_5
+		};
+		hash = hash.wrapping_add(self.total_len);
+
+		let mut rest = &self.buffer[..];
+		while rest.len() >= 8 {
+			hash ^= xxh64_round(0, read_u64(rest));
+			hash = hash.rotate_left(27).wrapping_mul(PRIME64_1).wrapping_add(PRIME64_4);
+			rest = &rest[8..];
+		}
+		if rest.len() >= 4 {
+			hash ^= (u32::from_le_bytes(rest[..4].try_into().unwrap()) as u64).wrapping_mul(PRIME64_1);
+			hash = hash.rotate_left(23).wrapping_mul(PRIME64_2).wrapping_add(PRIME64_3);
+			rest = &rest[4..];
+		}
+		for byte in rest {
+			hash ^= (*byte as u64).wrapping_mul(PRIME64_5);
+			hash = hash.rotate_left(11).wrapping_mul(PRIME64_1);
+		}
+
+		hash ^= hash >> 33;
+		hash = hash.wrapping_mul(PRIME64_2);
+		hash ^= hash >> 29;
+		hash = hash.wrapping_mul(PRIME64_3);
+		hash ^ (hash >> 32)
+	}
+}
+
+// computes a checksum from bytes given a piece at a time
+enum Hasher {
+	Crc32(u32),
+	XxHash(XxHash64),
+	Sha256(Sha256),
+}
+
+impl Hasher {
+	fn new(algorithm: ChecksumAlgorithm) -> Self {
+		match algorithm {
+			ChecksumAlgorithm::Crc32 => Self::Crc32(!0),
+			ChecksumAlgorithm::XxHash => Self::XxHash(XxHash64::new()),
+			ChecksumAlgorithm::Sha256 => Self::Sha256(Sha256::new()),
+		}
+	}
+
+	fn update(&mut self, bytes: &[u8]) {
+		match self {
+			Self::Crc32(crc) => {
+				for byte in bytes {
+					*crc ^= *byte as u32;
+					for _ in 0..8 {
+						*crc = if *crc & 1 != 0 { (*crc >> 1) ^ 0xedb88320 } else { *crc >> 1 };
+					}
+				}
+			},
+			Self::XxHash(hasher) => hasher.update(bytes),
+			Self::Sha256(hasher) => hasher.update(bytes),
+		}
+	}
+
+	// the checksum as it is stored in HeaderExt.checksum
+	fn finish(self) -> Hash {
+		let mut checksum = Hash::default();
+		match self {
+			Self::Crc32(crc) => checksum.0[..4].copy_from_slice(&(!crc).to_le_bytes()),
+			Self::XxHash(hasher) => checksum.0[..8].copy_from_slice(&hasher.finish().to_le_bytes()),
+			Self::Sha256(hasher) => checksum.0 = hasher.finalize().into(),
+		}
+		checksum
+	}
+}
+
+// the checksum of image with algorithm, with the checksum in its header read as zero
+pub fn compute(algorithm: ChecksumAlgorithm, image: &[u8]) -> Hash {
+	let range = checksum_range();
+	let mut hasher = Hasher::new(algorithm);
+	hasher.update(&image[..range.start]);
+	hasher.update(&[0; size_of::<Hash>()]);
+	hasher.update(&image[range.end..]);
+	hasher.finish()
+}
+
+#[derive(Debug)]
+pub enum ChecksumError {
+	// the algorithm was added by a newer version, so the checksum can not be checked
+	UnknownAlgorithm(u64),
+	// the image is too short to contain the checksum
+	Truncated,
+	Mismatch { algorithm: ChecksumAlgorithm, stored: ChecksumHeader, actual: ChecksumHeader },
+	Io(io::Error),
+}
+
+impl fmt::Display for ChecksumError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			Self::UnknownAlgorithm(algorithm) => write!(f, "checksum uses unknown algorithm {}", algorithm),
+			Self::Truncated => write!(f, "image is too short to contain its checksum"),
+			Self::Mismatch { algorithm, stored, actual } => write!(f, "{} checksum of the image is {} but the header has {}, the image is corrupt", algorithm.name(), actual.to_hex(), stored.to_hex()),
+			Self::Io(err) => write!(f, "could not read the image: {}", err),
+		}
+	}
+}
+
+// checks the checksum in the header of image
+pub fn check(image: &[u8], header: &ChecksumHeader) -> Result<(), ChecksumError> {
+	check_reader(&mut Cursor::new(image), image.len() as u64, header)
+}
+
+// checks the checksum in the header of an image image_len bytes long read from reader, a piece at a time
+pub fn check_reader<R: Read + Seek>(reader: &mut R, image_len: u64, header: &ChecksumHeader) -> Result<(), ChecksumError> {
+	let algorithm = ChecksumAlgorithm::from_u64(header.algorithm).ok_or(ChecksumError::UnknownAlgorithm(header.algorithm))?;
+
+	let range = checksum_range();
+	if image_len < range.end as u64 {
+		return Err(ChecksumError::Truncated);
+	}
+
+	let mut hasher = Hasher::new(algorithm);
+	let mut buffer = vec![0; 1 << 16];
+	let mut offset = 0;
+	reader.seek(SeekFrom::Start(0)).map_err(ChecksumError::Io)?;
+
+	while offset < image_len {
+		let len = cmp::min(buffer.len() as u64, image_len - offset) as usize;
+		reader.read_exact(&mut buffer[..len]).map_err(ChecksumError::Io)?;
+
+		// the checksum is read as zero, and is always in the first piece
+		if offset == 0 {
+			buffer[range.clone()].fill(0);
+		}
+
+		hasher.update(&buffer[..len]);
+		offset += len as u64;
+	}
+
+	let actual = ChecksumHeader {
+		algorithm: header.algorithm,
+		checksum: hasher.finish(),
+	};
+	if actual.checksum != header.checksum {
+		return Err(ChecksumError::Mismatch { algorithm, stored: *header, actual });
+	}
+
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn xxh64(bytes: &[u8]) -> u64 {
+		let mut hasher = XxHash64::new();
+		hasher.update(bytes);
+		hasher.finish()
+	}
+
+	#[test]
+	fn known_values() {
+		assert_eq!(crc32(b"123456789"), 0xcbf43926);
+		assert_eq!(xxh64(b""), 0xef46db3751d8e999);
+		assert_eq!(xxh64(b"a"), 0xd24ec4f1a98c6e5b);
+		assert_eq!(xxh64(b"abc"), 0x44bc2cf5ad770999);
+		assert_eq!(xxh64(b"Nobody inspects the spammish repetition"), 0xfbcea83c8a378bf1);
+	}
+
+	#[test]
+	fn pieces_match_whole() {
+		let bytes: Vec<u8> = (0..1000u32).map(|i| (i * 7) as u8).collect();
+
+		for algorithm in ChecksumAlgorithm::ALL.iter().copied() {
+			let mut whole = Hasher::new(algorithm);
+			whole.update(&bytes);
+			let whole = whole.finish();
+
+			let mut pieces = Hasher::new(algorithm);
+			for piece in bytes.chunks(13) {
+				pieces.update(piece);
+			}
+			assert_eq!(pieces.finish(), whole, "{}", algorithm.name());
+		}
+	}
+}
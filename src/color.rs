@@ -0,0 +1,50 @@
+// ansi colors for diagnostic output, controlled by --color and the NO_COLOR environment variable
+
+use std::env;
+use std::io::{self, IsTerminal};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+const RED: &str = "31";
+const GREEN: &str = "32";
+const YELLOW: &str = "33";
+
+// stderr and stdout are colored separately, since only one of them may be redirected
+static STDERR_ENABLED: AtomicBool = AtomicBool::new(false);
+static STDOUT_ENABLED: AtomicBool = AtomicBool::new(false);
+
+// choice is one of auto, always, or never, auto colors a stream when it is a terminal and NO_COLOR is not set
+pub fn init(choice: &str) {
+	let no_color = env::var_os("NO_COLOR").is_some_and(|value| !value.is_empty());
+
+	let (stderr, stdout) = match choice {
+		"always" => (true, true),
+		"never" => (false, false),
+		_ => (!no_color && io::stderr().is_terminal(), !no_color && io::stdout().is_terminal()),
+	};
+
+	STDERR_ENABLED.store(stderr, Ordering::Relaxed);
+	STDOUT_ENABLED.store(stdout, Ordering::Relaxed);
+}
+
+fn paint(enabled: &AtomicBool, code: &str, text: &str) -> String {
+	if enabled.load(Ordering::Relaxed) {
+		format!("\x1b[{}m{}\x1b[0m", code, text)
+	} else {
+		text.to_owned()
+	}
+}
+
+// for text printed to stderr
+pub fn error(text: &str) -> String {
+	paint(&STDERR_ENABLED, RED, text)
+}
+
+// for text printed to stderr
+pub fn warning(text: &str) -> String {
+	paint(&STDERR_ENABLED, YELLOW, text)
+}
+
+// for text printed to stdout
+pub fn ok(text: &str) -> String {
+	paint(&STDOUT_ENABLED, GREEN, text)
+}
@@ -0,0 +1,334 @@
+// --compress and --auto-compress, which compress the data of entries stored with FLAG_COMPRESSED, and the
+// decompression of compressed entries read from images
+//
+// gzip, zstd, and xz are run as commands rather than linked in, so they have to be installed to build or read
+// compressed images, and the output is only reproducible with the same versions of them
+
+use std::fmt;
+use std::io::Write;
+use std::ops::RangeInclusive;
+use std::process::{exit, Command, Stdio};
+use std::thread;
+use std::time::Instant;
+
+use gen_initrd::checksum::crc32;
+use gen_initrd::parse::ParsedEntry;
+use gen_initrd::{to_initrd, Compression, Entry, InitrdOptions, FLAG_COMPRESSED};
+
+use crate::exit_code;
+
+// entries with less data than this are stored uncompressed by --auto-compress, since the few bytes it could save
+// are not worth decompressing them
+pub const AUTO_MIN_LEN: usize = 512;
+
+// command compressing its stdin to its stdout with algorithm, which decompresses instead when given -d
+fn command(algorithm: Compression) -> (&'static str, &'static [&'static str]) {
+	match algorithm {
+		// -n leaves the name and modified time of the input out of the header, so the output is reproducible
+		Compression::Gzip => ("gzip", &["-c", "-n"]),
+		Compression::Zstd => ("zstd", &["-c", "-q"]),
+		Compression::Xz => ("xz", &["-c"]),
+	}
+}
+
+// levels --compress-level accepts for algorithm, which are those its command accepts, except gzip which does not
+// accept 0, so level 0 is written without running it
+pub fn levels(algorithm: Compression) -> RangeInclusive<u32> {
+	match algorithm {
+		Compression::Gzip => 0..=9,
+		Compression::Zstd => 1..=22,
+		Compression::Xz => 0..=9,
+	}
+}
+
+// checks level is in the range of levels of algorithm
+pub fn check_level(algorithm: Compression, level: u32) -> Result<(), String> {
+	let levels = levels(algorithm);
+	if levels.contains(&level) {
+		Ok(())
+	} else {
+		Err(format!("{} is not a level of {}, which has levels {} to {}", level, algorithm.name(), levels.start(), levels.end()))
+	}
+}
+
+// arguments selecting level for the command of algorithm, which must be in its range of levels
+fn level_args(algorithm: Compression, level: u32) -> Vec<String> {
+	let mut args = vec![format!("-{}", level)];
+	// zstd only uses levels above 19 when told to, since they need a lot more memory to decompress
+	if algorithm == Compression::Zstd && level > 19 {
+		args.push("--ultra".to_owned());
+	}
+	args
+}
+
+// data as gzip level 0, which is deflate stored blocks with no compression, since the gzip command has no level 0
+fn gzip_stored(data: &[u8]) -> Vec<u8> {
+	// magic, deflate, no flags, no modified time, no extra flags, unknown os
+	let mut out = vec![0x1f, 0x8b, 8, 0, 0, 0, 0, 0, 0, 255];
+
+	let mut blocks = data.chunks(u16::MAX as usize).peekable();
+	if blocks.peek().is_none() {
+		// a final empty block
+		out.extend_from_slice(&[1, 0, 0, 0xff, 0xff]);
+	}
+	while let Some(block) = blocks.next() {
+		out.push(if blocks.peek().is_none() { 1 } else { 0 });
+		out.extend_from_slice(&(block.len() as u16).to_le_bytes());
+		out.extend_from_slice(&(!(block.len() as u16)).to_le_bytes());
+		out.extend_from_slice(block);
+	}
+
+	out.extend_from_slice(&crc32(data).to_le_bytes());
+	out.extend_from_slice(&(data.len() as u32).to_le_bytes());
+	out
+}
+
+enum PipeError {
+	// the program could not be started, such as when it is not installed
+	Run(String),
+	// the program failed or gave unexpected output
+	Failed(String),
+}
+
+impl PipeError {
+	// the code to exit with, which is failed unless the program could not be run
+	fn exit_code(&self, failed: i32) -> i32 {
+		match self {
+			Self::Run(_) => exit_code::MISSING_PROGRAM,
+			Self::Failed(_) => failed,
+		}
+	}
+}
+
+impl fmt::Display for PipeError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			Self::Run(err) | Self::Failed(err) => write!(f, "{}", err),
+		}
+	}
+}
+
+// returns what program writes to stdout when given data on stdin
+fn pipe(program: &str, args: &[&str], data: &[u8]) -> Result<Vec<u8>, PipeError> {
+	let mut child = Command::new(program)
+		.args(args)
+		.stdin(Stdio::piped())
+		.stdout(Stdio::piped())
+		.stderr(Stdio::piped())
+		.spawn()
+		.map_err(|err| PipeError::Run(format!("could not run {}: {}", program, err)))?;
+
+	// stdin is written from another thread so the program can not deadlock writing output before reading everything
+	let mut stdin = child.stdin.take().unwrap();
+	let data = data.to_vec();
+	let writer = thread::spawn(move || stdin.write_all(&data));
+
+	let output = child.wait_with_output().map_err(|err| PipeError::Failed(format!("could not run {}: {}", program, err)))?;
+	let _ = writer.join();
+
+	if !output.status.success() {
+		let stderr = String::from_utf8_lossy(&output.stderr);
+		return Err(PipeError::Failed(format!("{} failed: {}", program, stderr.trim())));
+	}
+
+	Ok(output.stdout)
+}
+
+// name of an entry in messages, which is its type if it has no name
+fn display_name(entry: &Entry) -> String {
+	if entry.name.is_empty() { entry.typ.name().to_owned() } else { entry.name.to_owned() }
+}
+
+// the data of entry compressed with algorithm at level, or the default level of its command if it is None
+pub fn compress(algorithm: Compression, level: Option<u32>, entry: &Entry) -> Vec<u8> {
+	if algorithm == Compression::Gzip && level == Some(0) {
+		return gzip_stored(&entry.data);
+	}
+
+	let (program, args) = command(algorithm);
+	let mut args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+	args.extend(level.map(|level| level_args(algorithm, level)).into_iter().flatten());
+	let args: Vec<&str> = args.iter().map(String::as_str).collect();
+
+	match pipe(program, &args, &entry.data) {
+		Ok(compressed) => compressed,
+		Err(err) => {
+			error!("Could not compress entry {} with {}: {}", display_name(entry), algorithm.name(), err);
+			exit(err.exit_code(1));
+		},
+	}
+}
+
+// the data of an entry read from an image, decompressed if it is compressed
+pub fn entry_data(entry: &ParsedEntry) -> Vec<u8> {
+	let algorithm = match entry.compression {
+		Some(algorithm) => algorithm,
+		None => return entry.to_vec(),
+	};
+
+	let (program, _) = command(algorithm);
+	let data = pipe(program, &["-d", "-c"], entry.data).and_then(|data| if data.len() as u64 == entry.len {
+		Ok(data)
+	} else {
+		Err(PipeError::Failed(format!("it is {} bytes once decompressed instead of {}", data.len(), entry.len)))
+	});
+
+	match data {
+		Ok(data) => data,
+		Err(err) => {
+			error!("Could not decompress entry {} with {}: {}", entry.name, algorithm.name(), err);
+			exit(err.exit_code(exit_code::MALFORMED_IMAGE));
+		},
+	}
+}
+
+// --compress, which compresses every entry with algorithm even if it makes the entry larger, at the level given
+// by --compress-level, which must have been checked by check_level
+pub fn all(entries: &mut [Entry], algorithm: Compression, level: Option<u32>) {
+	for entry in entries.iter_mut() {
+		entry.compressed = Some((algorithm, compress(algorithm, level, entry)));
+	}
+}
+
+// --auto-compress, which compresses every entry with whichever algorithm makes it smallest, or leaves it
+// uncompressed if it is small or none of them make it smaller, printing each decision if verbose is set
+pub fn auto(entries: &mut [Entry], verbose: bool) {
+	for entry in entries.iter_mut() {
+		if entry.data.len() < AUTO_MIN_LEN {
+			if verbose {
+				eprintln!("{}: stored uncompressed, {} bytes is too small to compress", display_name(entry), entry.data.len());
+			}
+			continue;
+		}
+
+		entry.compressed = Compression::ALL.iter()
+			.map(|algorithm| (*algorithm, compress(*algorithm, None, entry)))
+			.filter(|(_, compressed)| compressed.len() < entry.data.len())
+			.min_by_key(|(_, compressed)| compressed.len());
+
+		if verbose {
+			match &entry.compressed {
+				Some((algorithm, compressed)) => eprintln!("{}: compressed with {} from {} to {} bytes", display_name(entry), algorithm.name(), entry.data.len(), compressed.len()),
+				None => eprintln!("{}: stored uncompressed, no algorithm makes its {} bytes smaller", display_name(entry), entry.data.len()),
+			}
+		}
+	}
+}
+
+// parses the ALGORITHM[:LEVEL],... value of size-estimate --try, where a missing level is the default level of the
+// command
+pub fn parse_candidates(arg: &str) -> Vec<(Compression, Option<u32>)> {
+	arg.split(',')
+		.map(|candidate| {
+			let (name, level) = match candidate.split_once(':') {
+				Some((name, level)) => (name, Some(level)),
+				None => (candidate, None),
+			};
+
+			let parsed = Compression::from_name(name)
+				.ok_or_else(|| format!("unknown algorithm {}, expected gzip, zstd, or xz", name))
+				.and_then(|algorithm| match level {
+					Some(level) => level.parse::<u32>()
+						.map_err(|_| format!("{} is not a number", level))
+						.and_then(|level| check_level(algorithm, level).map(|_| (algorithm, Some(level)))),
+					None => Ok((algorithm, None)),
+				});
+
+			match parsed {
+				Ok(candidate) => candidate,
+				Err(err) => {
+					error!("Invalid value for --try {}: {}", candidate, err);
+					exit(1);
+				},
+			}
+		})
+		.collect()
+}
+
+// size-estimate, which builds the image from entries with options once uncompressed and once with each candidate,
+// and prints a table of their sizes and how long compressing took, leaving entries compressed with the last candidate
+pub fn estimate(entries: &mut [Entry], options: &InitrdOptions, candidates: &[(Compression, Option<u32>)]) {
+	let size = |entries: &[Entry], flags: u64| {
+		let options = InitrdOptions { flags, ..*options };
+		match to_initrd(entries, &options) {
+			Ok(initrd) => initrd.len(),
+			Err(err) => {
+				error!("Could not generate initrd: {}", err);
+				exit(1);
+			},
+		}
+	};
+
+	println!("{:<10} {:<8} {:>12} {:>9}", "algorithm", "level", "size", "time");
+
+	for entry in entries.iter_mut() {
+		entry.compressed = None;
+	}
+	println!("{:<10} {:<8} {:>12} {:>9}", "none", "-", size(entries, options.flags & !FLAG_COMPRESSED), "-");
+
+	for (algorithm, level) in candidates.iter() {
+		let start = Instant::now();
+		all(entries, *algorithm, *level);
+		let time = start.elapsed();
+
+		let level = level.map_or("default".to_owned(), |level| level.to_string());
+		let size = size(entries, options.flags | FLAG_COMPRESSED);
+		println!("{:<10} {:<8} {:>12} {:>8.3}s", algorithm.name(), level, size, time.as_secs_f64());
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn level_ranges() {
+		assert!(check_level(Compression::Gzip, 0).is_ok());
+		assert!(check_level(Compression::Gzip, 10).is_err());
+		assert!(check_level(Compression::Zstd, 0).is_err());
+		assert!(check_level(Compression::Zstd, 22).is_ok());
+		assert!(check_level(Compression::Zstd, 23).is_err());
+		assert!(check_level(Compression::Xz, 9).is_ok());
+		assert_eq!(check_level(Compression::Xz, 10).unwrap_err(), "10 is not a level of xz, which has levels 0 to 9");
+	}
+
+	#[test]
+	fn candidates() {
+		assert_eq!(parse_candidates("gzip,zstd:19,xz:0"), vec![(Compression::Gzip, None), (Compression::Zstd, Some(19)), (Compression::Xz, Some(0))]);
+	}
+
+	// decompresses gzip made only of deflate stored blocks, checking every field gzip -d would
+	fn inflate_stored(gzip: &[u8]) -> Vec<u8> {
+		assert_eq!(gzip[..4], [0x1f, 0x8b, 8, 0]);
+		let mut rest = &gzip[10..];
+		let mut data = Vec::new();
+
+		loop {
+			// every block header is a whole byte, with the final bit and a block type of 0 for stored
+			let header = rest[0];
+			assert_eq!(header & !1, 0);
+			let len = u16::from_le_bytes([rest[1], rest[2]]);
+			let nlen = u16::from_le_bytes([rest[3], rest[4]]);
+			assert_eq!(nlen, !len);
+			data.extend_from_slice(&rest[5..5 + len as usize]);
+			rest = &rest[5 + len as usize..];
+
+			if header == 1 {
+				break;
+			}
+		}
+
+		assert_eq!(rest.len(), 8);
+		assert_eq!(rest[..4], crc32(&data).to_le_bytes());
+		assert_eq!(rest[4..], (data.len() as u32).to_le_bytes());
+		data
+	}
+
+	#[test]
+	fn gzip_stored_blocks() {
+		for len in [0, 1, u16::MAX as usize, u16::MAX as usize + 1, 200_000].iter() {
+			let data: Vec<u8> = (0..*len).map(|i| (i * 7) as u8).collect();
+			assert_eq!(inflate_stored(&gzip_stored(&data)), data);
+		}
+	}
+}
@@ -0,0 +1,403 @@
+// minimal 64 bit little endian elf reader and writer, used to embed the initrd in a section of the kernel,
+// to check executables in the initrd can be mapped directly from it, and to link entries to their debug files
+//
+// a new or resized section is appended to the end of the file along with a new copy of the
+// section header table and section name table, so no existing data has to be moved, a section and
+// table which are already at the end of the file from an earlier embed are replaced instead of copied
+
+use std::convert::{TryFrom, TryInto};
+
+use gen_initrd::align_up;
+use gen_initrd::checksum::crc32;
+
+const ELF_MAGIC: &[u8] = b"\x7fELF";
+const ELFCLASS64: u8 = 2;
+const ELFDATA2LSB: u8 = 1;
+
+const EHDR_SIZE: usize = 64;
+const SHDR_SIZE: usize = 64;
+
+// section indexes at or above this are reserved
+const SHN_LORESERVE: usize = 0xff00;
+
+const ET_EXEC: u16 = 2;
+const ET_DYN: u16 = 3;
+
+const PT_LOAD: u32 = 1;
+const PHDR_SIZE: usize = 56;
+
+const SHT_PROGBITS: u32 = 1;
+const SHT_SYMTAB: u32 = 2;
+const SHF_ALLOC: u64 = 1 << 1;
+
+fn read_u16(bytes: &[u8], offset: usize) -> u16 {
+	u16::from_le_bytes(bytes[offset..offset + 2].try_into().unwrap())
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+	u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
+
+fn read_u64(bytes: &[u8], offset: usize) -> u64 {
+	u64::from_le_bytes(bytes[offset..offset + 8].try_into().unwrap())
+}
+
+fn write_u16(bytes: &mut [u8], offset: usize, n: u16) {
+	bytes[offset..offset + 2].copy_from_slice(&n.to_le_bytes());
+}
+
+fn write_u32(bytes: &mut [u8], offset: usize, n: u32) {
+	bytes[offset..offset + 4].copy_from_slice(&n.to_le_bytes());
+}
+
+fn write_u64(bytes: &mut [u8], offset: usize, n: u64) {
+	bytes[offset..offset + 8].copy_from_slice(&n.to_le_bytes());
+}
+
+// returns the part of bytes at offset with length len, if it is in bounds
+fn region(bytes: &[u8], offset: u64, len: u64) -> Option<&[u8]> {
+	let start = usize::try_from(offset).ok()?;
+	let end = start.checked_add(usize::try_from(len).ok()?)?;
+	bytes.get(start..end)
+}
+
+// mutable version of region
+fn region_mut(bytes: &mut [u8], offset: u64, len: u64) -> Option<&mut [u8]> {
+	let start = usize::try_from(offset).ok()?;
+	let end = start.checked_add(usize::try_from(len).ok()?)?;
+	bytes.get_mut(start..end)
+}
+
+struct Section {
+	name: u32,
+	flags: u64,
+	offset: u64,
+	size: u64,
+}
+
+impl Section {
+	fn read(shdr: &[u8]) -> Self {
+		Section {
+			name: read_u32(shdr, 0),
+			flags: read_u64(shdr, 8),
+			offset: read_u64(shdr, 24),
+			size: read_u64(shdr, 32),
+		}
+	}
+}
+
+fn pad_to(vec: &mut Vec<u8>, align: u64) {
+	let len = align_up(vec.len() as u64, align) as usize;
+	vec.resize(len, 0);
+}
+
+// returns true if bytes start with the elf magic number
+pub fn is_elf(bytes: &[u8]) -> bool {
+	bytes.starts_with(ELF_MAGIC)
+}
+
+// returns the largest alignment required by any load segment, or None if elf can not be read
+pub fn max_load_align(elf: &[u8]) -> Option<u64> {
+	if elf.len() < EHDR_SIZE || !is_elf(elf) || elf[4] != ELFCLASS64 || elf[5] != ELFDATA2LSB {
+		return None;
+	}
+
+	let phoff = read_u64(elf, 0x20);
+	let phentsize = read_u16(elf, 0x36) as usize;
+	let phnum = read_u16(elf, 0x38) as usize;
+
+	if phentsize != PHDR_SIZE {
+		return None;
+	}
+
+	let table = region(elf, phoff, (phnum * PHDR_SIZE) as u64)?;
+
+	Some(table.chunks(PHDR_SIZE)
+		.filter(|phdr| read_u32(phdr, 0) == PT_LOAD)
+		.map(|phdr| read_u64(phdr, 48))
+		.fold(1, u64::max))
+}
+
+// returns why elf is not a 64 bit little endian elf file, or None if it is
+pub fn format_problem(elf: &[u8]) -> Option<&'static str> {
+	if elf.len() < EHDR_SIZE || !is_elf(elf) {
+		return Some("it is not an elf file");
+	}
+
+	if elf[4] != ELFCLASS64 || elf[5] != ELFDATA2LSB {
+		return Some("it is not a 64 bit little endian elf file");
+	}
+
+	None
+}
+
+// returns why elf can not be started by jumping to its entry point, or None if it can
+pub fn entry_point_problem(elf: &[u8]) -> Option<&'static str> {
+	if let Some(problem) = format_problem(elf) {
+		return Some(problem);
+	}
+
+	match read_u16(elf, 0x10) {
+		ET_EXEC => (),
+		ET_DYN => return Some("it is a shared object or position independent executable, not a static executable"),
+		_ => return Some("it is not an executable"),
+	}
+
+	let entry = read_u64(elf, 0x18);
+	if entry == 0 {
+		return Some("it has no entry point");
+	}
+
+	let phoff = read_u64(elf, 0x20);
+	let phentsize = read_u16(elf, 0x36) as usize;
+	let phnum = read_u16(elf, 0x38) as usize;
+	let table = match region(elf, phoff, (phnum * PHDR_SIZE) as u64) {
+		Some(table) if phentsize == PHDR_SIZE => table,
+		_ => return Some("its program headers can not be read"),
+	};
+
+	let loaded = table.chunks(PHDR_SIZE)
+		.filter(|phdr| read_u32(phdr, 0) == PT_LOAD)
+		.any(|phdr| {
+			let vaddr = read_u64(phdr, 16);
+			let memsz = read_u64(phdr, 40);
+			entry >= vaddr && entry - vaddr < memsz
+		});
+
+	if !loaded {
+		return Some("its entry point is not in any load segment");
+	}
+
+	None
+}
+
+fn machine_name(machine: u16) -> &'static str {
+	match machine {
+		0x03 => "x86",
+		0x28 => "arm",
+		0x3e => "x86_64",
+		0xb7 => "aarch64",
+		0xf3 => "riscv",
+		_ => "unknown machine",
+	}
+}
+
+// returns a short description of the architecture of elf and whether it has a symbol table,
+// or None if elf can not be read
+pub fn describe(elf: &[u8]) -> Option<String> {
+	if elf.len() < EHDR_SIZE || !is_elf(elf) {
+		return None;
+	}
+
+	if elf[4] != ELFCLASS64 || elf[5] != ELFDATA2LSB {
+		return Some("elf, not 64 bit little endian".to_owned());
+	}
+
+	let machine = read_u16(elf, 0x12);
+	let shoff = read_u64(elf, 0x28);
+	let shentsize = read_u16(elf, 0x3a) as usize;
+	let shnum = read_u16(elf, 0x3c) as usize;
+
+	let stripped = if shentsize == SHDR_SIZE {
+		let table = region(elf, shoff, (shnum * SHDR_SIZE) as u64)?;
+		!table.chunks(SHDR_SIZE).any(|shdr| read_u32(shdr, 4) == SHT_SYMTAB)
+	} else {
+		true
+	};
+
+	Some(format!("elf {}, {}", machine_name(machine), if stripped { "stripped" } else { "not stripped" }))
+}
+
+// returns the contents of a .gnu_debuglink section referring to the debug file debug_name containing debug_file,
+// which is the nul terminated name padded to 4 bytes followed by the crc32 of the debug file, gdb uses the same
+// crc32 as zlib
+pub fn debug_link(debug_name: &str, debug_file: &[u8]) -> Vec<u8> {
+	let mut link = debug_name.as_bytes().to_vec();
+	link.push(0);
+	link.resize(align_up(link.len() as u64, 4) as usize, 0);
+	link.extend_from_slice(&crc32(debug_file).to_le_bytes());
+	link
+}
+
+// returns a copy of elf with data stored in the section called section_name,
+// the section is created if it does not exist
+pub fn embed(elf: &[u8], section_name: &str, data: &[u8]) -> Result<Vec<u8>, &'static str> {
+	if elf.len() < EHDR_SIZE || &elf[..4] != ELF_MAGIC {
+		return Err("not an elf file");
+	}
+
+	if elf[4] != ELFCLASS64 || elf[5] != ELFDATA2LSB {
+		return Err("only 64 bit little endian elf files are supported");
+	}
+
+	let shoff = read_u64(elf, 0x28);
+	let shentsize = read_u16(elf, 0x3a) as usize;
+	let shnum = read_u16(elf, 0x3c) as usize;
+	let shstrndx = read_u16(elf, 0x3e) as usize;
+
+	if shoff == 0 || shnum == 0 {
+		return Err("elf file has no section header table");
+	}
+
+	if shentsize != SHDR_SIZE {
+		return Err("elf file has an unexpected section header size");
+	}
+
+	let table = region(elf, shoff, (shnum * SHDR_SIZE) as u64)
+		.ok_or("section header table is out of bounds")?;
+
+	if shstrndx == 0 || shstrndx >= shnum {
+		return Err("elf file has no section name table");
+	}
+
+	let strtab_section = Section::read(&table[shstrndx * SHDR_SIZE..]);
+	let strtab = region(elf, strtab_section.offset, strtab_section.size)
+		.ok_or("section name table is out of bounds")?;
+
+	let existing = (0..shnum).find(|i| {
+		let name = Section::read(&table[i * SHDR_SIZE..]).name as usize;
+		strtab.get(name..).and_then(|name| name.split(|c| *c == 0).next()) == Some(section_name.as_bytes())
+	});
+
+	let mut out = elf.to_vec();
+	let mut table = table.to_vec();
+
+	// the table is always written again at the end, so a copy already there is dropped rather than kept
+	if shoff + table.len() as u64 == elf.len() as u64 {
+		out.truncate(shoff as usize);
+	}
+
+	let index = match existing {
+		Some(index) => index,
+		None => {
+			if shnum + 1 >= SHN_LORESERVE {
+				return Err("elf file has no free section header slot");
+			}
+
+			// the new section name is added to a copy of the section name table at the end of the file
+			let mut new_strtab = strtab.to_vec();
+			let name = new_strtab.len() as u32;
+			new_strtab.extend_from_slice(section_name.as_bytes());
+			new_strtab.push(0);
+
+			let strtab_offset = out.len() as u64;
+			out.extend_from_slice(&new_strtab);
+
+			let strtab_shdr = &mut table[shstrndx * SHDR_SIZE..];
+			write_u64(strtab_shdr, 24, strtab_offset);
+			write_u64(strtab_shdr, 32, new_strtab.len() as u64);
+
+			let mut shdr = [0; SHDR_SIZE];
+			write_u32(&mut shdr, 0, name);
+			write_u32(&mut shdr, 4, SHT_PROGBITS);
+			write_u64(&mut shdr, 48, 8);
+			table.extend_from_slice(&shdr);
+
+			shnum
+		},
+	};
+
+	let section = Section::read(&table[index * SHDR_SIZE..]);
+
+	if section.flags & SHF_ALLOC != 0 {
+		// sections that are loaded into memory are part of a segment, so they can only be overwritten in place
+		if data.len() as u64 > section.size {
+			return Err("section is loaded into memory and is too small to hold the initrd");
+		}
+
+		let old = region_mut(&mut out, section.offset, section.size).ok_or("section is out of bounds")?;
+		old[..data.len()].copy_from_slice(data);
+		old[data.len()..].fill(0);
+		// the size is kept, so a larger initrd can be stored in the section later
+	} else {
+		// the section written by an earlier embed is replaced when nothing follows it but padding
+		let at_end = existing.is_some() && section.offset.checked_add(section.size)
+			.is_some_and(|end| section.offset.is_multiple_of(8) && align_up(end, 8) == out.len() as u64);
+		if at_end {
+			out.truncate(section.offset as usize);
+		}
+
+		pad_to(&mut out, 8);
+		let data_offset = out.len() as u64;
+		out.extend_from_slice(data);
+
+		write_u64(&mut table[index * SHDR_SIZE..], 24, data_offset);
+		write_u64(&mut table[index * SHDR_SIZE..], 32, data.len() as u64);
+	}
+
+	pad_to(&mut out, 8);
+	let table_offset = out.len() as u64;
+	out.extend_from_slice(&table);
+
+	write_u64(&mut out, 0x28, table_offset);
+	write_u16(&mut out, 0x3c, (table.len() / SHDR_SIZE) as u16);
+
+	Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	// an x86_64 elf header followed by a null section and a section name table
+	fn minimal_elf() -> Vec<u8> {
+		let strtab = b"\0.shstrtab\0";
+		let mut elf = vec![0; EHDR_SIZE];
+		elf[..4].copy_from_slice(ELF_MAGIC);
+		elf[4] = ELFCLASS64;
+		elf[5] = ELFDATA2LSB;
+		elf[6] = 1;
+		write_u16(&mut elf, 0x10, ET_EXEC);
+		write_u16(&mut elf, 0x12, 0x3e);
+		write_u16(&mut elf, 0x34, EHDR_SIZE as u16);
+		write_u16(&mut elf, 0x3a, SHDR_SIZE as u16);
+		write_u16(&mut elf, 0x3c, 2);
+		write_u16(&mut elf, 0x3e, 1);
+
+		let strtab_offset = elf.len() as u64;
+		elf.extend_from_slice(strtab);
+		pad_to(&mut elf, 8);
+		let shoff = elf.len() as u64;
+		write_u64(&mut elf, 0x28, shoff);
+
+		let mut shdrs = [0; 2 * SHDR_SIZE];
+		let strtab_shdr = &mut shdrs[SHDR_SIZE..];
+		write_u32(strtab_shdr, 0, 1);
+		write_u32(strtab_shdr, 4, 3);
+		write_u64(strtab_shdr, 24, strtab_offset);
+		write_u64(strtab_shdr, 32, strtab.len() as u64);
+		elf.extend_from_slice(&shdrs);
+		elf
+	}
+
+	// returns the data of the section called name
+	fn section_data<'a>(elf: &'a [u8], name: &str) -> Option<&'a [u8]> {
+		let shoff = read_u64(elf, 0x28);
+		let shnum = read_u16(elf, 0x3c) as usize;
+		let shstrndx = read_u16(elf, 0x3e) as usize;
+		let table = region(elf, shoff, (shnum * SHDR_SIZE) as u64)?;
+		let strtab = Section::read(&table[shstrndx * SHDR_SIZE..]);
+		let strtab = region(elf, strtab.offset, strtab.size)?;
+
+		table.chunks(SHDR_SIZE).map(Section::read).find(|section| {
+			strtab.get(section.name as usize..).and_then(|s| s.split(|c| *c == 0).next()) == Some(name.as_bytes())
+		}).and_then(|section| region(elf, section.offset, section.size))
+	}
+
+	#[test]
+	fn embed_section() {
+		let elf = minimal_elf();
+		assert_eq!(describe(&elf).unwrap(), "elf x86_64, stripped");
+
+		let embedded = embed(&elf, ".initrd", b"initrd bytes").unwrap();
+		assert_eq!(describe(&embedded).unwrap(), "elf x86_64, stripped");
+		assert_eq!(section_data(&embedded, ".initrd").unwrap(), b"initrd bytes");
+		assert_eq!(section_data(&embedded, ".shstrtab").unwrap(), b"\0.shstrtab\0.initrd\0");
+
+		// embedding again replaces the section instead of adding another one
+		let again = embed(&embedded, ".initrd", b"a longer initrd").unwrap();
+		assert_eq!(read_u16(&again, 0x3c), 3);
+		assert_eq!(section_data(&again, ".initrd").unwrap(), b"a longer initrd");
+		assert!(again.len() <= embedded.len() + 8);
+	}
+}
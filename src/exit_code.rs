@@ -0,0 +1,19 @@
+// exit codes for each class of failure, which are stable so scripts wrapping gen-initrd can tell them apart
+//
+//	0    success, including a build skipped by -n
+//	1    invalid arguments, and any failure not covered by another code
+//	2    an input file or file descriptor could not be read
+//	3    an output could not be written
+//	4    a check failed, such as validation of entries, --fail-on-warning, verify-hashes, or match
+//	5    an image read by a command or given as an input is malformed
+//	6    a program used to compress or decompress entries could not be run
+//	130  the build was interrupted by ctrl-c
+
+pub const MISSING_INPUT: i32 = 2;
+pub const WRITE_FAILED: i32 = 3;
+pub const VALIDATION_FAILED: i32 = 4;
+pub const MALFORMED_IMAGE: i32 = 5;
+pub const MISSING_PROGRAM: i32 = 6;
+// the same code a shell reports for a process killed by SIGINT
+#[cfg(unix)]
+pub const INTERRUPTED: i32 = 130;
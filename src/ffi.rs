@@ -0,0 +1,143 @@
+// c api for reading initrd images, enabled by the cffi feature
+//
+// every function takes a pointer to an image and its length in bytes, which must be valid to read
+// for the duration of the call, returned pointers point into the image and nothing is allocated,
+// the header for this api is include/gen_initrd.h and is generated by cbindgen
+
+#![allow(clippy::missing_safety_doc)]
+
+use std::ptr;
+use std::slice;
+
+use crate::parse::{self, ParseError};
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GenInitrdStatus {
+	Ok = 0,
+	// the image or an out pointer is null
+	Null = -1,
+	Truncated = -2,
+	BadMagic = -3,
+	UnknownFlags = -4,
+	BadTrailer = -5,
+	NameOutOfBounds = -6,
+	DataOutOfBounds = -7,
+	NameNotUtf8 = -8,
+	BadTrimLength = -9,
+	// the entry index is not less than the number of entries
+	BadIndex = -10,
+	BadCompression = -11,
+}
+
+// an entry of an image, name is not nul terminated
+#[repr(C)]
+pub struct GenInitrdEntry {
+	pub typ: u64,
+	pub name: *const u8,
+	pub name_len: usize,
+	pub data: *const u8,
+	pub data_len: usize,
+	// length of the data once zero extended, larger than data_len if trailing zeros were trimmed,
+	// or once decompressed if the data is compressed
+	pub len: u64,
+	// algorithm the data is compressed with, 1 for gzip, 2 for zstd, and 3 for xz, or 0 if it is not compressed
+	pub compression: u64,
+}
+
+fn error_status(err: ParseError) -> GenInitrdStatus {
+	match err {
+		ParseError::Truncated => GenInitrdStatus::Truncated,
+		ParseError::BadMagic(_) => GenInitrdStatus::BadMagic,
+		// the c api can only read images with an entry table
+		ParseError::StreamFormat => GenInitrdStatus::BadMagic,
+		ParseError::UnknownFlags(_) => GenInitrdStatus::UnknownFlags,
+		ParseError::BadTrailer => GenInitrdStatus::BadTrailer,
+		ParseError::NameOutOfBounds(_) => GenInitrdStatus::NameOutOfBounds,
+		ParseError::DataOutOfBounds(_) => GenInitrdStatus::DataOutOfBounds,
+		ParseError::NameNotUtf8(_) => GenInitrdStatus::NameNotUtf8,
+		ParseError::BadTrimLength(_) => GenInitrdStatus::BadTrimLength,
+		ParseError::BadCompression(_) => GenInitrdStatus::BadCompression,
+	}
+}
+
+unsafe fn image_bytes<'a>(image: *const u8, len: usize) -> Option<&'a [u8]> {
+	if image.is_null() {
+		None
+	} else {
+		Some(slice::from_raw_parts(image, len))
+	}
+}
+
+// checks the header and every entry of the image, returning GenInitrdStatus::Ok if it is well formed
+#[no_mangle]
+pub unsafe extern "C" fn gen_initrd_validate(image: *const u8, len: usize) -> GenInitrdStatus {
+	let bytes = match image_bytes(image, len) {
+		Some(bytes) => bytes,
+		None => return GenInitrdStatus::Null,
+	};
+
+	let layout = match parse::parse_layout(bytes) {
+		Ok(layout) => layout,
+		Err(err) => return error_status(err),
+	};
+
+	for i in 0..layout.len as usize {
+		if let Err(err) = parse::parse_entry(bytes, &layout, i) {
+			return error_status(err);
+		}
+	}
+
+	GenInitrdStatus::Ok
+}
+
+// writes the number of entries in the image to count
+#[no_mangle]
+pub unsafe extern "C" fn gen_initrd_entry_count(image: *const u8, len: usize, count: *mut u64) -> GenInitrdStatus {
+	let bytes = match image_bytes(image, len) {
+		Some(bytes) if !count.is_null() => bytes,
+		_ => return GenInitrdStatus::Null,
+	};
+
+	match parse::parse_layout(bytes) {
+		Ok(layout) => {
+			ptr::write(count, layout.len);
+			GenInitrdStatus::Ok
+		},
+		Err(err) => error_status(err),
+	}
+}
+
+// writes entry index of the image to entry
+#[no_mangle]
+pub unsafe extern "C" fn gen_initrd_get_entry(image: *const u8, len: usize, index: u64, entry: *mut GenInitrdEntry) -> GenInitrdStatus {
+	let bytes = match image_bytes(image, len) {
+		Some(bytes) if !entry.is_null() => bytes,
+		_ => return GenInitrdStatus::Null,
+	};
+
+	let layout = match parse::parse_layout(bytes) {
+		Ok(layout) => layout,
+		Err(err) => return error_status(err),
+	};
+
+	if index >= layout.len {
+		return GenInitrdStatus::BadIndex;
+	}
+
+	match parse::parse_entry(bytes, &layout, index as usize) {
+		Ok(parsed) => {
+			ptr::write(entry, GenInitrdEntry {
+				typ: parsed.typ,
+				name: parsed.name.as_ptr(),
+				name_len: parsed.name.len(),
+				data: parsed.data.as_ptr(),
+				data_len: parsed.data.len(),
+				len: parsed.len,
+				compression: parsed.compression.map_or(0, |algorithm| algorithm as u64),
+			});
+			GenInitrdStatus::Ok
+		},
+		Err(err) => error_status(err),
+	}
+}
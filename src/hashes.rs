@@ -0,0 +1,101 @@
+// lists of the sha256 of every entry's data, written by --emit-hashes and checked against an image by verify-hashes
+//
+//	build/early-init 9f86d081884c7d659a2feaa0c55ad015a3bf4f1b2b0b822cd15d6c15b0f00a08
+//	drivers/ext2 60303ae22b998861bce3b28f33eec1be758a213c86c93c076dbe9f558c11c752
+//
+// the name is everything before the last space, and entries stored without a name are listed by their type
+
+use std::process::exit;
+
+use gen_initrd::parse::ParsedEntry;
+use gen_initrd::{type_name, Entry};
+
+use crate::compress;
+use crate::exit_code;
+use crate::interrupt;
+use crate::lock::sha256_hex;
+
+// name and sha256 of an entry
+pub type EntryHash = (String, String);
+
+// hashes the data of an entry read from an image, including trimmed trailing zeros and decompressed
+pub fn from_parsed(entry: &ParsedEntry) -> EntryHash {
+	let name = if entry.name.is_empty() { type_name(entry.typ) } else { entry.name.to_owned() };
+	(name, sha256_hex(&compress::entry_data(entry)))
+}
+
+// hashes the data of an entry being built, giving the same result as from_parsed once it is read back
+pub fn from_entry(entry: &Entry) -> EntryHash {
+	let name = if entry.name.is_empty() { entry.typ.name().to_owned() } else { entry.name.to_owned() };
+	(name, sha256_hex(&entry.data))
+}
+
+// writes hashes sorted by name, so lists from different builds can be diffed
+pub fn write(path: &str, mut hashes: Vec<EntryHash>) {
+	hashes.sort();
+
+	let text: String = hashes.iter().map(|(name, hash)| format!("{} {}\n", name, hash)).collect();
+	if let Err(err) = interrupt::write(path, text) {
+		error!("Could not write entry hashes to {}: {}", path, err);
+		exit(exit_code::WRITE_FAILED);
+	}
+}
+
+fn valid_hash(hash: &str) -> bool {
+	hash.len() == 64 && hash.bytes().all(|byte| byte.is_ascii_digit() || (b'a'..=b'f').contains(&byte))
+}
+
+// parses the name hash lines of the file at path, blank lines and lines starting with # are ignored
+pub fn parse(path: &str, text: &str) -> Vec<EntryHash> {
+	let mut hashes = Vec::new();
+
+	for (i, line) in text.lines().enumerate() {
+		if line.trim().is_empty() || line.starts_with('#') {
+			continue;
+		}
+
+		match line.rsplit_once(' ') {
+			Some((name, hash)) if !name.is_empty() && valid_hash(hash) => hashes.push((name.to_owned(), hash.to_owned())),
+			_ => {
+				error!("{}:{}: expected an entry name followed by a lowercase hex sha256 of its data", path, i + 1);
+				exit(1);
+			},
+		}
+	}
+
+	hashes
+}
+
+// prints every entry of the image at path which is changed, missing, or not expected, and returns true if there are none
+pub fn check(path: &str, expected: &[EntryHash], actual: &[EntryHash]) -> bool {
+	// entries which match exactly are removed first, so names used more than once are paired up by their hashes
+	let mut expected: Vec<&EntryHash> = expected.iter().collect();
+	let mut unmatched: Vec<&EntryHash> = Vec::new();
+
+	for entry in actual.iter() {
+		match expected.iter().position(|other| *other == entry) {
+			Some(i) => {
+				expected.remove(i);
+			},
+			None => unmatched.push(entry),
+		}
+	}
+
+	let matched = unmatched.is_empty() && expected.is_empty();
+
+	for (name, hash) in unmatched {
+		match expected.iter().position(|(other, _)| other == name) {
+			Some(i) => {
+				error!("{}: entry {} has sha256 {}, expected {}", path, name, hash, expected[i].1);
+				expected.remove(i);
+			},
+			None => error!("{}: entry {} is not expected", path, name),
+		}
+	}
+
+	for (name, _) in expected {
+		error!("{}: missing entry {}", path, name);
+	}
+
+	matched
+}
@@ -0,0 +1,134 @@
+// ctrl-c handling, so an interrupted build never leaves a partial output at the output path
+//
+// outputs are written to a temporary file next to the output which is renamed over it once it is complete,
+// so interrupting the build removes the temporary file and leaves any previous output untouched
+
+use std::ffi::CString;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::os::raw::c_char;
+use std::process;
+use std::ptr;
+use std::sync::atomic::{AtomicBool, AtomicPtr, AtomicUsize, Ordering};
+
+#[cfg(unix)]
+use crate::exit_code;
+
+// temporary file currently being written, which the signal handler removes
+static TEMP_PATH: AtomicPtr<c_char> = AtomicPtr::new(ptr::null_mut());
+// set by the signal handler before it reads TEMP_PATH, after which no path it could be reading is freed
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+// number of temporary files created, which makes each temporary path unique within the process
+static TEMP_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+#[cfg(unix)]
+extern "C" fn on_interrupt(_signal: libc::c_int) {
+	// only async signal safe functions can be called here, so the path is removed without allocating
+	INTERRUPTED.store(true, Ordering::SeqCst);
+	let path = TEMP_PATH.load(Ordering::SeqCst);
+	unsafe {
+		if !path.is_null() {
+			libc::unlink(path);
+		}
+		libc::_exit(exit_code::INTERRUPTED);
+	}
+}
+
+// makes ctrl-c remove the temporary file being written and exit with exit_code::INTERRUPTED
+#[cfg(unix)]
+pub fn install() {
+	unsafe {
+		libc::signal(libc::SIGINT, on_interrupt as extern "C" fn(libc::c_int) as libc::sighandler_t);
+	}
+}
+
+#[cfg(not(unix))]
+pub fn install() {}
+
+// sets the temporary file removed by the signal handler, the previous path is freed unless the handler
+// may be reading it, in which case the process is about to exit anyway
+fn set_temp_path(path: Option<&str>) {
+	let path = path.and_then(|path| CString::new(path).ok()).map_or(ptr::null_mut(), CString::into_raw);
+	let old = TEMP_PATH.swap(path, Ordering::SeqCst);
+
+	// a handler that sets INTERRUPTED after this load reads TEMP_PATH after the swap, so it never sees old
+	if !old.is_null() && !INTERRUPTED.load(Ordering::SeqCst) {
+		unsafe {
+			drop(CString::from_raw(old));
+		}
+	}
+}
+
+// the file an output at path is written to, which is the target of path if it is a symlink,
+// so the symlink is kept rather than replaced with a regular file
+fn resolve(path: &str) -> String {
+	if fs::symlink_metadata(path).is_ok_and(|metadata| metadata.file_type().is_symlink()) {
+		if let Some(target) = fs::canonicalize(path).ok().and_then(|target| target.to_str().map(str::to_owned)) {
+			return target;
+		}
+	}
+	path.to_owned()
+}
+
+// an output being written to a temporary file, which only replaces the output once it is persisted
+pub struct TempOutput {
+	path: String,
+	temp_path: String,
+	pub file: File,
+}
+
+impl TempOutput {
+	pub fn create(path: &str) -> io::Result<Self> {
+		let path = resolve(path);
+
+		// the temporary file is new, so an existing file which happens to have its name is never overwritten
+		let (temp_path, file) = loop {
+			let temp_path = format!("{}.{}-{}.tmp", path, process::id(), TEMP_COUNT.fetch_add(1, Ordering::Relaxed));
+			match OpenOptions::new().write(true).create_new(true).open(&temp_path) {
+				Ok(file) => break (temp_path, file),
+				Err(err) if err.kind() == io::ErrorKind::AlreadyExists => continue,
+				Err(err) => return Err(err),
+			}
+		};
+
+		// registered once the file is created, so a file of the same name that already existed is never removed
+		set_temp_path(Some(&temp_path));
+
+		Ok(TempOutput {
+			path,
+			temp_path,
+			file,
+		})
+	}
+
+	// replaces the output with the temporary file, which keeps the permissions of the output it replaces
+	pub fn persist(self) -> io::Result<()> {
+		let result = match fs::metadata(&self.path) {
+			Ok(metadata) => fs::set_permissions(&self.temp_path, metadata.permissions()),
+			Err(_) => Ok(()),
+		}.and_then(|_| fs::rename(&self.temp_path, &self.path));
+
+		if result.is_err() {
+			let _ = fs::remove_file(&self.temp_path);
+		}
+
+		set_temp_path(None);
+		result
+	}
+
+	// removes the temporary file, leaving the output as it was
+	pub fn discard(&self) {
+		let _ = fs::remove_file(&self.temp_path);
+		set_temp_path(None);
+	}
+}
+
+// writes bytes to path through a TempOutput, like fs::write but leaving path as it was if writing fails or is interrupted
+pub fn write(path: &str, bytes: impl AsRef<[u8]>) -> io::Result<()> {
+	let mut output = TempOutput::create(path)?;
+	if let Err(err) = output.file.write_all(bytes.as_ref()) {
+		output.discard();
+		return Err(err);
+	}
+	output.persist()
+}
@@ -0,0 +1,410 @@
+// --emit-layout, which describes where every entry ended up in the generated initrd for other tools
+//
+//	size = 4096
+//
+//	[[entry]]
+//	type = "early-init"
+//	name = "build/early-init"
+//	name-offset = 152
+//	data-offset = 176
+//	data-len = 2048
+//	len = 2048
+//
+// offsets are from the start of the initrd, and len is larger than data-len if trailing zeros were trimmed
+//
+// --symbol-map writes the same offsets as text a debugger can use to find which entry an offset is in,
+// one line of hex offset, hex length, and name per entry sorted by offset
+//
+//	0xb0 0x800 build/early-init
+//
+// --pin-layout reads a layout written by --emit-layout and checks every entry in it is still at the same offsets
+//
+// --from-json-layout builds an initrd from a layout planned by another tool, with the fields of --emit-layout as
+// json, or as toml so a layout written by --emit-layout can be given as it is, where every entry can also give
+// the path its data is read from, which defaults to its name, and its align
+//
+//	{"size": 4096, "entry": [{"type": "early-init", "name": "early-init", "path": "build/early-init",
+//		"name-offset": 152, "data-offset": 176, "data-len": 2048}]}
+//
+// the entries are included in the order given, and the generated initrd has to match the layout exactly, so it
+// must be built with the same options the layout was planned for, entries of types generated by options such as
+// --comment are not read from files and need those options to be given again
+
+use std::fs;
+use std::process::exit;
+
+use serde::{Deserialize, Serialize};
+
+use gen_initrd::parse::Initrd;
+use gen_initrd::{type_name, EntryType};
+
+use crate::exit_code;
+use crate::interrupt;
+use crate::manifest::{self, Manifest, ManifestFile};
+
+fn write_text(path: &str, what: &str, text: &str) {
+	if let Err(err) = interrupt::write(path, text) {
+		error!("Could not write {} to {}: {}", what, path, err);
+		exit(exit_code::WRITE_FAILED);
+	}
+}
+
+// offset of slice, which must be part of bytes, from the start of bytes
+fn offset(bytes: &[u8], slice: &[u8]) -> u64 {
+	slice.as_ptr() as u64 - bytes.as_ptr() as u64
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "kebab-case")]
+struct Layout<'a> {
+	size: u64,
+	// offset of the initrd in its containing file, if it was built with --base-offset
+	#[serde(skip_serializing_if = "Option::is_none")]
+	base_offset: Option<u64>,
+	#[serde(rename = "entry")]
+	entries: Vec<LayoutEntry<'a>>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "kebab-case")]
+struct LayoutEntry<'a> {
+	#[serde(rename = "type")]
+	typ: String,
+	name: &'a str,
+	name_offset: u64,
+	data_offset: u64,
+	data_len: u64,
+	len: u64,
+}
+
+// the parts of a layout read by --pin-layout, other fields are ignored
+#[derive(Deserialize)]
+pub struct PinnedLayout {
+	#[serde(default, rename = "entry")]
+	entries: Vec<PinnedEntry>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case")]
+struct PinnedEntry {
+	#[serde(rename = "type")]
+	typ: String,
+	name: String,
+	name_offset: u64,
+	data_offset: u64,
+}
+
+pub fn load_pinned(path: &str) -> PinnedLayout {
+	let text = match fs::read_to_string(path) {
+		Ok(text) => text,
+		Err(err) => {
+			error!("Could not read from file {}: {}", path, err);
+			exit(exit_code::MISSING_INPUT);
+		},
+	};
+
+	match toml::from_str(&text) {
+		Ok(layout) => layout,
+		Err(err) => {
+			error!("Invalid layout file {}: {}", path, err);
+			exit(1);
+		},
+	}
+}
+
+// exits if an entry of the layout pinned at path is missing from initrd, which was parsed from bytes,
+// or is at different offsets, or only warns if allow_drift is set
+pub fn check_pinned(path: &str, pinned: &PinnedLayout, bytes: &[u8], initrd: &Initrd, allow_drift: bool) {
+	let mut drifted = false;
+
+	for pinned_entry in pinned.entries.iter() {
+		let entry = initrd.entries.iter()
+			.find(|entry| type_name(entry.typ) == pinned_entry.typ && entry.name == pinned_entry.name);
+
+		let problem = match entry {
+			None => "is no longer in the initrd".to_owned(),
+			Some(entry) => {
+				let name_offset = offset(bytes, entry.name.as_bytes());
+				let data_offset = offset(bytes, entry.data);
+				if name_offset == pinned_entry.name_offset && data_offset == pinned_entry.data_offset {
+					continue;
+				}
+
+				format!("moved from name offset {:#x} and data offset {:#x} to {:#x} and {:#x}",
+					pinned_entry.name_offset, pinned_entry.data_offset, name_offset, data_offset)
+			},
+		};
+
+		if allow_drift {
+			warning!("{}: pinned {} entry {} {}", path, pinned_entry.typ, pinned_entry.name, problem);
+		} else {
+			error!("{}: pinned {} entry {} {}", path, pinned_entry.typ, pinned_entry.name, problem);
+			drifted = true;
+		}
+	}
+
+	if drifted {
+		exit(exit_code::VALIDATION_FAILED);
+	}
+}
+
+// a layout read by --from-json-layout
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct PlannedLayout {
+	size: Option<u64>,
+	#[serde(default, rename = "entry")]
+	entries: Vec<PlannedEntry>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case")]
+struct PlannedEntry {
+	#[serde(rename = "type")]
+	typ: String,
+	name: String,
+	path: Option<String>,
+	align: Option<u64>,
+	name_offset: u64,
+	data_offset: u64,
+	data_len: u64,
+}
+
+impl PlannedEntry {
+	fn path(&self) -> &str {
+		self.path.as_deref().unwrap_or(&self.name)
+	}
+
+	// entries of these types are generated by options rather than read from a file
+	fn is_generated(&self) -> bool {
+		matches!(EntryType::from_name(&self.typ), Some(EntryType::Comment | EntryType::InitArgs | EntryType::SourcePaths | EntryType::Tags))
+	}
+}
+
+impl PlannedLayout {
+	// name the layout gives the required entry of type typ
+	pub fn required_name(&self, typ: EntryType) -> Option<&str> {
+		self.entries.iter()
+			.find(|entry| EntryType::from_name(&entry.typ) == Some(typ))
+			.map(|entry| entry.name.as_str())
+	}
+
+	// the files to read the entries of the layout from, path is where the layout was read from
+	pub fn to_manifest(&self, path: &str) -> Manifest {
+		let files = self.entries.iter()
+			.filter(|entry| !entry.is_generated())
+			.map(|entry| {
+				let required = EntryType::from_name(&entry.typ).is_some_and(|typ| typ.is_required());
+				ManifestFile {
+					path: entry.path().to_owned(),
+					// required entries are named by required_name instead
+					name: Some(entry.name.clone()).filter(|_| !required),
+					typ: Some(entry.typ.clone()),
+					align: entry.align,
+					tags: Default::default(),
+				}
+			})
+			.collect();
+
+		let mut manifest = manifest::from_files(files, path);
+		manifest.sources.push(path.to_owned());
+		manifest
+	}
+}
+
+pub fn load_planned(path: &str) -> PlannedLayout {
+	let text = match fs::read_to_string(path) {
+		Ok(text) => text,
+		Err(err) => {
+			error!("Could not read from file {}: {}", path, err);
+			exit(exit_code::MISSING_INPUT);
+		},
+	};
+
+	// a toml document can not start with {, so anything else is read as toml
+	let layout = if text.trim_start().starts_with('{') {
+		serde_json::from_str(&text).map_err(|err| err.to_string())
+	} else {
+		toml::from_str(&text).map_err(|err| err.to_string())
+	};
+
+	match layout {
+		Ok(layout) => layout,
+		Err(err) => {
+			error!("Invalid layout file {}: {}", path, err);
+			exit(1);
+		},
+	}
+}
+
+// exits if initrd, which was parsed from bytes, does not have exactly the entries of the layout planned at path
+// at the offsets it planned
+pub fn check_planned(path: &str, planned: &PlannedLayout, bytes: &[u8], initrd: &Initrd) {
+	let mut mismatched = false;
+
+	if let Some(size) = planned.size.filter(|size| *size != bytes.len() as u64) {
+		error!("{}: the layout is {} bytes, but the generated initrd is {} bytes", path, size, bytes.len());
+		mismatched = true;
+	}
+
+	if planned.entries.len() != initrd.entries.len() {
+		error!("{}: the layout has {} entries, but the generated initrd has {}", path, planned.entries.len(), initrd.entries.len());
+		mismatched = true;
+	}
+
+	for (i, (planned_entry, entry)) in planned.entries.iter().zip(initrd.entries.iter()).enumerate() {
+		let name_offset = offset(bytes, entry.name.as_bytes());
+		let data_offset = offset(bytes, entry.data);
+
+		let problem = if type_name(entry.typ) != planned_entry.typ || entry.name != planned_entry.name {
+			format!("is {} entry {} in the generated initrd", type_name(entry.typ), entry.name)
+		} else if entry.data.len() as u64 != planned_entry.data_len {
+			// the data is not read from a file when its type is generated by an option
+			let source = if planned_entry.is_generated() { "its option" } else { planned_entry.path() };
+			format!("has {} bytes of data instead of {}, {} has probably changed", entry.data.len(), planned_entry.data_len, source)
+		} else if name_offset != planned_entry.name_offset || data_offset != planned_entry.data_offset {
+			format!("is at name offset {:#x} and data offset {:#x} instead of {:#x} and {:#x}",
+				name_offset, data_offset, planned_entry.name_offset, planned_entry.data_offset)
+		} else {
+			continue;
+		};
+
+		error!("{}: entry {}, {} entry {}, {}", path, i, planned_entry.typ, planned_entry.name, problem);
+		mismatched = true;
+	}
+
+	if mismatched {
+		exit(exit_code::VALIDATION_FAILED);
+	}
+}
+
+// writes the layout of initrd, which was parsed from bytes, to path
+pub fn write(path: &str, bytes: &[u8], initrd: &Initrd) {
+	let layout = Layout {
+		size: bytes.len() as u64,
+		base_offset: Some(initrd.base_offset).filter(|base_offset| *base_offset != 0),
+		entries: initrd.entries.iter().map(|entry| LayoutEntry {
+			typ: type_name(entry.typ),
+			name: entry.name,
+			name_offset: offset(bytes, entry.name.as_bytes()),
+			data_offset: offset(bytes, entry.data),
+			data_len: entry.data.len() as u64,
+			len: entry.len,
+		}).collect(),
+	};
+
+	let text = match toml::to_string(&layout) {
+		Ok(text) => text,
+		Err(err) => {
+			error!("Could not serialize layout: {}", err);
+			exit(1);
+		},
+	};
+
+	write_text(path, "layout", &text);
+}
+
+// writes the symbol map of initrd, which was parsed from bytes, to path,
+// entries without a name are given their type name
+pub fn write_symbol_map(path: &str, bytes: &[u8], initrd: &Initrd) {
+	let mut symbols: Vec<(u64, u64, String)> = initrd.entries.iter().map(|entry| {
+		let name = if entry.name.is_empty() { type_name(entry.typ) } else { entry.name.to_owned() };
+		(offset(bytes, entry.data), entry.data.len() as u64, name)
+	}).collect();
+	symbols.sort();
+
+	let text: String = symbols.iter()
+		.map(|(offset, len, name)| format!("{:#x} {:#x} {}\n", offset, len, name))
+		.collect();
+
+	write_text(path, "symbol map", &text);
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::env;
+	use std::process;
+
+	use gen_initrd::parse::parse;
+	use gen_initrd::{to_initrd, Entry, InitrdOptions, ALIGN, FLAG_BASE_OFFSET};
+
+	fn entry<'a>(typ: EntryType, name: &'a str, data: &[u8]) -> Entry<'a> {
+		Entry {
+			typ,
+			name,
+			data: data.to_vec(),
+			uid: 0,
+			gid: 0,
+			align: ALIGN,
+			source: None,
+			tags: Vec::new(),
+			compressed: None,
+		}
+	}
+
+	// an initrd with a base offset, so offsets from the start of the initrd differ from the offsets it stores
+	fn initrd() -> Vec<u8> {
+		let entries = vec![
+			entry(EntryType::EarlyInit, "build/early-init", &[1; 100]),
+			entry(EntryType::PartList, "build/part-list", b"/dev/sda1 ext2 /"),
+			entry(EntryType::Any, "drivers/ext2", &[2; 9]),
+		];
+		let options = InitrdOptions {
+			flags: FLAG_BASE_OFFSET,
+			base_offset: 0x1000,
+			..InitrdOptions::default()
+		};
+		to_initrd(&entries, &options).unwrap()
+	}
+
+	// writes the layout of bytes with write and returns where it was written
+	fn write_layout(test: &str, bytes: &[u8]) -> String {
+		let path = env::temp_dir().join(format!("gen-initrd-{}-{}.toml", test, process::id()));
+		let path = path.to_str().unwrap().to_owned();
+		write(&path, bytes, &parse(bytes).unwrap());
+		path
+	}
+
+	#[test]
+	fn planned_round_trip() {
+		let bytes = initrd();
+		let path = write_layout("planned-round-trip", &bytes);
+		let planned = load_planned(&path);
+		fs::remove_file(&path).unwrap();
+
+		let initrd = parse(&bytes).unwrap();
+		assert_eq!(planned.size, Some(bytes.len() as u64));
+		assert_eq!(planned.required_name(EntryType::EarlyInit), Some("build/early-init"));
+		assert_eq!(planned.entries.len(), initrd.entries.len());
+		for (planned_entry, entry) in planned.entries.iter().zip(initrd.entries.iter()) {
+			assert_eq!(planned_entry.name, entry.name);
+			assert_eq!(planned_entry.name_offset, offset(&bytes, entry.name.as_bytes()));
+			assert_eq!(planned_entry.data_offset, offset(&bytes, entry.data));
+			assert_eq!(planned_entry.data_len, entry.data.len() as u64);
+		}
+
+		// exits if the initrd does not match
+		check_planned(&path, &planned, &bytes, &initrd);
+	}
+
+	#[test]
+	fn pinned_round_trip() {
+		let bytes = initrd();
+		let path = write_layout("pinned-round-trip", &bytes);
+		let pinned = load_pinned(&path);
+		fs::remove_file(&path).unwrap();
+
+		let initrd = parse(&bytes).unwrap();
+		assert_eq!(pinned.entries.len(), initrd.entries.len());
+		for (pinned_entry, entry) in pinned.entries.iter().zip(initrd.entries.iter()) {
+			assert_eq!(pinned_entry.typ, type_name(entry.typ));
+			assert_eq!(pinned_entry.name, entry.name);
+			assert_eq!(pinned_entry.name_offset, offset(&bytes, entry.name.as_bytes()));
+			assert_eq!(pinned_entry.data_offset, offset(&bytes, entry.data));
+		}
+
+		// exits if an entry moved
+		check_pinned(&path, &pinned, &bytes, &initrd, false);
+	}
+}
@@ -0,0 +1,1176 @@
+use std::cmp;
+use std::convert::{TryFrom, TryInto};
+use std::fmt;
+use std::fs::{File, Metadata};
+use std::io::{self, Read};
+use std::mem::size_of;
+
+use crate::checksum::ChecksumAlgorithm;
+
+pub mod checksum;
+pub mod merkle;
+pub mod parse;
+pub mod reader;
+pub mod stream;
+#[cfg(feature = "cffi")]
+pub mod ffi;
+
+pub const MAGIC: u64 = 0x39f298aa4b92e836;
+// used instead of MAGIC when the header is followed by a HeaderExt
+pub const MAGIC_EXT: u64 = 0xa4c1e07d5b3f9962;
+// used instead of MAGIC when the header is followed by entries in the stream format instead of an entry table
+pub const MAGIC_STREAM: u64 = 0x5d0c7a41e9b3f218;
+pub const ALIGN: u64 = 8;
+
+// entries are stored as EntryRawCompact instead of EntryRaw
+pub const FLAG_COMPACT: u64 = 1 << 0;
+// every entry in the table is followed by an EntryOwnership
+pub const FLAG_OWNERSHIP: u64 = 1 << 1;
+// the image ends with a Trailer
+pub const FLAG_TRAILER: u64 = 1 << 2;
+// entry name and data offsets are relative to the start of the data region instead of the image
+pub const FLAG_RELATIVE: u64 = 1 << 3;
+// trailing zeros are trimmed from entry data, and every entry in the table is followed by an EntryTrim
+pub const FLAG_TRIM_ZEROS: u64 = 1 << 4;
+// entry name and data offsets are relative to HeaderExt.base_offset bytes before the start of the image,
+// for images placed at a known offset in a larger file, has no effect with FLAG_RELATIVE
+pub const FLAG_BASE_OFFSET: u64 = 1 << 5;
+// every entry's data starts at a multiple of HeaderExt.dma_align and is followed by zero padding up to a multiple
+// of it, so data can be transferred by dma straight from the image
+pub const FLAG_DMA_ALIGN: u64 = 1 << 6;
+// the entry table is after the data of every entry at HeaderExt.table_offset instead of directly after the header,
+// so a writer can place the data before it knows the final offsets
+pub const FLAG_TABLE_AT_END: u64 = 1 << 7;
+// the data of entries may be compressed, and every entry in the table is followed by an EntryCompression saying
+// how, so loaders which can not decompress must reject the image
+pub const FLAG_COMPRESSED: u64 = 1 << 8;
+// the image is followed by a merkle tree over its blocks at HeaderExt.merkle_offset, before the trailer if there
+// is one, and the root is stored in HeaderExt.merkle_root, readers which do not check it can ignore it
+pub const FLAG_MERKLE: u64 = 1 << 32;
+// HeaderExt.name_align and HeaderExt.data_align record the alignments names and data were placed with,
+// readers can ignore them since the offset of every name and data is stored
+pub const FLAG_ALIGNMENTS: u64 = 1 << 33;
+// HeaderExt.checksum holds a checksum of the whole image computed with HeaderExt.checksum_algorithm, see checksum,
+// readers which do not check it can ignore it
+pub const FLAG_CHECKSUM: u64 = 1 << 34;
+// every flag this version knows how to read
+pub const KNOWN_FLAGS: u64 = FLAG_COMPACT | FLAG_OWNERSHIP | FLAG_TRAILER | FLAG_RELATIVE | FLAG_TRIM_ZEROS | FLAG_BASE_OFFSET | FLAG_DMA_ALIGN | FLAG_TABLE_AT_END | FLAG_COMPRESSED | FLAG_MERKLE | FLAG_ALIGNMENTS | FLAG_CHECKSUM;
+// flags in the upper half only add information readers can ignore, so readers reject unknown flags in the
+// lower half, which change how the rest of the image is read
+pub const OPTIONAL_FLAGS: u64 = 0xffff_ffff_0000_0000;
+
+// names of the flags set in flags, for displaying to the user
+pub fn flag_names(flags: u64) -> Vec<&'static str> {
+	let names = [
+		(FLAG_COMPACT, "compact"),
+		(FLAG_OWNERSHIP, "ownership"),
+		(FLAG_TRAILER, "trailer"),
+		(FLAG_RELATIVE, "relative"),
+		(FLAG_TRIM_ZEROS, "trim-zeros"),
+		(FLAG_BASE_OFFSET, "base-offset"),
+		(FLAG_DMA_ALIGN, "dma-align"),
+		(FLAG_TABLE_AT_END, "table-at-end"),
+		(FLAG_COMPRESSED, "compressed"),
+		(FLAG_MERKLE, "merkle"),
+		(FLAG_ALIGNMENTS, "alignments"),
+		(FLAG_CHECKSUM, "checksum"),
+	];
+
+	names.iter()
+		.filter(|(flag, _)| flags & flag != 0)
+		.map(|(_, name)| *name)
+		.collect()
+}
+
+// a sha256 hash, which raw_bytes can store like an integer, its bytes are kept in order
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Hash(pub [u8; 32]);
+
+impl Hash {
+	fn to_le_bytes(self) -> [u8; 32] {
+		self.0
+	}
+
+	fn from_le_bytes(bytes: [u8; 32]) -> Self {
+		Hash(bytes)
+	}
+}
+
+// lowercase hex, the same as sha256sum
+impl fmt::Display for Hash {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		self.0.iter().try_for_each(|byte| write!(f, "{:02x}", byte))
+	}
+}
+
+// implements encode and from_bytes for a struct made only of integers, the fields are listed in the order they
+// are stored and each is read and written as little endian at its own offset, so the encoding does not depend on
+// the layout rust gives the struct
+macro_rules! raw_bytes {
+	($ty:ident { $($field:ident: $int:ty),* $(,)? }) => {
+		impl $ty {
+			// size of the encoded struct in bytes
+			pub const SIZE: usize = 0 $(+ std::mem::size_of::<$int> ())*;
+
+			fn encode(&self) -> Vec<u8> {
+				let mut bytes = Vec::with_capacity(Self::SIZE);
+				$(bytes.extend_from_slice(&self.$field.to_le_bytes());)*
+				bytes
+			}
+
+			// returns ParseError::Truncated if bytes is too short to contain the struct
+			#[allow(unused_assignments)]
+			pub fn from_bytes(bytes: &[u8]) -> Result<Self, crate::parse::ParseError> {
+				let bytes = bytes.get(..Self::SIZE).ok_or(crate::parse::ParseError::Truncated)?;
+				let mut offset = 0;
+				$(
+					let $field = <$int>::from_le_bytes(bytes[offset..offset + std::mem::size_of::<$int> ()].try_into().unwrap());
+					offset += std::mem::size_of::<$int> ();
+				)*
+
+				Ok(Self {
+					$($field,)*
+				})
+			}
+		}
+	};
+}
+
+#[repr(u64)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EntryType {
+	Any = 0,
+	EarlyInit = 1,
+	PartList = 2,
+	FsSever = 3,
+	HwAccessServer = 4,
+	// freeform text describing the image, such as the commit it was built from
+	Comment = 5,
+	// arguments for early-init, which reads them from the image instead of being passed them by the kernel
+	InitArgs = 6,
+	// the path every entry read from a file was read from, keyed by its index in the entry table
+	SourcePaths = 7,
+	// key=value tags classifying entries, keyed by their index in the entry table
+	Tags = 8,
+}
+
+impl EntryType {
+	pub const ALL: [EntryType; 9] = [
+		Self::Any,
+		Self::EarlyInit,
+		Self::PartList,
+		Self::FsSever,
+		Self::HwAccessServer,
+		Self::Comment,
+		Self::InitArgs,
+		Self::SourcePaths,
+		Self::Tags,
+	];
+
+	// parses the names used on the command line, or the numeric value of the type
+	pub fn from_name(name: &str) -> Option<Self> {
+		match name.parse::<u64>() {
+			Ok(typ) => Self::from_u64(typ),
+			Err(_) => Self::ALL.iter().copied().find(|typ| typ.name() == name),
+		}
+	}
+
+	pub fn from_u64(typ: u64) -> Option<Self> {
+		Self::ALL.iter().copied().find(|known| *known as u64 == typ)
+	}
+
+	pub fn name(&self) -> &'static str {
+		match self {
+			Self::Any => "any",
+			Self::EarlyInit => "early-init",
+			Self::PartList => "part-list",
+			Self::FsSever => "fs-server",
+			Self::HwAccessServer => "hwaccess-server",
+			Self::Comment => "comment",
+			Self::InitArgs => "init-args",
+			Self::SourcePaths => "source-paths",
+			Self::Tags => "tags",
+		}
+	}
+
+	// returns true for the types of the entries every initrd must have
+	pub fn is_required(&self) -> bool {
+		!matches!(self, Self::Any | Self::Comment | Self::InitArgs | Self::SourcePaths | Self::Tags)
+	}
+}
+
+// algorithm the data of an entry is compressed with, stored in EntryCompression, where 0 means the data
+// is not compressed
+#[repr(u64)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+	Gzip = 1,
+	Zstd = 2,
+	Xz = 3,
+}
+
+impl Compression {
+	pub const ALL: [Compression; 3] = [
+		Self::Gzip,
+		Self::Zstd,
+		Self::Xz,
+	];
+
+	pub fn from_name(name: &str) -> Option<Self> {
+		Self::ALL.iter().copied().find(|algorithm| algorithm.name() == name)
+	}
+
+	pub fn from_u64(algorithm: u64) -> Option<Self> {
+		Self::ALL.iter().copied().find(|known| *known as u64 == algorithm)
+	}
+
+	pub fn name(&self) -> &'static str {
+		match self {
+			Self::Gzip => "gzip",
+			Self::Zstd => "zstd",
+			Self::Xz => "xz",
+		}
+	}
+}
+
+// name of a type read from an initrd, which may not be one this version knows about
+pub fn type_name(typ: u64) -> String {
+	match EntryType::from_u64(typ) {
+		Some(typ) => typ.name().to_owned(),
+		None => typ.to_string(),
+	}
+}
+
+#[derive(Debug)]
+pub struct Header {
+	pub magic: u64,
+	pub len: u64,
+}
+
+impl Header {
+	fn new(len: u64, flags: u64) -> Self {
+		Header {
+			magic: if flags == 0 { MAGIC } else { MAGIC_EXT },
+			len,
+		}
+	}
+}
+
+raw_bytes!(Header { magic: u64, len: u64 });
+
+// directly follows the header if magic is MAGIC_EXT
+#[derive(Debug)]
+pub struct HeaderExt {
+	// size of this struct in bytes, so readers can skip fields added after the ones they know
+	pub size: u64,
+	pub flags: u64,
+	// offset of the image in the file containing it if FLAG_BASE_OFFSET is set, otherwise 0,
+	// not present in images written before it was added, so readers must check size first
+	pub base_offset: u64,
+	// alignment of entry data and its padded length if FLAG_DMA_ALIGN is set, otherwise 0,
+	// only written when it or a later field is needed
+	pub dma_align: u64,
+	// offset of the entry table in the image if FLAG_TABLE_AT_END is set, otherwise 0
+	pub table_offset: u64,
+	// size of the blocks the merkle tree is over, offset of the tree, and its root if FLAG_MERKLE is set,
+	// only written when it is set
+	pub merkle_block_size: u64,
+	pub merkle_offset: u64,
+	pub merkle_root: Hash,
+	// alignment of the start of every name and data if FLAG_ALIGNMENTS is set, only written when it
+	// or a later field is needed
+	pub name_align: u64,
+	pub data_align: u64,
+	// a ChecksumAlgorithm and the checksum of the image if FLAG_CHECKSUM is set, only written when it is set
+	pub checksum_algorithm: u64,
+	pub checksum: Hash,
+}
+
+impl HeaderExt {
+	// size of HeaderExt before base_offset was added
+	pub const MIN_SIZE: u64 = 16;
+	// size of HeaderExt before dma_align was added, which is still written when FLAG_DMA_ALIGN is not set
+	// so those images stay the same
+	const BASE_OFFSET_SIZE: u64 = 24;
+	// size of HeaderExt before table_offset was added
+	const DMA_ALIGN_SIZE: u64 = 32;
+	// size of HeaderExt before the merkle tree fields were added
+	const TABLE_OFFSET_SIZE: u64 = 40;
+	// size of HeaderExt before the alignment fields were added
+	const MERKLE_SIZE: u64 = 88;
+	// size of HeaderExt before the checksum fields were added
+	const ALIGNMENTS_SIZE: u64 = 104;
+	// offset of merkle_root from the start of the image, which is hashed as zero
+	pub const MERKLE_ROOT_OFFSET: usize = Header::SIZE + Self::MERKLE_SIZE as usize - size_of::<Hash>();
+	// offset of checksum from the start of the image, which is read as zero by the checksum and merkle tree
+	pub const CHECKSUM_OFFSET: usize = Header::SIZE + Self::SIZE - size_of::<Hash>();
+
+	// number of bytes of HeaderExt written for an image with flags
+	fn size(flags: u64) -> u64 {
+		if flags & FLAG_CHECKSUM != 0 {
+			Self::SIZE as u64
+		} else if flags & FLAG_ALIGNMENTS != 0 {
+			Self::ALIGNMENTS_SIZE
+		} else if flags & FLAG_MERKLE != 0 {
+			Self::MERKLE_SIZE
+		} else if flags & FLAG_TABLE_AT_END != 0 {
+			Self::TABLE_OFFSET_SIZE
+		} else if flags & FLAG_DMA_ALIGN != 0 {
+			Self::DMA_ALIGN_SIZE
+		} else {
+			Self::BASE_OFFSET_SIZE
+		}
+	}
+
+	// offsets which are only known once the rest of the image is written are left as 0
+	fn new(flags: u64, base_offset: u64, dma_align: u64) -> Self {
+		HeaderExt {
+			size: Self::size(flags),
+			flags,
+			base_offset,
+			dma_align,
+			table_offset: 0,
+			merkle_block_size: 0,
+			merkle_offset: 0,
+			merkle_root: Hash::default(),
+			name_align: 0,
+			data_align: 0,
+			checksum_algorithm: 0,
+			checksum: Hash::default(),
+		}
+	}
+
+	// overwrites the extended header at the start of image
+	fn write_to(&self, image: &mut [u8]) {
+		let end = Header::SIZE + self.size as usize;
+		image[Header::SIZE..end].copy_from_slice(&self.encode()[..self.size as usize]);
+	}
+}
+
+raw_bytes!(HeaderExt {
+	size: u64,
+	flags: u64,
+	base_offset: u64,
+	dma_align: u64,
+	table_offset: u64,
+	merkle_block_size: u64,
+	merkle_offset: u64,
+	merkle_root: Hash,
+	name_align: u64,
+	data_align: u64,
+	checksum_algorithm: u64,
+	checksum: Hash,
+});
+
+// last bytes of the image when FLAG_TRAILER is set, so a loader can cheaply detect truncation
+#[derive(Debug)]
+pub struct Trailer {
+	// always MAGIC
+	pub magic: u64,
+	// length of the whole image, including the trailer
+	pub len: u64,
+}
+
+raw_bytes!(Trailer { magic: u64, len: u64 });
+
+// returns the uid and gid owning a file, or 0 for both on platforms without them
+#[cfg(unix)]
+pub fn metadata_owner(metadata: &Metadata) -> (u32, u32) {
+	use std::os::unix::fs::MetadataExt;
+
+	(metadata.uid(), metadata.gid())
+}
+
+#[cfg(not(unix))]
+pub fn metadata_owner(_metadata: &Metadata) -> (u32, u32) {
+	(0, 0)
+}
+
+#[derive(Debug)]
+pub struct Entry<'a> {
+	pub typ: EntryType,
+	pub name: &'a str,
+	pub data: Vec<u8>,
+	// only stored if FLAG_OWNERSHIP is set
+	pub uid: u32,
+	pub gid: u32,
+	// alignment of the data's offset, a power of two which is at least ALIGN
+	pub align: u64,
+	// path the data was read from, which is not stored in the entry itself
+	pub source: Option<&'a str>,
+	// key=value tags, which are not stored in the entry itself
+	pub tags: Vec<(&'a str, &'a str)>,
+	// data compressed with an algorithm, which is stored instead of data if FLAG_COMPRESSED is set
+	pub compressed: Option<(Compression, Vec<u8>)>,
+}
+
+impl Entry<'_> {
+	// reads the entry data and owner from path, and stores it in the initrd as name
+	pub fn new<'a>(typ: EntryType, path: &str, name: &'a str) -> io::Result<Entry<'a>> {
+		let mut file = File::open(path)?;
+		let mut data = Vec::new();
+		file.read_to_end(&mut data)?;
+
+		let (uid, gid) = metadata_owner(&file.metadata()?);
+
+		Ok(Entry {
+			typ,
+			name,
+			data,
+			uid,
+			gid,
+			align: ALIGN,
+			source: None,
+			tags: Vec::new(),
+			compressed: None,
+		})
+	}
+
+	fn name_bytes(&self) -> &[u8] {
+		self.name.as_bytes()
+	}
+
+	// data that is stored in the initrd, which is the compressed data if FLAG_COMPRESSED is set and the entry
+	// is compressed, and otherwise excludes trailing zeros if FLAG_TRIM_ZEROS is set
+	fn data_bytes(&self, flags: u64) -> &[u8] {
+		if let (Some((_, compressed)), true) = (&self.compressed, flags & FLAG_COMPRESSED != 0) {
+			compressed
+		} else if flags & FLAG_TRIM_ZEROS != 0 {
+			let len = self.data.iter().rposition(|byte| *byte != 0).map_or(0, |i| i + 1);
+			// readers reject more than MAX_TRIMMED_ZEROS zeros, so any beyond that are kept
+			let len = cmp::max(len, self.data.len().saturating_sub(MAX_TRIMMED_ZEROS as usize));
+			&self.data[..len]
+		} else {
+			&self.data[..]
+		}
+	}
+
+	// does not set name and data offset
+	fn as_raw(&self, flags: u64) -> EntryRaw {
+		EntryRaw {
+			typ: self.typ as u64,
+			name: 0,
+			name_len: self.name.len() as u64,
+			data: 0,
+			data_len: self.data_bytes(flags).len() as u64,
+		}
+	}
+}
+
+#[derive(Debug)]
+pub struct EntryRaw {
+	pub typ: u64,
+	pub name: u64,
+	pub name_len: u64,
+	pub data: u64,
+	pub data_len: u64,
+}
+
+raw_bytes!(EntryRaw { typ: u64, name: u64, name_len: u64, data: u64, data_len: u64 });
+
+// used instead of EntryRaw when FLAG_COMPACT is set
+#[derive(Debug)]
+pub struct EntryRawCompact {
+	pub typ: u32,
+	pub name: u32,
+	pub name_len: u32,
+	pub data: u32,
+	pub data_len: u32,
+}
+
+impl EntryRawCompact {
+	// returns None if any field does not fit in 32 bits
+	fn from_raw(raw: &EntryRaw) -> Option<Self> {
+		Some(EntryRawCompact {
+			typ: raw.typ.try_into().ok()?,
+			name: raw.name.try_into().ok()?,
+			name_len: raw.name_len.try_into().ok()?,
+			data: raw.data.try_into().ok()?,
+			data_len: raw.data_len.try_into().ok()?,
+		})
+	}
+
+	fn to_raw(&self) -> EntryRaw {
+		EntryRaw {
+			typ: self.typ.into(),
+			name: self.name.into(),
+			name_len: self.name_len.into(),
+			data: self.data.into(),
+			data_len: self.data_len.into(),
+		}
+	}
+}
+
+raw_bytes!(EntryRawCompact { typ: u32, name: u32, name_len: u32, data: u32, data_len: u32 });
+
+// starts each entry of an image in the stream format, directly followed by its name and data
+#[derive(Debug)]
+pub struct StreamEntryRaw {
+	pub typ: u64,
+	pub name_len: u64,
+	pub data_len: u64,
+}
+
+raw_bytes!(StreamEntryRaw { typ: u64, name_len: u64, data_len: u64 });
+
+// follows each entry in the table when FLAG_OWNERSHIP is set
+#[derive(Debug, Clone, Copy)]
+pub struct EntryOwnership {
+	pub uid: u32,
+	pub gid: u32,
+}
+
+raw_bytes!(EntryOwnership { uid: u32, gid: u32 });
+
+// follows each entry in the table, after its EntryOwnership if present, when FLAG_TRIM_ZEROS is set
+#[derive(Debug, Clone, Copy)]
+pub struct EntryTrim {
+	// length of the data before trailing zeros were trimmed, the data is zero extended to this length
+	pub original_len: u64,
+}
+
+raw_bytes!(EntryTrim { original_len: u64 });
+
+// most trailing zeros that can be trimmed from an entry, so a corrupt original_len can not make readers
+// allocate far more memory than the image is
+pub const MAX_TRIMMED_ZEROS: u64 = 1 << 30;
+
+// follows each entry in the table, after its EntryTrim if present, when FLAG_COMPRESSED is set
+//
+// the stored data of a compressed entry is never trimmed, so its EntryTrim has an original_len of its data_len
+#[derive(Debug, Clone, Copy)]
+pub struct EntryCompression {
+	// a Compression, or 0 if the data is stored uncompressed
+	pub algorithm: u64,
+	// length of the data once decompressed, which is data_len if the data is not compressed
+	pub len: u64,
+}
+
+raw_bytes!(EntryCompression { algorithm: u64, len: u64 });
+
+// size in bytes of each entry in the table
+pub fn table_entry_size(flags: u64) -> usize {
+	let mut size = if flags & FLAG_COMPACT != 0 {
+		EntryRawCompact::SIZE
+	} else {
+		EntryRaw::SIZE
+	};
+
+	if flags & FLAG_OWNERSHIP != 0 {
+		size += EntryOwnership::SIZE;
+	}
+
+	if flags & FLAG_TRIM_ZEROS != 0 {
+		size += EntryTrim::SIZE;
+	}
+
+	if flags & FLAG_COMPRESSED != 0 {
+		size += EntryCompression::SIZE;
+	}
+
+	size
+}
+
+// offset of the first name after the header, which is table_start bytes long, and the entry table unless
+// FLAG_TABLE_AT_END is set, or None if it does not fit in 64 bits
+pub fn data_start(table_start: usize, len: usize, flags: u64) -> Option<u64> {
+	let table_len = if flags & FLAG_TABLE_AT_END != 0 {
+		0
+	} else {
+		u64::try_from(len).ok()?.checked_mul(table_entry_size(flags) as u64)?
+	};
+	checked_align_up(table_len.checked_add(table_start as u64)?, ALIGN)
+}
+
+pub fn align_up(n: u64, align: u64) -> u64 {
+	(n + align - 1) & !(align - 1)
+}
+
+fn checked_align_up(n: u64, align: u64) -> Option<u64> {
+	Some(n.checked_add(align - 1)? & !(align - 1))
+}
+
+fn align_to(vec: &mut Vec<u8>, align: u64) {
+	let len = vec.len() as u64;
+	let aligned_len = align_up(len, align);
+
+	for _ in 0..(aligned_len - len) {
+		vec.push(0);
+	}
+}
+
+// order the names and data of entries are placed in after the entry table,
+// the entry table itself is always in the same order as the entries
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DataOrder {
+	Entries,
+	Name,
+	// smallest data first
+	Size,
+	// largest data first
+	SizeDesc,
+	// every name directly after the entry table in table order, followed by the data smallest first,
+	// so small entries share pages with the table
+	HotFirst,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct InitrdOptions {
+	pub flags: u64,
+	pub data_order: DataOrder,
+	// added to every offset if FLAG_BASE_OFFSET is set
+	pub base_offset: u64,
+	// alignment of every entry's data and its padded length if FLAG_DMA_ALIGN is set
+	pub dma_align: u64,
+	// size of the blocks the merkle tree is over if FLAG_MERKLE is set
+	pub merkle_block_size: u64,
+	// alignment of the start of every name, a power of two
+	pub name_align: u64,
+	// alignment of the start of every entry's data, a power of two which is at least ALIGN,
+	// entries with a larger alignment keep it
+	pub data_align: u64,
+	// algorithm of the checksum of the whole image if FLAG_CHECKSUM is set
+	pub checksum_algorithm: ChecksumAlgorithm,
+}
+
+impl Default for InitrdOptions {
+	fn default() -> Self {
+		InitrdOptions {
+			flags: 0,
+			data_order: DataOrder::Entries,
+			base_offset: 0,
+			dma_align: 0,
+			merkle_block_size: 0,
+			name_align: ALIGN,
+			data_align: ALIGN,
+			checksum_algorithm: ChecksumAlgorithm::Crc32,
+		}
+	}
+}
+
+// indices of entries in the order their data is placed
+fn physical_order(entries: &[Entry], data_order: DataOrder, flags: u64) -> Vec<usize> {
+	let mut order: Vec<usize> = (0..entries.len()).collect();
+
+	match data_order {
+		DataOrder::Entries => (),
+		DataOrder::Name => order.sort_by_key(|i| entries[*i].name),
+		DataOrder::Size => order.sort_by_key(|i| entries[*i].data.len()),
+		DataOrder::SizeDesc => order.sort_by_key(|i| std::cmp::Reverse(entries[*i].data.len())),
+		// the size actually stored is what determines which pages an entry touches
+		DataOrder::HotFirst => order.sort_by_key(|i| entries[*i].data_bytes(flags).len()),
+	}
+
+	order
+}
+
+#[derive(Debug)]
+pub enum EncodeError {
+	// FLAG_COMPACT is set but an offset or length does not fit in 32 bits
+	CompactOverflow,
+	// an offset does not fit in 64 bits
+	OffsetOverflow,
+	// an entry's alignment is not a power of two which is at least ALIGN
+	BadAlignment(u64),
+	// the name alignment is not a power of two
+	BadNameAlignment(u64),
+	BadMerkleBlockSize(u64),
+	// a region would start at offset before the end of the previous one, which is a bug in the layout of entries
+	Overlap { offset: u64, previous_end: u64 },
+}
+
+impl fmt::Display for EncodeError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			Self::CompactOverflow => write!(f, "initrd is too large for the compact format, all offsets and lengths must fit in 32 bits"),
+			Self::OffsetOverflow => write!(f, "initrd is too large, an offset does not fit in 64 bits"),
+			Self::BadAlignment(align) => write!(f, "alignment {} is not a power of two which is at least {}", align, ALIGN),
+			Self::BadNameAlignment(align) => write!(f, "name alignment {} is not a power of two", align),
+			Self::BadMerkleBlockSize(size) => write!(f, "merkle block size {} is not a power of two which is at least {}", size, merkle::MIN_BLOCK_SIZE),
+			Self::Overlap { offset, previous_end } => write!(f, "region at {:#x} overlaps the previous region ending at {:#x}", offset, previous_end),
+		}
+	}
+}
+
+// returns true if entries can be aligned to align
+pub fn valid_alignment(align: u64) -> bool {
+	align.is_power_of_two() && align >= ALIGN
+}
+
+// size of the header and extended header
+fn header_len(flags: u64) -> usize {
+	if flags == 0 {
+		Header::SIZE
+	} else {
+		Header::SIZE + HeaderExt::size(flags) as usize
+	}
+}
+
+// offsets of the name and data of every entry, in the same order as entries
+fn entry_positions(entries: &[Entry], options: &InitrdOptions) -> Result<Vec<(u64, u64)>, EncodeError> {
+	let flags = options.flags;
+	let advance = |offset: u64, len: u64, align: u64| offset.checked_add(len)
+		.and_then(|end| checked_align_up(end, align))
+		.ok_or(EncodeError::OffsetOverflow);
+
+	// current offset of data in file
+	let mut offset = data_start(header_len(flags), entries.len(), flags).ok_or(EncodeError::OffsetOverflow)?;
+	let mut positions = vec![(0, 0); entries.len()];
+
+	let name_align = options.name_align;
+	if !name_align.is_power_of_two() {
+		return Err(EncodeError::BadNameAlignment(name_align));
+	}
+
+	if !valid_alignment(options.data_align) {
+		return Err(EncodeError::BadAlignment(options.data_align));
+	}
+
+	// alignment of the start of every entry's data, and of its end, which is followed by a name
+	// unless FLAG_DMA_ALIGN pads it further
+	let (data_align, data_end_align) = if flags & FLAG_DMA_ALIGN != 0 {
+		if !valid_alignment(options.dma_align) {
+			return Err(EncodeError::BadAlignment(options.dma_align));
+		}
+		(cmp::max(options.data_align, options.dma_align), options.dma_align)
+	} else {
+		(options.data_align, name_align)
+	};
+
+	let names_first = matches!(options.data_order, DataOrder::HotFirst);
+	if names_first {
+		for (entry, position) in entries.iter().zip(positions.iter_mut()) {
+			position.0 = offset;
+			offset = advance(offset, entry.name.len() as u64, name_align)?;
+		}
+	}
+
+	for i in physical_order(entries, options.data_order, flags) {
+		let entry = &entries[i];
+		if !valid_alignment(entry.align) {
+			return Err(EncodeError::BadAlignment(entry.align));
+		}
+
+		if !names_first {
+			positions[i].0 = offset;
+			offset = advance(offset, entry.name.len() as u64, name_align)?;
+		}
+
+		let data = checked_align_up(offset, cmp::max(entry.align, data_align)).ok_or(EncodeError::OffsetOverflow)?;
+		offset = advance(data, entry.data_bytes(flags).len() as u64, data_end_align)?;
+
+		positions[i].1 = data;
+	}
+
+	Ok(positions)
+}
+
+// every name and data in the image with its offset and whether it is a name, sorted by offset
+fn placed_regions<'a>(entries: &'a [Entry], positions: &[(u64, u64)], flags: u64) -> Vec<(u64, bool, &'a [u8])> {
+	let mut regions: Vec<_> = entries.iter().zip(positions.iter())
+		.flat_map(|(entry, (name, data))| vec![(*name, true, entry.name_bytes()), (*data, false, entry.data_bytes(flags))])
+		.collect();
+
+	// an empty name or data shares its offset with the region after it, so empty regions are placed first,
+	// otherwise the stable sort keeps table order, which is not the order data is placed in with some layouts
+	regions.sort_by_key(|(offset, _, bytes)| (*offset, !bytes.is_empty()));
+	regions
+}
+
+pub fn to_initrd(entries: &[Entry], options: &InitrdOptions) -> Result<Vec<u8>, EncodeError> {
+	let flags = options.flags;
+	let positions = entry_positions(entries, options)?;
+
+	let mut out = Vec::new();
+
+	let header = Header::new(entries.len() as u64, flags);
+	out.extend_from_slice(&header.encode());
+
+	let base_offset = if flags & FLAG_BASE_OFFSET != 0 { options.base_offset } else { 0 };
+	let dma_align = if flags & FLAG_DMA_ALIGN != 0 { options.dma_align } else { 0 };
+
+	// the table and merkle tree offsets are filled in once the data has been written
+	let mut ext = HeaderExt::new(flags, base_offset, dma_align);
+	if flags & FLAG_ALIGNMENTS != 0 {
+		ext.name_align = options.name_align;
+		ext.data_align = options.data_align;
+	}
+	// the algorithm is covered by the merkle tree, so it is written before it, only the checksum is written last
+	if flags & FLAG_CHECKSUM != 0 {
+		ext.checksum_algorithm = options.checksum_algorithm as u64;
+	}
+	if flags != 0 {
+		out.extend_from_slice(&ext.encode()[..ext.size as usize]);
+	}
+
+	let data_start = data_start(out.len(), entries.len(), flags).ok_or(EncodeError::OffsetOverflow)?;
+
+	// offset stored in the table for a position in the image
+	let stored_offset = |offset: u64| if flags & FLAG_RELATIVE != 0 {
+		Ok(offset - data_start)
+	} else {
+		offset.checked_add(base_offset).ok_or(EncodeError::OffsetOverflow)
+	};
+
+	let mut raw_entries: Vec<EntryRaw> = entries.iter().map(|entry| entry.as_raw(flags)).collect();
+
+	for (raw_entry, (name, data)) in raw_entries.iter_mut().zip(positions.iter().copied()) {
+		raw_entry.name = stored_offset(name)?;
+		raw_entry.data = stored_offset(data)?;
+	}
+
+	let mut table = Vec::new();
+
+	for (entry, raw_entry) in entries.iter().zip(raw_entries.iter()) {
+		if flags & FLAG_COMPACT != 0 {
+			table.extend_from_slice(&EntryRawCompact::from_raw(raw_entry).ok_or(EncodeError::CompactOverflow)?.encode());
+		} else {
+			table.extend_from_slice(&raw_entry.encode());
+		}
+
+		if flags & FLAG_OWNERSHIP != 0 {
+			let ownership = EntryOwnership {
+				uid: entry.uid,
+				gid: entry.gid,
+			};
+			table.extend_from_slice(&ownership.encode());
+		}
+
+		let compression = entry.compressed.as_ref().filter(|_| flags & FLAG_COMPRESSED != 0);
+
+		if flags & FLAG_TRIM_ZEROS != 0 {
+			let trim = EntryTrim {
+				original_len: if compression.is_some() { raw_entry.data_len } else { entry.data.len() as u64 },
+			};
+			table.extend_from_slice(&trim.encode());
+		}
+
+		if flags & FLAG_COMPRESSED != 0 {
+			let compression = EntryCompression {
+				algorithm: compression.map_or(0, |(algorithm, _)| *algorithm as u64),
+				len: if compression.is_some() { entry.data.len() as u64 } else { raw_entry.data_len },
+			};
+			table.extend_from_slice(&compression.encode());
+		}
+	}
+
+	if flags & FLAG_TABLE_AT_END == 0 {
+		out.extend_from_slice(&table);
+	}
+
+	for (offset, _, bytes) in placed_regions(entries, &positions, flags) {
+		// positions only move forward, and the gaps between them are the alignment padding, so a region
+		// starting before the end of the previous one would mean the table points at the wrong bytes
+		if (out.len() as u64) > offset {
+			return Err(EncodeError::Overlap { offset, previous_end: out.len() as u64 });
+		}
+		out.resize(offset as usize, 0);
+		out.extend_from_slice(bytes);
+	}
+
+	// the padding after the last entry's data is not followed by any other region
+	if flags & FLAG_DMA_ALIGN != 0 {
+		align_to(&mut out, options.dma_align);
+	}
+
+	if flags & FLAG_TABLE_AT_END != 0 {
+		align_to(&mut out, ALIGN);
+
+		ext.table_offset = out.len() as u64;
+		ext.write_to(&mut out);
+		out.extend_from_slice(&table);
+	}
+
+	// the tree covers everything before it, including the header with the root still zero
+	if flags & FLAG_MERKLE != 0 {
+		if !merkle::valid_block_size(options.merkle_block_size) {
+			return Err(EncodeError::BadMerkleBlockSize(options.merkle_block_size));
+		}
+		align_to(&mut out, ALIGN);
+
+		ext.merkle_block_size = options.merkle_block_size;
+		ext.merkle_offset = out.len() as u64;
+		ext.write_to(&mut out);
+
+		let levels = merkle::levels(&out, options.merkle_block_size as usize);
+		ext.merkle_root = merkle::root(&levels);
+		out.extend_from_slice(&merkle::encode(&levels));
+		ext.write_to(&mut out);
+	}
+
+	if flags & FLAG_TRAILER != 0 {
+		align_to(&mut out, ALIGN);
+
+		let trailer = Trailer {
+			magic: MAGIC,
+			len: (out.len() + Trailer::SIZE) as u64,
+		};
+		out.extend_from_slice(&trailer.encode());
+	}
+
+	// the checksum covers everything else, including the merkle tree and trailer
+	if flags & FLAG_CHECKSUM != 0 {
+		ext.checksum = checksum::compute(options.checksum_algorithm, &out);
+		ext.write_to(&mut out);
+	}
+
+	Ok(out)
+}
+
+// writes entries in the stream format, where each entry is followed by its name and data so the image can be
+// read front to back without seeking, the alignment of entries is not kept since there are no offsets to align
+pub fn to_stream(entries: &[Entry]) -> Vec<u8> {
+	let mut out = Vec::new();
+
+	let header = Header {
+		magic: MAGIC_STREAM,
+		len: entries.len() as u64,
+	};
+	out.extend_from_slice(&header.encode());
+
+	for entry in entries.iter() {
+		let raw = StreamEntryRaw {
+			typ: entry.typ as u64,
+			name_len: entry.name.len() as u64,
+			data_len: entry.data.len() as u64,
+		};
+		out.extend_from_slice(&raw.encode());
+
+		// padding keeps every StreamEntryRaw 8 byte aligned
+		out.extend_from_slice(entry.name_bytes());
+		align_to(&mut out, ALIGN);
+		out.extend_from_slice(&entry.data);
+		align_to(&mut out, ALIGN);
+	}
+
+	out
+}
+
+// returns how many bytes of padding are inserted after names and after data respectively
+pub fn padding_waste(entries: &[Entry], options: &InitrdOptions) -> Result<(u64, u64), EncodeError> {
+	let positions = entry_positions(entries, options)?;
+	let regions = placed_regions(entries, &positions, options.flags);
+
+	let mut name_waste = 0;
+	let mut data_waste = 0;
+
+	// the last region is not followed by any padding
+	for (region, next) in regions.iter().zip(regions.iter().skip(1)) {
+		let (offset, is_name, bytes) = region;
+		let gap = next.0 - (offset + bytes.len() as u64);
+
+		if *is_name {
+			name_waste += gap;
+		} else {
+			data_waste += gap;
+		}
+	}
+
+	Ok((name_waste, data_waste))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::parse::{parse, parse_layout, parse_table_entry};
+
+	fn entry<'a>(name: &'a str, data: &[u8]) -> Entry<'a> {
+		Entry {
+			typ: EntryType::Any,
+			name,
+			data: data.to_vec(),
+			uid: 0,
+			gid: 0,
+			align: ALIGN,
+			source: None,
+			tags: Vec::new(),
+			compressed: None,
+		}
+	}
+
+	// the raw table entries of an image, with the offsets as they are stored
+	fn table(image: &[u8]) -> Vec<EntryRaw> {
+		let layout = parse_layout(image).unwrap();
+		let size = table_entry_size(layout.flags);
+		(0..layout.len as usize)
+			.map(|i| parse_table_entry(&image[layout.table + i * size..], layout.flags).unwrap().raw)
+			.collect()
+	}
+
+	// entry counts this large only fit in a 64 bit usize
+	#[cfg(target_pointer_width = "64")]
+	#[test]
+	fn data_start_of_large_entry_counts() {
+		let size = table_entry_size(0) as u64;
+		let len = 1 << 40;
+		assert_eq!(data_start(Header::SIZE, len, 0), Some(align_up(Header::SIZE as u64 + len as u64 * size, ALIGN)));
+
+		// the table would not fit in 64 bits
+		assert_eq!(data_start(Header::SIZE, usize::MAX, 0), None);
+		assert_eq!(data_start(usize::MAX, 1, 0), None);
+		// no table is stored before the data
+		assert_eq!(data_start(Header::SIZE, usize::MAX, FLAG_TABLE_AT_END), Some(align_up(Header::SIZE as u64, ALIGN)));
+	}
+
+	#[test]
+	fn compact_offsets_at_u32_limit() {
+		let entries = vec![entry("init", b"early init"), entry("fs", &[1; 100])];
+		let mut options = InitrdOptions {
+			flags: FLAG_COMPACT | FLAG_BASE_OFFSET,
+			..InitrdOptions::default()
+		};
+
+		// place the last region so it starts exactly at the largest offset a compact entry can store
+		let last = entry_positions(&entries, &options).unwrap().iter()
+			.map(|(name, data)| cmp::max(*name, *data))
+			.max()
+			.unwrap();
+		options.base_offset = u32::MAX as u64 - last;
+
+		let image = to_initrd(&entries, &options).unwrap();
+		let raw = table(&image);
+		assert_eq!(raw[1].data, u32::MAX as u64);
+		assert_eq!(raw[1].data_len, 100);
+
+		let initrd = parse(&image).unwrap();
+		assert_eq!(initrd.entries[1].data, &[1; 100][..]);
+
+		options.base_offset += 1;
+		assert!(matches!(to_initrd(&entries, &options), Err(EncodeError::CompactOverflow)));
+
+		// without FLAG_COMPACT the offsets fit, the larger table moves them further past the limit
+		options.flags &= !FLAG_COMPACT;
+		let image = to_initrd(&entries, &options).unwrap();
+		assert!(table(&image)[1].data > u32::MAX as u64 + 1);
+		assert_eq!(parse(&image).unwrap().entries[1].data, &[1; 100][..]);
+	}
+
+	#[test]
+	fn offsets_around_alignment() {
+		// lengths around ALIGN and the space they take up once padded
+		let lens = [(ALIGN - 1, ALIGN), (ALIGN, ALIGN), (ALIGN + 1, 2 * ALIGN)];
+
+		for (name_len, padded_name_len) in lens.iter() {
+			for (data_len, padded_data_len) in lens.iter() {
+				let names = ["a".repeat(*name_len as usize), "b".repeat(*name_len as usize)];
+				let entries = vec![entry(&names[0], &vec![1; *data_len as usize]), entry(&names[1], &vec![2; *data_len as usize])];
+
+				let image = to_initrd(&entries, &InitrdOptions::default()).unwrap();
+				let raw = table(&image);
+
+				// the name of the first entry starts the data region, and every region follows the previous one padded to ALIGN
+				let start = data_start(Header::SIZE, 2, 0).unwrap();
+				assert_eq!(raw[0].name, start);
+				assert_eq!(raw[0].data, raw[0].name + padded_name_len);
+				assert_eq!(raw[1].name, raw[0].data + padded_data_len);
+				assert_eq!(raw[1].data, raw[1].name + padded_name_len);
+				assert_eq!(image.len() as u64, raw[1].data + data_len);
+
+				let initrd = parse(&image).unwrap();
+				for (parsed, entry) in initrd.entries.iter().zip(entries.iter()) {
+					assert_eq!(parsed.name, entry.name);
+					assert_eq!(parsed.data, &entry.data[..]);
+				}
+			}
+		}
+	}
+
+	#[test]
+	fn name_align_below_data_align_above_align() {
+		// odd lengths so names and data do not already end on an alignment
+		let entries = vec![entry("abc", &[1; 11]), entry("defgh", &[2; 1])];
+
+		for name_align in [1, 2, 4].iter().copied() {
+			for data_align in [2 * ALIGN, 8 * ALIGN].iter().copied() {
+				let options = InitrdOptions {
+					flags: FLAG_ALIGNMENTS,
+					name_align,
+					data_align,
+					..InitrdOptions::default()
+				};
+
+				let image = to_initrd(&entries, &options).unwrap();
+				let layout = parse_layout(&image).unwrap();
+				assert_eq!(layout.flags & FLAG_ALIGNMENTS, FLAG_ALIGNMENTS);
+				assert_eq!((layout.name_align, layout.data_align), (name_align, data_align));
+
+				// names follow the previous data padded only to name_align, and data is padded to data_align
+				let raw = table(&image);
+				let start = data_start(header_len(FLAG_ALIGNMENTS), 2, FLAG_ALIGNMENTS).unwrap();
+				assert_eq!(raw[0].name, start);
+				assert_eq!(raw[0].data, align_up(start + 3, data_align));
+				assert_eq!(raw[1].name, align_up(raw[0].data + 11, name_align));
+				assert_eq!(raw[1].data, align_up(raw[1].name + 5, data_align));
+				// which is not padded to ALIGN as it would be without FLAG_ALIGNMENTS
+				assert_ne!(raw[1].name % ALIGN, 0);
+
+				let initrd = parse(&image).unwrap();
+				assert_eq!(initrd.entries[0].data, &[1; 11][..]);
+				assert_eq!(initrd.entries[1].name, "defgh");
+			}
+		}
+	}
+
+	#[test]
+	fn compressed_entries() {
+		let mut compressed = entry("compressed", &[0; 100]);
+		compressed.compressed = Some((Compression::Zstd, b"not really zstd".to_vec()));
+		let entries = vec![compressed, entry("plain", b"plain\0\0")];
+		let options = InitrdOptions {
+			flags: FLAG_COMPRESSED | FLAG_TRIM_ZEROS,
+			..InitrdOptions::default()
+		};
+
+		let image = to_initrd(&entries, &options).unwrap();
+		let initrd = parse(&image).unwrap();
+
+		// compressed data is stored as given and never trimmed
+		assert_eq!(initrd.entries[0].compression, Some(Compression::Zstd));
+		assert_eq!(initrd.entries[0].data, b"not really zstd");
+		assert_eq!(initrd.entries[0].len, 100);
+		assert_eq!(initrd.entries[1].compression, None);
+		assert_eq!(initrd.entries[1].data, b"plain");
+		assert_eq!(initrd.entries[1].to_vec(), b"plain\0\0");
+
+		// the compressed data is ignored without FLAG_COMPRESSED
+		let image = to_initrd(&entries, &InitrdOptions::default()).unwrap();
+		assert_eq!(parse(&image).unwrap().entries[0].data, &[0; 100][..]);
+	}
+
+	#[test]
+	fn checksum_with_merkle_tree() {
+		let entries = vec![entry("init", &[1; 3000]), entry("fs", b"fs server")];
+
+		for algorithm in checksum::ChecksumAlgorithm::ALL.iter() {
+			let options = InitrdOptions {
+				flags: FLAG_CHECKSUM | FLAG_MERKLE | FLAG_TRAILER,
+				merkle_block_size: merkle::MIN_BLOCK_SIZE,
+				checksum_algorithm: *algorithm,
+				..InitrdOptions::default()
+			};
+
+			let mut image = to_initrd(&entries, &options).unwrap();
+			let initrd = parse(&image).unwrap();
+			let header = initrd.checksum.unwrap();
+			assert_eq!(header.algorithm, *algorithm as u64);
+			checksum::check(&image, &header).unwrap();
+			// the tree is computed before the checksum, which it reads as zero
+			merkle::check(&image, &initrd.merkle.unwrap()).unwrap();
+
+			let last = image.len() - 1;
+			image[last] ^= 1;
+			assert!(matches!(checksum::check(&image, &header), Err(checksum::ChecksumError::Mismatch { .. })));
+		}
+	}
+
+	#[test]
+	fn offsets_past_u64_limit() {
+		let entries = vec![entry("init", b"early init")];
+		let options = InitrdOptions {
+			flags: FLAG_BASE_OFFSET,
+			base_offset: u64::MAX - 8,
+			..InitrdOptions::default()
+		};
+		assert!(matches!(to_initrd(&entries, &options), Err(EncodeError::OffsetOverflow)));
+	}
+}
@@ -0,0 +1,123 @@
+// lock file recording the path and sha256 of every entry and the sha256 of the generated initrd, so a build can be
+// checked against it later
+//
+//	output = "<sha256 of the initrd>"
+//
+//	[[entry]]
+//	name = "init"
+//	type = "early-init"
+//	path = "build/early-init"
+//	sha256 = "<sha256 of the entry data>"
+//
+// path is left out for entries which are not read from a file, such as those read from --fd
+
+use std::fs;
+use std::process::exit;
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use gen_initrd::Entry;
+
+use crate::exit_code;
+use crate::interrupt;
+
+#[derive(Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct Lock {
+	pub output: String,
+	#[serde(default, rename = "entry")]
+	pub entries: Vec<LockEntry>,
+}
+
+#[derive(Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct LockEntry {
+	pub name: String,
+	#[serde(rename = "type")]
+	pub typ: String,
+	// path the entry was read from
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub path: Option<String>,
+	pub sha256: String,
+}
+
+pub fn sha256_hex(bytes: &[u8]) -> String {
+	Sha256::digest(bytes).iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+// creates the lock describing initrd, which was generated from entries
+pub fn from_build(entries: &[Entry], initrd: &[u8]) -> Lock {
+	Lock {
+		output: sha256_hex(initrd),
+		entries: entries.iter().map(|entry| LockEntry {
+			name: entry.name.to_owned(),
+			typ: entry.typ.name().to_owned(),
+			path: entry.source.map(str::to_owned),
+			sha256: sha256_hex(&entry.data),
+		}).collect(),
+	}
+}
+
+pub fn load(path: &str) -> Lock {
+	let text = match fs::read_to_string(path) {
+		Ok(text) => text,
+		Err(err) => {
+			error!("Could not read from file {}: {}", path, err);
+			exit(exit_code::MISSING_INPUT);
+		},
+	};
+
+	match toml::from_str(&text) {
+		Ok(lock) => lock,
+		Err(err) => {
+			error!("Invalid lock file {}: {}", path, err);
+			exit(1);
+		},
+	}
+}
+
+pub fn write(path: &str, lock: &Lock) {
+	let text = match toml::to_string(lock) {
+		Ok(text) => text,
+		Err(err) => {
+			error!("Could not serialize lock file: {}", err);
+			exit(1);
+		},
+	};
+
+	if let Err(err) = interrupt::write(path, text) {
+		error!("Could not write lock file {}: {}", path, err);
+		exit(exit_code::WRITE_FAILED);
+	}
+}
+
+// prints every difference between the lock at path and the lock of the current build,
+// and returns true if there are none
+pub fn check(path: &str, locked: &Lock, built: &Lock) -> bool {
+	if locked == built {
+		return true;
+	}
+
+	for entry in built.entries.iter() {
+		match locked.entries.iter().find(|locked_entry| locked_entry.name == entry.name && locked_entry.typ == entry.typ) {
+			Some(locked_entry) if locked_entry == entry => (),
+			Some(locked_entry) if locked_entry.sha256 != entry.sha256 => error!("{}: entry {} has changed", path, entry.name),
+			Some(locked_entry) => error!("{}: entry {} was read from {} instead of {}", path, entry.name,
+				entry.path.as_deref().unwrap_or("no file"), locked_entry.path.as_deref().unwrap_or("no file")),
+			None => error!("{}: entry {} is not in the lock file", path, entry.name),
+		}
+	}
+
+	for locked_entry in locked.entries.iter() {
+		if !built.entries.iter().any(|entry| entry.name == locked_entry.name && entry.typ == locked_entry.typ) {
+			error!("{}: entry {} is no longer in the initrd", path, locked_entry.name);
+		}
+	}
+
+	if locked.output != built.output {
+		error!("{}: generated initrd does not match the locked output hash", path);
+	}
+
+	false
+}
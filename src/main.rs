@@ -1,247 +1,2650 @@
-use clap::clap_app;
+// clap_app expands recursively once for every argument
+#![recursion_limit = "256"]
 
-use std::fs::{File, metadata};
-use std::io::{self, Read, Write};
+use clap::{clap_app, Arg, ArgMatches, SubCommand};
+
+use std::fs::{self, File, OpenOptions, metadata};
+use std::io::{self, BufReader, Write};
+use std::path::Path;
 use std::process::exit;
-use std::time::SystemTime;
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use std::cmp;
+use std::convert::TryFrom;
+use std::env;
+use std::iter;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
 
-const MAGIC: u64 = 0x39f298aa4b92e836;
-const ALIGN: u64 = 8;
+use gen_initrd::{checksum, merkle, parse, reader, stream, align_up, flag_names, metadata_owner, padding_waste, to_initrd, to_stream, type_name, valid_alignment};
+use gen_initrd::{Compression, DataOrder, Entry, EntryOwnership, EntryType, InitrdOptions, ALIGN, KNOWN_FLAGS};
+use gen_initrd::checksum::{ChecksumAlgorithm, ChecksumError};
+use gen_initrd::stream::{StreamEntry, StreamReader};
+use gen_initrd::{FLAG_ALIGNMENTS, FLAG_BASE_OFFSET, FLAG_CHECKSUM, FLAG_COMPACT, FLAG_COMPRESSED, FLAG_DMA_ALIGN, FLAG_MERKLE, FLAG_OWNERSHIP, FLAG_RELATIVE, FLAG_TABLE_AT_END, FLAG_TRAILER, FLAG_TRIM_ZEROS};
 
-#[repr(u64)]
-#[derive(Debug, Clone, Copy)]
-enum EntryType {
-	Any = 0,
-	EarlyInit = 1,
-	PartList = 2,
-	FsSever = 3,
-	HwAccessServer = 4,
+// prints an error message to stderr, in red if color is enabled
+macro_rules! error {
+	($($arg:tt)*) => {
+		eprintln!("{}", crate::color::error(&format!($($arg)*)))
+	};
 }
 
-#[repr(C)]
-#[derive(Debug)]
-struct Header {
-	magic: u64,
-	len: u64,
+// prints a warning message to stderr, with a yellow prefix if color is enabled, and counts it for --fail-on-warning
+macro_rules! warning {
+	($($arg:tt)*) => {{
+		crate::WARNING_COUNT.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+		eprintln!("{} {}", crate::color::warning("Warning:"), format!($($arg)*))
+	}};
 }
 
-impl Header {
-	fn new(len: u64) -> Self {
-		Header {
-			magic: MAGIC,
-			len,
-		}
+mod audit;
+mod c_header;
+mod color;
+mod compress;
+mod elf;
+mod exit_code;
+mod hashes;
+mod interrupt;
+mod layout;
+mod lock;
+mod manifest;
+mod part_list;
+mod preprocess;
+mod progress;
+mod sign;
+mod source_paths;
+mod tags;
+mod tree;
+mod validate;
+mod watch;
+
+// number of entries at the start of the entry list which come from the required arguments
+const REQUIRED_ENTRIES: usize = 4;
+
+// longest text --comment can store
+const MAX_COMMENT_LEN: usize = 4096;
+
+// number of warnings printed so far
+static WARNING_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+// set once the build starts writing its outputs, after which --time-budget no longer aborts it,
+// so an output is never left half written
+static WRITING_OUTPUT: Mutex<bool> = Mutex::new(false);
+
+// exits if any warnings have been printed and fail is set
+fn check_warnings(fail: bool) {
+	let count = WARNING_COUNT.load(Ordering::Relaxed);
+
+	if fail && count != 0 {
+		error!("{} warning{} printed, failing because of --fail-on-warning", count, if count == 1 { " was" } else { "s were" });
+		exit(exit_code::VALIDATION_FAILED);
 	}
+}
+
+// exits if the build has not started writing its outputs after the number of seconds in budget
+fn start_time_budget(budget: &str) {
+	let duration = match budget.parse::<f64>().ok().filter(|seconds| *seconds > 0.0).map(Duration::try_from_secs_f64) {
+		Some(Ok(duration)) => duration,
+		_ => {
+			error!("Invalid value for --time-budget: {} is not a positive number of seconds", budget);
+			exit(1);
+		},
+	};
 
-	fn as_bytes(&self) -> &[u8] {
-		unsafe {
-			let ptr = self as *const _ as *const u8;
-			std::slice::from_raw_parts(ptr, std::mem::size_of::<Self> ())
+	let budget = budget.to_owned();
+	thread::spawn(move || {
+		thread::sleep(duration);
+
+		// the lock is held while exiting so the build can not start writing at the same time
+		let writing = WRITING_OUTPUT.lock().unwrap();
+		if !*writing {
+			error!("Build did not finish within --time-budget of {} seconds", budget);
+			exit(1);
 		}
+	});
+}
+
+fn percent(part: u64, total: u64) -> f64 {
+	if total == 0 {
+		0.0
+	} else {
+		part as f64 * 100.0 / total as f64
 	}
 }
 
-#[derive(Debug)]
-struct Entry<'a> {
+// an additional file listed in the file passed to --files-csv or the manifest
+struct ListedFile<'a> {
+	path: &'a str,
 	typ: EntryType,
 	name: &'a str,
-	data: Vec<u8>,
+	align: u64,
+	tags: Vec<(&'a str, &'a str)>,
+}
+
+// an entry given by --entry TYPE:PATH[:NAME], or by one of the required entry flags which are shorthands for it
+struct EntryArg<'a> {
+	typ: EntryType,
+	path: &'a str,
+	name: Option<&'a str>,
+}
+
+// parses the TYPE:PATH[:NAME] value of --entry, paths can not contain : but names can
+fn parse_entry_arg(arg: &str) -> EntryArg<'_> {
+	let mut parts = arg.splitn(3, ':');
+	let (typ, path, name) = match (parts.next(), parts.next(), parts.next()) {
+		(Some(typ), Some(path), name) if !path.is_empty() && name != Some("") => (typ, path, name),
+		_ => {
+			error!("Invalid value for --entry: expected TYPE:PATH or TYPE:PATH:NAME, got {}", arg);
+			exit(1);
+		},
+	};
+
+	match EntryType::from_name(typ) {
+		Some(typ) => EntryArg { typ, path, name },
+		None => {
+			error!("Invalid value for --entry {}: unknown entry type {}", arg, typ);
+			exit(1);
+		},
+	}
+}
+
+// path relative to dir, if path is under dir, used to name additional files with --relative-to
+fn relative_name<'a>(path: &'a str, dir: Option<&str>) -> Option<&'a str> {
+	Path::new(path).strip_prefix(dir?).ok()
+		.and_then(|name| name.to_str())
+		.filter(|name| !name.is_empty())
 }
 
-impl Entry<'_> {
-	fn new(typ: EntryType, path: &str) -> io::Result<Entry> {
-		let mut file = File::open(path)?;
-		let mut data = Vec::new();
-		file.read_to_end(&mut data)?;
+// parses rows of `path,type,name`, where type and name may be left blank, a blank name is the path relative to
+// relative_to if it is given and the path is under it, or the file name of the path otherwise
+// files ending in .tsv are tab separated instead of comma separated
+fn parse_files_csv<'a>(csv_path: &str, text: &'a str, relative_to: Option<&str>) -> Vec<ListedFile<'a>> {
+	let separator = if csv_path.ends_with(".tsv") { '\t' } else { ',' };
 
-		Ok(Entry {
+	let mut out = Vec::new();
+
+	for (i, line) in text.lines().enumerate() {
+		let line_num = i + 1;
+
+		if line.trim().is_empty() || line.starts_with('#') {
+			continue;
+		}
+
+		let fields: Vec<&str> = line.split(separator).map(str::trim).collect();
+		if fields.len() > 3 || fields[0].is_empty() {
+			error!("{}:{}: malformed row, expected path{}type{}name", csv_path, line_num, separator, separator);
+			exit(1);
+		}
+
+		let path = fields[0];
+
+		let typ = match fields.get(1) {
+			None | Some(&"") => EntryType::Any,
+			Some(typ) => match EntryType::from_name(typ) {
+				Some(typ) => typ,
+				None => {
+					error!("{}:{}: unknown entry type {}", csv_path, line_num, typ);
+					exit(1);
+				},
+			},
+		};
+
+		let name = match fields.get(2) {
+			None | Some(&"") => relative_name(path, relative_to)
+				.or_else(|| Path::new(path).file_name().and_then(|name| name.to_str()))
+				.unwrap_or(path),
+			Some(name) => name,
+		};
+
+		out.push(ListedFile {
+			path,
 			typ,
-			name: path,
-			data,
-		})
+			name,
+			align: ALIGN,
+			tags: Vec::new(),
+		});
+	}
+
+	out
+}
+
+// exits if out_path refers to the same file as one of the inputs, since creating the output would truncate that input
+fn check_output_not_input(out_path: &str, inputs: &[&str]) {
+	// if the output does not exist yet it can not be any of the inputs
+	let out_canonical = match fs::canonicalize(out_path) {
+		Ok(path) => path,
+		Err(_) => return,
+	};
+
+	for input in inputs {
+		if fs::canonicalize(input).is_ok_and(|path| path == out_canonical) {
+			error!("Output file {} is the same file as input {}, refusing to overwrite it", out_path, input);
+			exit(1);
+		}
+	}
+}
+
+// returns the entry in base with the same name and type, if the file at path
+// has not been modified since base was written
+fn reuse_base_entry<'a>(base: &parse::Initrd, base_time: SystemTime, typ: EntryType, path: &str, name: &'a str) -> Option<Entry<'a>> {
+	// required entries may have been stored without a name by --omit-special-names
+	let base_entry = base.entries.iter()
+		.filter(|entry| entry.typ == typ as u64)
+		.find(|entry| entry.name == name || (typ.is_required() && entry.name.is_empty()))?;
+
+	// if the file can not be read it is left for Entry::new to report
+	let file_metadata = metadata(path).ok()?;
+	if file_metadata.modified().ok()? >= base_time {
+		return None;
 	}
 
-	fn name_bytes(&self) -> &[u8] {
-		self.name.as_bytes()
+	let (uid, gid) = metadata_owner(&file_metadata);
+
+	Some(Entry {
+		typ,
+		name,
+		data: compress::entry_data(base_entry),
+		uid,
+		gid,
+		align: ALIGN,
+		source: None,
+		tags: Vec::new(),
+		compressed: None,
+	})
+}
+
+// parses the TYPE=N value of --entry-limit-per-type
+fn parse_type_limit(arg: &str) -> (EntryType, usize) {
+	let parsed = arg.split_once('=')
+		.and_then(|(typ, limit)| Some((EntryType::from_name(typ)?, limit.parse::<usize>().ok()?)));
+
+	match parsed {
+		Some(parsed) => parsed,
+		None => {
+			error!("Invalid value for --entry-limit-per-type: expected TYPE=N where TYPE is an entry type, got {}", arg);
+			exit(1);
+		},
 	}
+}
 
-	fn data_bytes(&self) -> &[u8] {
-		&self.data[..]
+// exits if there are more entries of a type than its limit, types without a limit can have any number of entries
+fn check_type_limits(entries: &[Entry], limits: &[(EntryType, usize)]) {
+	for (typ, limit) in limits.iter() {
+		let count = entries.iter().filter(|entry| entry.typ == *typ).count();
+		if count > *limit {
+			error!("There are {} {} entries, which is more than --entry-limit-per-type {}={}", count, typ.name(), typ.name(), limit);
+			exit(exit_code::VALIDATION_FAILED);
+		}
 	}
+}
 
-	// does not set name and data offset
-	fn as_raw(&self) -> EntryRaw {
-		EntryRaw {
-			typ: self.typ as u64,
-			name: 0,
-			name_len: self.name.as_bytes().len() as u64,
-			data: 0,
-			data_len: self.data.len() as u64,
+// warns about required entries with no data, usually left by a failed build, or exits if fail is set,
+// empty entries of other types can be used as markers so they are allowed
+fn check_empty_entries(entries: &[Entry], fail: bool) {
+	for entry in entries.iter().filter(|entry| entry.typ.is_required() && entry.data.is_empty()) {
+		if fail {
+			error!("{} entry {} is empty", entry.typ.name(), entry.name);
+			exit(exit_code::VALIDATION_FAILED);
 		}
+
+		warning!("{} entry {} is empty, it was probably left by a failed build", entry.typ.name(), entry.name);
+	}
+}
+
+// writes original, the data of an elf entry before it was preprocessed, to a debug file in dir, and adds a
+// .gnu_debuglink section referring to it to the entry, so a debugger can find the debug info a preprocessor removed
+fn add_debug_link(entry: &mut Entry, original: &[u8], dir: &str) {
+	if !elf::is_elf(original) {
+		return;
+	}
+
+	let file_name = Path::new(entry.name).file_name()
+		.and_then(|name| name.to_str())
+		.unwrap_or(entry.name);
+	let debug_name = format!("{}.debug", file_name);
+	// dir and debug_name are both utf-8, so the path is too
+	let debug_path = Path::new(dir).join(&debug_name).to_string_lossy().into_owned();
+
+	if let Err(err) = interrupt::write(&debug_path, original) {
+		error!("Could not write debug file {}: {}", debug_path, err);
+		exit(exit_code::WRITE_FAILED);
+	}
+
+	match elf::embed(&entry.data, ".gnu_debuglink", &elf::debug_link(&debug_name, original)) {
+		Ok(data) => entry.data = data,
+		Err(err) => {
+			error!("Could not add debug link to entry {}: {}", entry.name, err);
+			exit(1);
+		},
 	}
 }
 
-#[repr(C)]
-#[derive(Debug)]
-struct EntryRaw {
-	typ: u64,
-	name: u64,
-	name_len: u64,
-	data: u64,
-	data_len: u64,
+// exits if the names of entries, each padded to name_align as they are stored, take more than max bytes,
+// since a loader may copy every name into a fixed size buffer
+fn check_name_budget(entries: &[Entry], max: u64, name_align: u64) {
+	let stored_len = |entry: &Entry| align_up(entry.name.len() as u64, name_align);
+	let total: u64 = entries.iter().map(stored_len).sum();
+	if total <= max {
+		return;
+	}
+
+	let mut largest: Vec<&Entry> = entries.iter().collect();
+	largest.sort_by_key(|entry| cmp::Reverse(stored_len(entry)));
+	let largest: Vec<String> = largest.iter()
+		.take(3)
+		.map(|entry| format!("{} ({} bytes)", entry.name, stored_len(entry)))
+		.collect();
+
+	error!("Entry names take {} bytes, which is more than --max-total-name-bytes {}, the largest are {}", total, max, largest.join(", "));
+	exit(exit_code::VALIDATION_FAILED);
 }
 
-impl EntryRaw {
-	fn as_bytes(&self) -> &[u8] {
-		unsafe {
-			let ptr = self as *const _ as *const u8;
-			std::slice::from_raw_parts(ptr, std::mem::size_of::<Self> ())
+// exits if an entry name has a byte which is not ascii
+fn check_ascii_names(entries: &[Entry]) {
+	for entry in entries.iter() {
+		if let Some((i, byte)) = entry.name.bytes().enumerate().find(|(_, byte)| !byte.is_ascii()) {
+			error!("Entry {} has non ascii byte {:#04x} at position {} of its name", entry.name, byte, i);
+			exit(exit_code::VALIDATION_FAILED);
 		}
 	}
 }
 
-fn align_up(n: u64, align: u64) -> u64 {
-	(n + align - 1) & !(align - 1)
+// exits if two entries have different names which are the same when case is ignored,
+// since a loader on a case insensitive filesystem could not tell them apart
+fn check_case_collisions(entries: &[Entry]) {
+	let mut folded: Vec<(String, &str)> = Vec::new();
+
+	for entry in entries.iter().filter(|entry| !entry.name.is_empty()) {
+		let lower = entry.name.to_lowercase();
+
+		if let Some((_, other)) = folded.iter().find(|(other_lower, other)| *other_lower == lower && *other != entry.name) {
+			error!("Entries {} and {} have names which only differ in case", other, entry.name);
+			exit(exit_code::VALIDATION_FAILED);
+		}
+
+		folded.push((lower, entry.name));
+	}
 }
 
-fn align_to(vec: &mut Vec<u8>, align: u64) {
-	let len = vec.len() as u64;
-	let aligned_len = align_up(len, align);
+// converts backslashes to forward slashes and removes empty and . components, so names built on any host
+// match the paths the kernel looks up
+fn normalize_name(name: &str) -> String {
+	let name = name.replace('\\', "/");
+	let components: Vec<&str> = name.split('/')
+		.filter(|component| !component.is_empty() && *component != ".")
+		.collect();
 
-	for _ in 0..(aligned_len - len) {
-		vec.push(0);
+	let normalized = components.join("/");
+	if name.starts_with('/') {
+		format!("/{}", normalized)
+	} else if normalized.is_empty() && !name.is_empty() {
+		".".to_owned()
+	} else {
+		normalized
 	}
 }
 
-fn to_initrd(entries: &Vec<Entry>) -> Vec<u8> {
-	// current offset of data in file
-	let mut offset = (std::mem::size_of::<Header> () + std::mem::size_of::<EntryRaw> () * entries.len ()) as u64;
+// renames entries using the source-name=image-name lines of the rename map,
+// names in the map which are not entries are an error if strict is set and a warning otherwise
+fn apply_rename_map<'a>(entries: &mut [Entry<'a>], map_path: &str, map_text: &'a str, strict: bool) {
+	let mut renames: Vec<(&str, &str)> = Vec::new();
 
-	let mut out = Vec::new();
+	for (i, line) in map_text.lines().enumerate() {
+		let line = line.trim();
+		if line.is_empty() || line.starts_with('#') {
+			continue;
+		}
 
-	let header = Header::new(entries.len() as u64);
-	out.extend_from_slice(header.as_bytes());
+		let (source, target) = match line.split_once('=') {
+			Some((source, target)) if !source.is_empty() && !target.is_empty() => (source, target),
+			_ => {
+				error!("{}:{}: expected source-name=image-name", map_path, i + 1);
+				exit(1);
+			},
+		};
 
-	for entry in entries.iter() {
-		let mut raw_entry = entry.as_raw();
+		if renames.iter().any(|(other, _)| *other == source) {
+			error!("{}:{}: {} is renamed more than once", map_path, i + 1, source);
+			exit(1);
+		}
 
-		raw_entry.name = offset;
-		offset += align_up(raw_entry.name_len, ALIGN);
+		if !entries.iter().any(|entry| entry.name == source) {
+			if strict {
+				error!("{}:{}: there is no entry named {}", map_path, i + 1, source);
+				exit(1);
+			}
+			warning!("{}:{}: there is no entry named {}", map_path, i + 1, source);
+		}
 
-		raw_entry.data = offset;
-		offset += align_up(raw_entry.data_len, ALIGN);
+		renames.push((source, target));
+	}
 
-		out.extend_from_slice(raw_entry.as_bytes());
+	for entry in entries.iter_mut() {
+		if let Some((_, target)) = renames.iter().find(|(source, _)| *source == entry.name) {
+			entry.name = target;
+		}
 	}
+}
 
-	for entry in entries.iter() {
-		align_to(&mut out, ALIGN);
-		out.extend_from_slice(entry.name_bytes());
+// removes every additional entry with the same name as another one, keeping the last one given if keep_last is set
+// or else the first, required entries are found by their type so they are never removed,
+// every removed entry is printed if verbose is set
+fn dedupe_names(entries: &mut Vec<Entry>, keep_last: bool, verbose: bool) {
+	let others = &entries[REQUIRED_ENTRIES..];
+	let keep: Vec<bool> = others.iter().enumerate().map(|(i, entry)| {
+		let overriding = if keep_last { &others[i + 1..] } else { &others[..i] };
+		!overriding.iter().any(|other| other.name == entry.name)
+	}).collect();
 
-		align_to(&mut out, ALIGN);
-		out.extend_from_slice(entry.data_bytes());
+	if verbose {
+		for (entry, _) in others.iter().zip(keep.iter()).filter(|(_, keep)| !**keep) {
+			match entry.source {
+				Some(source) => eprintln!("entry {} from {} dropped, another entry has the same name", entry.name, source),
+				None => eprintln!("entry {} dropped, another entry has the same name", entry.name),
+			}
+		}
 	}
 
-	out
+	let mut keep = iter::repeat_n(true, REQUIRED_ENTRIES).chain(keep);
+	entries.retain(|_| keep.next().unwrap());
+}
+
+// sorts the additional entries so the ones named in the order file come first, in the order they are listed,
+// followed by the rest sorted by name, the required entries always stay at the start
+fn apply_entry_order(entries: &mut [Entry], order_path: &str, order_text: &str) {
+	let mut order = Vec::new();
+
+	for (i, line) in order_text.lines().enumerate() {
+		let name = line.trim();
+		if name.is_empty() || name.starts_with('#') {
+			continue;
+		}
+
+		if !entries.iter().any(|entry| entry.name == name) {
+			error!("{}:{}: there is no entry named {}", order_path, i + 1, name);
+			exit(1);
+		}
+
+		order.push(name);
+	}
+
+	let position = |entry: &Entry| order.iter().position(|name| *name == entry.name);
+
+	entries[REQUIRED_ENTRIES..].sort_by(|a, b| match (position(a), position(b)) {
+		(Some(a), Some(b)) => a.cmp(&b),
+		(Some(_), None) => cmp::Ordering::Less,
+		(None, Some(_)) => cmp::Ordering::Greater,
+		(None, None) => a.name.cmp(b.name),
+	});
+}
+
+// parses the NAME=N value of --fd
+fn parse_fd_arg(arg: &str) -> (&str, i32) {
+	let parsed = arg.rsplit_once('=')
+		.and_then(|(name, fd)| Some((name, fd.parse::<i32>().ok()?)))
+		.filter(|(name, fd)| !name.is_empty() && *fd >= 0);
+
+	match parsed {
+		Some(parsed) => parsed,
+		None => {
+			error!("Invalid value for --fd: expected NAME=N where N is a file descriptor, got {}", arg);
+			exit(1);
+		},
+	}
+}
+
+// reads everything from an inherited file descriptor until eof, taking ownership of it
+#[cfg(unix)]
+fn read_fd(fd: i32) -> Vec<u8> {
+	use std::fs::File;
+	use std::io::Read;
+	use std::os::unix::io::FromRawFd;
+
+	// the caller makes sure each fd is only read once, so nothing else owns it
+	let mut file = unsafe { File::from_raw_fd(fd) };
+	let mut data = Vec::new();
+
+	if let Err(err) = file.read_to_end(&mut data) {
+		error!("Could not read from file descriptor {}: {}", fd, err);
+		exit(exit_code::MISSING_INPUT);
+	}
+
+	data
+}
+
+#[cfg(not(unix))]
+fn read_fd(_fd: i32) -> Vec<u8> {
+	error!("--fd is only supported on unix platforms");
+	exit(1);
+}
+
+// parses a size in bytes with an optional K, M, or G suffix for powers of 1024
+fn parse_size(arg: &str, size: &str) -> u64 {
+	let (digits, multiplier) = match size.char_indices().last() {
+		Some((i, 'K')) | Some((i, 'k')) => (&size[..i], 1 << 10),
+		Some((i, 'M')) | Some((i, 'm')) => (&size[..i], 1 << 20),
+		Some((i, 'G')) | Some((i, 'g')) => (&size[..i], 1 << 30),
+		_ => (size, 1),
+	};
+
+	match digits.parse::<u64>().ok().and_then(|n| n.checked_mul(multiplier)) {
+		Some(size) => size,
+		None => {
+			error!("Invalid value for {}: {} is not a size in bytes", arg, size);
+			exit(1);
+		},
+	}
+}
+
+// returns the path given for a required entry on the command line, or else in the manifest
+fn required_path<'a>(arg: Option<&'a str>, from_manifest: Option<&'a str>, name: &str) -> &'a str {
+	match arg.or(from_manifest) {
+		Some(path) => path,
+		None => {
+			error!("No {} given on the command line or in the manifest", name);
+			exit(1);
+		},
+	}
+}
+
+fn parse_id(arg: &str, id: &str) -> u32 {
+	match id.parse() {
+		Ok(id) => id,
+		Err(_) => {
+			error!("Invalid value for {}: {} is not a numeric id", arg, id);
+			exit(1);
+		},
+	}
+}
+
+// parses an octal file mode like 0644
+fn parse_mode(mode: &str) -> u32 {
+	match u32::from_str_radix(mode, 8) {
+		Ok(mode) if mode <= 0o7777 => mode,
+		_ => {
+			error!("Invalid value for --output-mode: {} is not an octal file mode", mode);
+			exit(1);
+		},
+	}
+}
+
+#[cfg(unix)]
+fn set_output_mode(path: &str, mode: u32) {
+	use std::os::unix::fs::PermissionsExt;
+
+	if let Err(err) = fs::set_permissions(path, fs::Permissions::from_mode(mode)) {
+		error!("Could not set permissions of output file {}: {}", path, err);
+		exit(exit_code::WRITE_FAILED);
+	}
+}
+
+#[cfg(not(unix))]
+fn set_output_mode(_path: &str, _mode: u32) {
+	warning!("--output-mode is not supported on this platform, ignoring it");
+}
+
+// parses --newer-than, which is either a number of seconds since the unix epoch or a file whose modified time is used
+fn parse_newer_than(arg: &str) -> SystemTime {
+	match arg.parse::<u64>() {
+		Ok(secs) => UNIX_EPOCH + Duration::from_secs(secs),
+		Err(_) => get_file_modify_time(arg),
+	}
+}
+
+// path an input file is read from, which is under sysroot if it is given, even if path is absolute
+fn in_sysroot(sysroot: Option<&str>, path: &str) -> String {
+	match sysroot {
+		Some(sysroot) => format!("{}/{}", sysroot.trim_end_matches('/'), path.trim_start_matches('/')),
+		None => path.to_owned(),
+	}
 }
 
 fn get_file_modify_time(path: &str) -> SystemTime {
 	match metadata(path) {
 		Ok(metadata) => metadata.modified().expect("platform does not support file modified time"),
 		Err(err) => {
-			eprintln!("Could not read from file {}: {}", path, err);
-			exit(1);
+			error!("Could not read from file {}: {}", path, err);
+			exit(exit_code::MISSING_INPUT);
 		},
  	}
 }
 
-fn main() {
-	let matches = clap_app!(("gen-initrd") =>
-		(version: "0.1.0")
-		(about: "Simple utility to generate initrd image for the aurora kernel")
-		(@arg ("check-newer"): -n "Check if any files to be included in initrd are newer than the output initrd image, if they are not do not build initrd")
-		(@arg ("early-init"): -i --init <EXECUTABLE> "First executable spawned by kernel which is responsible for mounting the root filesytem and spawning the init process")
-		(@arg ("part-list"): -p --("part-list") <FILE> "File read by early-init which describes which filesytem drivers to use for which partitions and where to mount them")
-		(@arg ("fs-server"): -f --fs <EXECUTABLE> "Filesystem serveri binary")
-		(@arg ("hwaccess-server"): -a --hwaccess <EXECUTABLE> "Hwacess server which drivers will use to interface with hardware")
-		(@arg out: -o <FILE> "Output file to save initrd to")
-		(@arg files: [FILE] ... "additional files to include in initrd")
-	).get_matches();
+// reads an entry from path, retrying errors other than the file not existing or not being accessible
+// up to retries times with a backoff, since network filesystems can fail transiently
+fn read_entry<'a>(typ: EntryType, path: &str, name: &'a str, retries: u32) -> Entry<'a> {
+	let mut attempts = 1;
+
+	loop {
+		match Entry::new(typ, path, name) {
+			Ok(entry) => return entry,
+			Err(err) if attempts > retries || matches!(err.kind(), io::ErrorKind::NotFound | io::ErrorKind::PermissionDenied | io::ErrorKind::IsADirectory) => {
+				if attempts > 1 {
+					error!("Could not read from file {} after {} attempts: {}", path, attempts, err);
+				} else {
+					error!("Could not read from file {}: {}", path, err);
+				}
+				exit(exit_code::MISSING_INPUT);
+			},
+			Err(_) => {
+				thread::sleep(Duration::from_millis(100 << cmp::min(attempts - 1, 5)));
+				attempts += 1;
+			},
+		}
+	}
+}
 
-	let early_init = matches.value_of("early-init").unwrap();
-	let part_list = matches.value_of("part-list").unwrap();
-	let fs_server = matches.value_of("fs-server").unwrap();
-	let hwaccess_server = matches.value_of("hwaccess-server").unwrap();
-	let other_files = matches.values_of("files");
+fn read_file(path: &str) -> Vec<u8> {
+	match fs::read(path) {
+		Ok(bytes) => bytes,
+		Err(err) => {
+			error!("Could not read from file {}: {}", path, err);
+			exit(exit_code::MISSING_INPUT);
+		},
+	}
+}
 
-	let out_path = matches.value_of("out").unwrap();
+// reads the list of additional files from the environment variable var
+fn read_files_env(var: &str) -> String {
+	match env::var(var) {
+		Ok(files) => files,
+		Err(env::VarError::NotPresent) => {
+			error!("Environment variable {} passed to --files-env is not set", var);
+			exit(1);
+		},
+		Err(env::VarError::NotUnicode(_)) => {
+			error!("Environment variable {} passed to --files-env is not valid utf-8", var);
+			exit(1);
+		},
+	}
+}
 
-	if matches.is_present("check-newer") {
-		if let Ok(initrd_metadata) = metadata(out_path) {
-			let initrd_time = initrd_metadata.modified().unwrap();
+fn read_text_file(path: &str) -> String {
+	match fs::read_to_string(path) {
+		Ok(text) => text,
+		Err(err) => {
+			error!("Could not read from file {}: {}", path, err);
+			exit(exit_code::MISSING_INPUT);
+		},
+	}
+}
 
-			let early_init_time = get_file_modify_time(early_init);
-			let fs_server_time = get_file_modify_time(fs_server);
-			let hwaccess_server_time = get_file_modify_time(hwaccess_server);
-			let part_list_time = get_file_modify_time(part_list);
+// reads every entry of an image in the stream format
+fn read_stream_or_exit(path: &str, bytes: &[u8]) -> Vec<StreamEntry> {
+	let entries = StreamReader::new(bytes).and_then(|reader| reader.collect::<Result<Vec<_>, _>>());
 
-			let mut latest_time = cmp::max(
-				cmp::max(early_init_time, fs_server_time),
-				cmp::max(part_list_time, hwaccess_server_time),
-			);
+	match entries {
+		Ok(entries) => entries,
+		Err(err) => {
+			error!("{}: invalid stream initrd: {}", path, err);
+			exit(exit_code::MALFORMED_IMAGE);
+		},
+	}
+}
 
-			if let Some(other_files) = other_files.clone() {
-				for file in other_files {
-					latest_time = cmp::max(latest_time, get_file_modify_time(file));
-				}
-			}
+// prints the first lines of verify, describing the header of initrd, which has entry_count entries
+fn print_summary(path: &str, initrd: &parse::Initrd, entry_count: usize, is_stream: bool) {
+	let mut features: Vec<String> = flag_names(initrd.flags).into_iter().map(str::to_owned).collect();
+	if is_stream {
+		features.push("stream".to_owned());
+	}
+	// the parser only accepts unknown flags which readers can ignore
+	let unknown_optional = initrd.flags & !KNOWN_FLAGS;
+	if unknown_optional != 0 {
+		features.push(format!("unknown optional {:#x}", unknown_optional));
+	}
+	let features = if features.is_empty() { "none".to_owned() } else { features.join(", ") };
 
-			if initrd_time > latest_time {
-				eprintln!("Skipping initrd generation, no files have changed");
-				exit(0);
-			}
-		}
+	println!("{}: {}, {} entries, features: {}", path, color::ok("ok"), entry_count, features);
+	if initrd.flags & FLAG_BASE_OFFSET != 0 {
+		println!("\tbase offset: {:#x}", initrd.base_offset);
+	}
+	if initrd.flags & FLAG_DMA_ALIGN != 0 {
+		println!("\tdma alignment: {}", initrd.dma_align);
+	}
+	if initrd.flags & FLAG_ALIGNMENTS != 0 {
+		println!("\tname alignment: {}, data alignment: {}", initrd.name_align, initrd.data_align);
+	}
+	if let Some(tree) = initrd.merkle {
+		println!("\tmerkle tree: {} blocks of {} bytes, root {}", tree.block_count(), tree.block_size, tree.root);
 	}
+	if let Some(header) = initrd.checksum {
+		let algorithm = ChecksumAlgorithm::from_u64(header.algorithm).map_or_else(|| header.algorithm.to_string(), |algorithm| algorithm.name().to_owned());
+		println!("\tchecksum: {} {}", algorithm, header.to_hex());
+	}
+}
 
-	// check if file any files are newer than the initrd, don't create it if they are not
+// describes an entry with len bytes of data owned by ownership, the way verify lists entries
+fn describe_entry(len: u64, stored_len: u64, compression: Option<Compression>, ownership: Option<EntryOwnership>) -> String {
+	let mut description = format!("{} bytes", len);
 
-	let mk_entry = |typ, path| {
-		match Entry::new(typ, path) {
-			Ok(entry) => entry,
-			Err(err) => {
-				eprintln!("Could not read from file {}: {}", path, err);
-				exit(1);
-			},
-		}
+	if let Some(compression) = compression {
+		description += &format!(", compressed with {} to {} bytes", compression.name(), stored_len);
+	}
+
+	if let Some(ownership) = ownership {
+		description += &format!(", owned by {}:{}", ownership.uid, ownership.gid);
+	}
+
+	description
+}
+
+// verify --streaming, which reads the image a piece at a time with reader::read instead of loading it into memory
+fn verify_streaming(path: &str, filter: &EntryFilter) {
+	let file = match File::open(path) {
+		Ok(file) => file,
+		Err(err) => {
+			error!("Could not read from file {}: {}", path, err);
+			exit(exit_code::MISSING_INPUT);
+		},
 	};
 
-	let mut entries = vec![
-		mk_entry(EntryType::EarlyInit, early_init),
-		mk_entry(EntryType::PartList, part_list),
-		mk_entry(EntryType::FsSever, fs_server),
-		mk_entry(EntryType::HwAccessServer, hwaccess_server),
-	];
+	let initrd = match reader::read(&mut BufReader::new(file)) {
+		Ok(initrd) => initrd,
+		Err(reader::ReadError::Io(err)) => {
+			error!("Could not read from file {}: {}", path, err);
+			exit(exit_code::MISSING_INPUT);
+		},
+		Err(err) => {
+			error!("{}: invalid initrd: {}", path, err);
+			exit(exit_code::MALFORMED_IMAGE);
+		},
+	};
 
-	if let Some(files) = other_files {
-		for file in files {
-			entries.push(mk_entry(EntryType::Any, file));
-		}
+	let regions: Vec<(u64, u64)> = initrd.entries.iter().map(|entry| (entry.data_offset, entry.data_len)).collect();
+	check_data_overlaps(path, &regions, |i| (initrd.entries[i].typ, &initrd.entries[i].name));
+
+	let layout = initrd.layout;
+	let header = parse::Initrd {
+		flags: layout.flags,
+		base_offset: layout.base_offset,
+		unknown_header_len: layout.unknown_header_len,
+		dma_align: layout.dma_align,
+		merkle: layout.merkle,
+		name_align: layout.name_align,
+		data_align: layout.data_align,
+		checksum: layout.checksum,
+		entries: Vec::new(),
+	};
+	print_summary(path, &header, initrd.entries.len(), false);
+
+	for entry in initrd.entries.iter().filter(|entry| filter.includes_type_len(entry.typ, entry.len)) {
+		println!("\t{} {}: {}", type_name(entry.typ), entry.name, describe_entry(entry.len, entry.data_len, entry.compression, entry.ownership));
 	}
+}
 
-	let mut out_file = match File::create(out_path)
-	{
-		Ok(file) => file,
-		Err(_) => {
-			eprintln!("Could not create output file {}", out_path);
-			exit(1);
+fn verify(matches: &ArgMatches) {
+	let path = matches.value_of("image").unwrap();
+	let filter = EntryFilter::new(matches);
+
+	if matches.is_present("streaming") {
+		return verify_streaming(path, &filter);
+	}
+
+	let bytes = read_file(path);
+
+	// entries of a stream image are read into memory, and shown the same way as entries of the table format
+	let is_stream = stream::is_stream(&bytes);
+	let streamed = if is_stream { read_stream_or_exit(path, &bytes) } else { Vec::new() };
+	let initrd = if is_stream {
+		parse::Initrd {
+			flags: 0,
+			base_offset: 0,
+			unknown_header_len: 0,
+			dma_align: 0,
+			merkle: None,
+			name_align: ALIGN,
+			data_align: ALIGN,
+			checksum: None,
+			entries: streamed.iter().map(StreamEntry::as_parsed).collect(),
 		}
+	} else {
+		parse_or_exit(path, &bytes)
 	};
 
-	let initrd_vec = to_initrd(&entries);
-	if let Err(_) = out_file.write_all(&initrd_vec[..])
-	{
-		eprintln!("Could not write initrd to output file {}", out_path);
-		exit(1);
+	if let Some(tree) = initrd.merkle {
+		if let Err(err) = merkle::check(&bytes, &tree) {
+			error!("{}: invalid initrd: {}", path, err);
+			exit(exit_code::MALFORMED_IMAGE);
+		}
+	}
+
+	match initrd.checksum.map(|header| checksum::check(&bytes, &header)) {
+		Some(Err(err @ ChecksumError::UnknownAlgorithm(_))) => warning!("{}: {}, it is not checked", path, err),
+		Some(Err(err)) => {
+			error!("{}: invalid initrd: {}", path, err);
+			exit(exit_code::MALFORMED_IMAGE);
+		},
+		_ => (),
+	}
+
+	// entries of a stream image are copied out of it, and can not overlap since each follows the previous one
+	if !is_stream {
+		let regions: Vec<(u64, u64)> = initrd.entries.iter()
+			.map(|entry| (entry.data.as_ptr() as u64 - bytes.as_ptr() as u64, entry.data.len() as u64))
+			.collect();
+		check_data_overlaps(path, &regions, |i| (initrd.entries[i].typ, initrd.entries[i].name));
+	}
+
+	print_summary(path, &initrd, initrd.entries.len(), is_stream);
+
+	let entries = initrd.entries.iter().filter(|entry| filter.includes(entry));
+
+	for comment in entries.clone().filter(|entry| entry.typ == EntryType::Comment as u64) {
+		println!("\tcomment: {}", String::from_utf8_lossy(&compress::entry_data(comment)));
+	}
+
+	for init_args in entries.clone().filter(|entry| entry.typ == EntryType::InitArgs as u64) {
+		println!("\tinit args: {}", String::from_utf8_lossy(&compress::entry_data(init_args)));
+	}
+
+	if matches.is_present("print-tree") {
+		return tree::print(entries);
+	}
+
+	// source paths and tags are keyed by the index of the entry in the whole image, so indexes are taken before filtering
+	let entry_data = |typ: EntryType| initrd.entries.iter()
+		.find(|entry| entry.typ == typ as u64)
+		.map_or(Vec::new(), compress::entry_data);
+	let source_data = entry_data(EntryType::SourcePaths);
+	let source_paths = source_paths::decode(&source_data);
+	let tag_data = entry_data(EntryType::Tags);
+	let tags = tags::decode(&tag_data);
+
+	for (i, entry) in initrd.entries.iter().enumerate().filter(|(_, entry)| filter.includes(entry)) {
+		let mut description = describe_entry(entry.len, entry.data.len() as u64, entry.compression, entry.ownership);
+
+		// content which is not recognized is only described by its size
+		if matches.is_present("decode") {
+			if let Some(elf) = elf::describe(&compress::entry_data(entry)) {
+				description += &format!(", {}", elf);
+			}
+
+			if let Some((_, path)) = source_paths.iter().find(|(index, _)| *index == i) {
+				description += &format!(", read from {}", path);
+			}
+
+			let entry_tags: Vec<String> = tags.iter()
+				.filter(|(index, _, _)| *index == i)
+				.map(|(_, key, value)| format!("{}={}", key, value))
+				.collect();
+			if !entry_tags.is_empty() {
+				description += &format!(", tagged {}", entry_tags.join(" "));
+			}
+		}
+
+		println!("\t{} {}: {}", type_name(entry.typ), entry.name, description);
+	}
+}
+
+// entry types read from a comma separated list, types this version does not know can be given as numbers
+fn parse_type_list(arg: &str, list: &str) -> Vec<u64> {
+	list.split(',').map(|name| match EntryType::from_name(name) {
+		Some(typ) => typ as u64,
+		None => match name.parse::<u64>() {
+			Ok(typ) => typ,
+			Err(_) => {
+				error!("Invalid value for {}: unknown entry type {}", arg, name);
+				exit(1);
+			},
+		},
+	}).collect()
+}
+
+// which entries a command operates on, from --only-types, --exclude-types, --min-size, and --max-size
+struct EntryFilter {
+	only: Option<Vec<u64>>,
+	exclude: Vec<u64>,
+	// inclusive range of data lengths, including trimmed trailing zeros
+	min_size: u64,
+	max_size: u64,
+}
+
+impl EntryFilter {
+	fn new(matches: &ArgMatches) -> Self {
+		let min_size = matches.value_of("min-size").map_or(0, |size| parse_size("--min-size", size));
+		let max_size = matches.value_of("max-size").map_or(u64::MAX, |size| parse_size("--max-size", size));
+		if min_size > max_size {
+			error!("Invalid value for --min-size: {} is larger than --max-size {}", min_size, max_size);
+			exit(1);
+		}
+
+		EntryFilter {
+			only: matches.value_of("only-types").map(|list| parse_type_list("--only-types", list)),
+			exclude: matches.value_of("exclude-types").map_or(Vec::new(), |list| parse_type_list("--exclude-types", list)),
+			min_size,
+			max_size,
+		}
+	}
+
+	fn includes(&self, entry: &parse::ParsedEntry) -> bool {
+		self.includes_type_len(entry.typ, entry.len)
+	}
+
+	// for entries which are not read into a ParsedEntry, such as by verify --streaming
+	fn includes_type_len(&self, typ: u64, len: u64) -> bool {
+		self.only.as_ref().is_none_or(|only| only.contains(&typ)) && !self.exclude.contains(&typ)
+			&& (self.min_size..=self.max_size).contains(&len)
+	}
+}
+
+// exits if the file at path is not exactly expected, or is not a valid initrd if is_image is set
+fn verify_written(path: &str, expected: &[u8], is_image: bool) {
+	let written = read_file(path);
+
+	if is_image {
+		if let Err(err) = parse::parse(&written) {
+			error!("Verification of output file {} failed: invalid initrd: {}", path, err);
+			exit(exit_code::WRITE_FAILED);
+		}
+	}
+
+	if written != expected {
+		error!("Verification of output file {} failed: contents on disk differ from the generated initrd", path);
+		exit(exit_code::WRITE_FAILED);
+	}
+}
+
+// exits if the data of two entries overlaps, which the encoder never does, entries with the same offset and
+// length are allowed since an image can store identical data once for several entries, regions holds the offset
+// and length of the data of every entry, and entry gives the type and name of an entry by its index
+fn check_data_overlaps<'a>(path: &str, regions: &[(u64, u64)], entry: impl Fn(usize) -> (u64, &'a str)) {
+	let mut regions: Vec<(u64, u64, usize)> = regions.iter().enumerate()
+		.filter(|(_, (_, len))| *len != 0)
+		.map(|(i, (offset, len))| (*offset, offset + len, i))
+		.collect();
+	regions.sort_unstable();
+
+	let describe = |i: usize| match entry(i) {
+		(typ, "") => format!("entry {} ({})", i, type_name(typ)),
+		(_, name) => format!("entry {} ({})", i, name),
+	};
+
+	let mut overlapping = false;
+	// the region ending last so far, which every later region overlapping an earlier one also overlaps
+	let mut furthest: Option<(u64, u64, usize)> = None;
+
+	for &(start, end, i) in regions.iter() {
+		if let Some((other_start, other_end, other)) = furthest {
+			if start < other_end && (start, end) != (other_start, other_end) {
+				error!("{}: invalid initrd: data of {} at {:#x}..{:#x} overlaps data of {} at {:#x}..{:#x}",
+					path, describe(i), start, end, describe(other), other_start, other_end);
+				overlapping = true;
+			}
+		}
+
+		if furthest.is_none_or(|(_, other_end, _)| end > other_end) {
+			furthest = Some((start, end, i));
+		}
+	}
+
+	if overlapping {
+		exit(exit_code::MALFORMED_IMAGE);
+	}
+}
+
+// exits if an elf entry has a load segment that would be misaligned if mapped directly from the initrd
+fn check_elf_alignment(initrd: &[u8]) {
+	let parsed = match parse::parse(initrd) {
+		Ok(parsed) => parsed,
+		Err(err) => {
+			error!("Generated initrd is invalid: {}", err);
+			exit(1);
+		},
+	};
+
+	let mut misaligned = false;
+
+	for entry in parsed.entries.iter().filter(|entry| elf::is_elf(entry.data)) {
+		let offset = entry.data.as_ptr() as u64 - initrd.as_ptr() as u64;
+
+		match elf::max_load_align(entry.data) {
+			Some(align) if !offset.is_multiple_of(align) => {
+				error!("Entry {} is at offset {:#x} in the initrd, but has a load segment requiring {:#x} byte alignment", entry.name, offset, align);
+				misaligned = true;
+			},
+			Some(_) => (),
+			None => warning!("entry {} looks like an elf file but its program headers could not be read", entry.name),
+		}
+	}
+
+	if misaligned {
+		exit(exit_code::VALIDATION_FAILED);
+	}
+}
+
+fn parse_or_exit<'a>(path: &str, bytes: &'a [u8]) -> parse::Initrd<'a> {
+	match parse::parse(bytes) {
+		Ok(initrd) => initrd,
+		Err(err) => {
+			error!("{}: invalid initrd: {}", path, err);
+			exit(exit_code::MALFORMED_IMAGE);
+		},
+	}
+}
+
+// returns the entry called name, or the required entry with that type name, exiting if there is none
+fn find_entry<'a, 'b>(initrd: &'b parse::Initrd<'a>, name: &str) -> &'b parse::ParsedEntry<'a> {
+	// required entries can also be found by their type name, since --omit-special-names leaves them unnamed
+	let by_type = EntryType::from_name(name).filter(|typ| *typ != EntryType::Any);
+	let entry = initrd.entries.iter().find(|entry| entry.name == name)
+		.or_else(|| initrd.entries.iter().find(|entry| by_type.is_some_and(|typ| entry.typ == typ as u64)));
+
+	match entry {
+		Some(entry) => entry,
+		None => {
+			error!("There is no entry named {}", name);
+			exit(1);
+		},
+	}
+}
+
+// prints the offset and length of the data of the entry called name in initrd
+fn print_entry_offset(path: &str, initrd: &[u8], name: &str) {
+	let parsed = parse_or_exit(path, initrd);
+	let entry = find_entry(&parsed, name);
+
+	let offset = entry.data.as_ptr() as u64 - initrd.as_ptr() as u64;
+	println!("{}: data at offset {:#x}, {} bytes", name, offset, entry.data.len());
+}
+
+// path of chunk i of an initrd split into chunks written to out_path
+fn chunk_path(out_path: &str, i: usize) -> String {
+	format!("{}.{:03}", out_path, i)
+}
+
+// paths of the chunks of an initrd previously split into chunks of chunk_size bytes written to out_path, or None if
+// they are missing or are not all chunk_size bytes other than the last, so one of them was removed or changed
+fn existing_chunks(out_path: &str, chunk_size: u64) -> Option<Vec<String>> {
+	let lens: Vec<(String, u64)> = (0..)
+		.map(|i| chunk_path(out_path, i))
+		.map_while(|path| metadata(&path).ok().filter(|metadata| metadata.is_file()).map(|metadata| (path, metadata.len())))
+		.collect();
+
+	let (last, full) = lens.split_last()?;
+	let complete = full.iter().all(|(_, len)| *len == chunk_size)
+		&& (1..=chunk_size).contains(&last.1)
+		// a chunk after a missing one means the missing one was removed rather than never written
+		&& !Path::new(&chunk_path(out_path, lens.len() + 1)).exists();
+
+	if complete { Some(lens.into_iter().map(|(path, _)| path).collect()) } else { None }
+}
+
+// writes bytes to numbered chunks of chunk_size bytes, the last of which may be shorter, and returns the path and
+// length of each chunk
+fn write_split(out_path: &str, bytes: &[u8], chunk_size: u64) -> Vec<(String, usize)> {
+	let chunks: Vec<(String, usize)> = bytes.chunks(usize::try_from(chunk_size).unwrap_or(usize::MAX))
+		.enumerate()
+		.map(|(i, chunk)| {
+			let path = chunk_path(out_path, i);
+			write_file(&path, chunk);
+			(path, chunk.len())
+		})
+		.collect();
+
+	// chunks left by a previous larger build would otherwise be joined onto this one, and are removed with their signatures
+	let mut i = chunks.len();
+	while fs::remove_file(chunk_path(out_path, i)).is_ok() {
+		let _ = fs::remove_file(sign::signature_path(&chunk_path(out_path, i)));
+		i += 1;
+	}
+
+	chunks
+}
+
+// concatenates the chunks written by --split back into the initrd
+fn join(matches: &ArgMatches) {
+	let prefix = matches.value_of("prefix").unwrap();
+	let out_path = matches.value_of("out").unwrap();
+
+	let mut bytes = Vec::new();
+	let mut i = 0;
+	loop {
+		let path = chunk_path(prefix, i);
+		match fs::read(&path) {
+			Ok(chunk) => bytes.extend_from_slice(&chunk),
+			Err(err) if err.kind() == io::ErrorKind::NotFound && i != 0 => break,
+			Err(err) => {
+				error!("Could not read from file {}: {}", path, err);
+				exit(exit_code::MISSING_INPUT);
+			},
+		}
+		i += 1;
+	}
+
+	write_file(out_path, &bytes);
+}
+
+// creates the temporary file an output at path is written to before it replaces path
+fn create_output(path: &str) -> interrupt::TempOutput {
+	match interrupt::TempOutput::create(path) {
+		Ok(output) => output,
+		Err(err) => {
+			match output_dir(path) {
+				Some(dir) if !dir.exists() => error!("Could not create output file {}: directory {} does not exist", path, dir.display()),
+				Some(dir) if !dir.is_dir() => error!("Could not create output file {}: {} is not a directory", path, dir.display()),
+				_ => error!("Could not create output file {}: {}", path, err),
+			}
+			exit(exit_code::WRITE_FAILED);
+		},
+	}
+}
+
+// the directory an output at path is written to, if path has one
+fn output_dir(path: &str) -> Option<&Path> {
+	Path::new(path).parent().filter(|dir| !dir.as_os_str().is_empty())
+}
+
+// creates the directory an output at path is written to and every missing directory above it, for --create-dirs
+fn create_output_dir(path: &str) {
+	if let Some(dir) = output_dir(path) {
+		if let Err(err) = fs::create_dir_all(dir) {
+			error!("Could not create directory {} for output file {}: {}", dir.display(), path, err);
+			exit(exit_code::WRITE_FAILED);
+		}
+	}
+}
+
+// writes bytes to the temporary file of output, exiting and leaving path untouched if it fails
+fn write_output(output: &mut interrupt::TempOutput, path: &str, bytes: &[u8]) {
+	if output.file.write_all(bytes).is_err() {
+		output.discard();
+		error!("Could not write initrd to output file {}", path);
+		exit(exit_code::WRITE_FAILED);
+	}
+}
+
+// replaces path with the complete temporary file of output
+fn persist_output(output: interrupt::TempOutput, path: &str) {
+	if let Err(err) = output.persist() {
+		error!("Could not replace output file {}: {}", path, err);
+		exit(exit_code::WRITE_FAILED);
+	}
+}
+
+fn write_file(path: &str, bytes: &[u8]) {
+	let mut output = create_output(path);
+	write_output(&mut output, path, bytes);
+	persist_output(output, path);
+}
+
+// exits if initrd, read from path, has header fields or optional flags from a newer version, which may describe
+// the rest of the image so they can be neither kept nor dropped when writing a new image from it
+fn check_rewritable(path: &str, initrd: &parse::Initrd) {
+	if initrd.unknown_header_len != 0 {
+		error!("{}: header has {} bytes of fields added by a newer version, which would be lost by rewriting it", path, initrd.unknown_header_len);
+		exit(exit_code::MALFORMED_IMAGE);
+	}
+
+	// the parser only accepts unknown flags which readers can ignore, but a writer can not keep what they describe
+	let unknown_optional = initrd.flags & !KNOWN_FLAGS;
+	if unknown_optional != 0 {
+		error!("{}: uses optional flags {:#x} added by a newer version, which would be lost by rewriting it", path, unknown_optional);
+		exit(exit_code::MALFORMED_IMAGE);
+	}
+
+	if let Some(header) = initrd.checksum.filter(|header| ChecksumAlgorithm::from_u64(header.algorithm).is_none()) {
+		error!("{}: checksum uses algorithm {} added by a newer version, which can not be computed for the rewritten image", path, header.algorithm);
+		exit(exit_code::MALFORMED_IMAGE);
+	}
+}
+
+// algorithm of the checksum of an image checked by check_rewritable, if it has one
+fn checksum_algorithm(initrd: &parse::Initrd) -> Option<ChecksumAlgorithm> {
+	initrd.checksum.and_then(|header| ChecksumAlgorithm::from_u64(header.algorithm))
+}
+
+// converts an entry read from the image at path into one that can be written to a new image
+fn owned_entry<'a>(path: &str, entry: &parse::ParsedEntry<'a>) -> Entry<'a> {
+	let typ = match EntryType::from_u64(entry.typ) {
+		Some(typ) => typ,
+		None => {
+			error!("{}: entry {} has unknown type {}", path, entry.name, entry.typ);
+			exit(exit_code::MALFORMED_IMAGE);
+		},
+	};
+
+	let (uid, gid) = entry.ownership.map_or((0, 0), |ownership| (ownership.uid, ownership.gid));
+
+	Entry {
+		typ,
+		name: entry.name,
+		data: compress::entry_data(entry),
+		uid,
+		gid,
+		align: ALIGN,
+		source: None,
+		tags: Vec::new(),
+		// compressed data is kept as it is rather than compressed again
+		compressed: entry.compression.map(|algorithm| (algorithm, entry.data.to_vec())),
+	}
+}
+
+// writes a copy of an image with the data of every entry aligned to a new alignment
+fn realign(matches: &ArgMatches) {
+	let path = matches.value_of("image").unwrap();
+	let out_path = matches.value_of("out").unwrap();
+	check_output_not_input(out_path, &[path]);
+
+	let align = parse_size("--align", matches.value_of("align").unwrap());
+	if !valid_alignment(align) {
+		error!("Invalid value for --align: {} is not a power of two which is at least {}", align, ALIGN);
+		exit(1);
+	}
+
+	let bytes = read_file(path);
+	let initrd = parse_or_exit(path, &bytes);
+	check_rewritable(path, &initrd);
+
+	let entries: Vec<Entry> = initrd.entries.iter()
+		.map(|entry| Entry {
+			align,
+			..owned_entry(path, entry)
+		})
+		.collect();
+
+	let options = InitrdOptions {
+		flags: initrd.flags,
+		base_offset: initrd.base_offset,
+		dma_align: initrd.dma_align,
+		merkle_block_size: initrd.merkle.map_or(0, |tree| tree.block_size),
+		// data alignment is replaced by the alignment of every entry
+		name_align: initrd.name_align,
+		checksum_algorithm: checksum_algorithm(&initrd).unwrap_or(ChecksumAlgorithm::Crc32),
+		..InitrdOptions::default()
+	};
+
+	match to_initrd(&entries, &options) {
+		Ok(realigned) => write_file(out_path, &realigned),
+		Err(err) => {
+			error!("Could not realign initrd: {}", err);
+			exit(1);
+		},
+	}
+}
+
+fn merge(matches: &ArgMatches) {
+	let out_path = matches.value_of("out").unwrap();
+	let input_paths: Vec<&str> = matches.values_of("images").unwrap().collect();
+	check_output_not_input(out_path, &input_paths);
+
+	let inputs: Vec<Vec<u8>> = input_paths.iter().map(|path| read_file(path)).collect();
+
+	let filter = EntryFilter::new(matches);
+
+	let mut flags = 0;
+	let mut dma_align = 0;
+	let mut merkle_block_size = 0;
+	let mut name_align = 1;
+	let mut data_align = ALIGN;
+	// the algorithm of the first image with a checksum
+	let mut merged_checksum = None;
+	let mut entries = Vec::new();
+	// which input each name came from, to detect collisions between inputs
+	let mut name_sources: Vec<(&str, &str)> = Vec::new();
+	// which input each required entry came from
+	let mut required_sources: Vec<(EntryType, &str)> = Vec::new();
+
+	for (path, bytes) in input_paths.iter().zip(inputs.iter()) {
+		let initrd = parse_or_exit(path, bytes);
+
+		check_rewritable(path, &initrd);
+
+		// the merged image is not at the same position in a containing file as its inputs
+		flags |= initrd.flags & !FLAG_BASE_OFFSET;
+		dma_align = cmp::max(dma_align, initrd.dma_align);
+		merkle_block_size = cmp::max(merkle_block_size, initrd.merkle.map_or(0, |tree| tree.block_size));
+		name_align = cmp::max(name_align, initrd.name_align);
+		data_align = cmp::max(data_align, initrd.data_align);
+		merged_checksum = merged_checksum.or_else(|| checksum_algorithm(&initrd));
+
+		// source paths and tags are keyed by the index of entries in their own image, which is not kept by merging
+		let entries_kept = initrd.entries.into_iter()
+			.filter(|entry| filter.includes(entry) && !matches!(EntryType::from_u64(entry.typ), Some(EntryType::SourcePaths | EntryType::Tags)));
+
+		for entry in entries_kept {
+			// every image has the required entries, so they are kept once if every image has the same ones
+			if let Some(typ) = EntryType::from_u64(entry.typ).filter(EntryType::is_required) {
+				if let Some((_, first_path)) = required_sources.iter().find(|(first_typ, _)| *first_typ == typ) {
+					let first = entries.iter().find(|first: &&Entry| first.typ == typ).unwrap();
+					if first.name != entry.name || first.data != compress::entry_data(&entry) {
+						error!("The {} of {} is not the same as the one of {}, merged images must have the same required entries", typ.name(), path, first_path);
+						exit(exit_code::VALIDATION_FAILED);
+					}
+					continue;
+				}
+				required_sources.push((typ, path));
+			}
+
+			if !matches.is_present("allow-duplicates") {
+				if let Some((_, other_path)) = name_sources.iter().find(|(name, other)| *name == entry.name && other != path) {
+					error!("Entry {} is in both {} and {}, pass --allow-duplicates to keep both", entry.name, other_path, path);
+					exit(exit_code::VALIDATION_FAILED);
+				}
+				name_sources.push((entry.name, path));
+			}
+
+			entries.push(owned_entry(path, &entry));
+		}
+	}
+
+	// the merged image keeps the largest dma alignment of its inputs so every entry stays usable by dma,
+	// the largest merkle block size so its tree is no larger than the largest tree of its inputs,
+	// and the largest name and data alignments so every name and data stays as aligned as it was
+	let options = InitrdOptions {
+		flags,
+		dma_align,
+		merkle_block_size,
+		name_align,
+		data_align,
+		checksum_algorithm: merged_checksum.unwrap_or(ChecksumAlgorithm::Crc32),
+		..InitrdOptions::default()
+	};
+
+	match to_initrd(&entries, &options) {
+		Ok(initrd) => write_file(out_path, &initrd),
+		Err(err) => {
+			error!("Could not merge initrds: {}", err);
+			exit(1);
+		},
+	}
+}
+
+// stores initrd in the section called section_name of the elf file at elf_path
+fn embed_in_elf(elf_path: &str, section_name: &str, initrd: &[u8]) {
+	let elf_bytes = read_file(elf_path);
+
+	let embedded = match elf::embed(&elf_bytes, section_name, initrd) {
+		Ok(bytes) => bytes,
+		Err(err) => {
+			error!("Could not embed initrd in elf file {}: {}", elf_path, err);
+			exit(1);
+		},
+	};
+
+	if let Err(err) = interrupt::write(elf_path, embedded) {
+		error!("Could not write elf file {}: {}", elf_path, err);
+		exit(exit_code::WRITE_FAILED);
+	}
+}
+
+fn gen_manifest(matches: &ArgMatches) {
+	let manifest = manifest::from_dir(matches.value_of("dir").unwrap());
+	let text = manifest::to_toml(&manifest);
+
+	match matches.value_of("out") {
+		Some(path) => {
+			if let Err(err) = interrupt::write(path, text) {
+				error!("Could not write manifest to {}: {}", path, err);
+				exit(exit_code::WRITE_FAILED);
+			}
+		},
+		None => print!("{}", text),
+	}
+}
+
+fn sign_image(matches: &ArgMatches) {
+	let path = matches.value_of("image").unwrap();
+	let key = sign::load_signing_key(matches.value_of("key").unwrap());
+
+	let sig_path = sign::signature_path(path);
+	sign::write_signature(&key, &read_file(path), &sig_path);
+}
+
+fn verify_signature(matches: &ArgMatches) {
+	let path = matches.value_of("image").unwrap();
+	let key = sign::load_verifying_key(matches.value_of("pubkey").unwrap());
+
+	let sig_path = matches.value_of("signature")
+		.map(str::to_owned)
+		.unwrap_or_else(|| sign::signature_path(path));
+
+	if sign::check_signature(&key, &read_file(path), &sig_path) {
+		println!("{}: {}", path, color::ok("signature ok"));
+	} else {
+		error!("{}: signature {} is not valid for this image", path, sig_path);
+		exit(exit_code::VALIDATION_FAILED);
+	}
+}
+
+// checks that an image contains exactly the entries described by a manifest
+fn match_manifest(matches: &ArgMatches) {
+	let manifest = manifest::load(matches.value_of("manifest").unwrap());
+	let path = matches.value_of("image").unwrap();
+	let bytes = read_file(path);
+	let initrd = parse_or_exit(path, &bytes);
+
+	// required entries the manifest does not give can have any name, and may have been stored without one
+	let required = [
+		(EntryType::EarlyInit, &manifest.early_init),
+		(EntryType::PartList, &manifest.part_list),
+		(EntryType::FsSever, &manifest.fs_server),
+		(EntryType::HwAccessServer, &manifest.hwaccess_server),
+	];
+	let mut expected: Vec<(EntryType, Option<&str>)> = required.iter()
+		.map(|(typ, path)| (*typ, path.as_deref()))
+		.collect();
+	expected.extend(manifest.files.iter().map(|file| {
+		let typ = file.typ.as_deref().and_then(EntryType::from_name).unwrap_or(EntryType::Any);
+		(typ, Some(file.name()))
+	}));
+
+	// manifests can not describe comments, init args, source paths, or tags, so they are not compared
+	let mut remaining: Vec<&parse::ParsedEntry> = initrd.entries.iter()
+		.filter(|entry| !matches!(EntryType::from_u64(entry.typ), Some(EntryType::Comment | EntryType::InitArgs | EntryType::SourcePaths | EntryType::Tags)))
+		.collect();
+	let mut matched = true;
+
+	for (typ, name) in expected {
+		let found = remaining.iter().position(|entry| {
+			entry.typ == typ as u64 && match name {
+				Some(name) => entry.name == name || (typ.is_required() && entry.name.is_empty()),
+				None => true,
+			}
+		});
+
+		match found {
+			Some(i) => {
+				remaining.remove(i);
+			},
+			None => {
+				error!("{}: missing {} entry {}", path, typ.name(), name.unwrap_or(""));
+				matched = false;
+			},
+		}
+	}
+
+	for entry in remaining {
+		error!("{}: {} entry {} is not in the manifest", path, type_name(entry.typ), entry.name);
+		matched = false;
+	}
+
+	if !matched {
+		exit(exit_code::VALIDATION_FAILED);
+	}
+
+	println!("{}: {}", path, color::ok("matches manifest"));
+}
+
+fn verify_hashes(matches: &ArgMatches) {
+	let expected_path = matches.value_of("expected").unwrap();
+	let expected = hashes::parse(expected_path, &read_text_file(expected_path));
+	let path = matches.value_of("image").unwrap();
+	let bytes = read_file(path);
+	let initrd = parse_or_exit(path, &bytes);
+
+	let actual: Vec<hashes::EntryHash> = initrd.entries.iter().map(hashes::from_parsed).collect();
+	if !hashes::check(path, &expected, &actual) {
+		exit(exit_code::VALIDATION_FAILED);
+	}
+
+	println!("{}: {}", path, color::ok("hashes match"));
+}
+
+fn print_offset(matches: &ArgMatches) {
+	let path = matches.value_of("image").unwrap();
+	print_entry_offset(path, &read_file(path), matches.value_of("name").unwrap());
+}
+
+// prints data in the same format as xxd, 16 bytes per line
+fn hex_dump(data: &[u8]) {
+	for (i, line) in data.chunks(16).enumerate() {
+		let hex: Vec<String> = line.chunks(2)
+			.map(|pair| pair.iter().map(|byte| format!("{:02x}", byte)).collect())
+			.collect();
+		let ascii: String = line.iter()
+			.map(|byte| if byte.is_ascii_graphic() || *byte == b' ' { *byte as char } else { '.' })
+			.collect();
+
+		println!("{:08x}: {:<40} {}", i * 16, hex.join(" "), ascii);
+	}
+}
+
+fn dump_hex(matches: &ArgMatches) {
+	let path = matches.value_of("image").unwrap();
+	let bytes = read_file(path);
+	let initrd = parse_or_exit(path, &bytes);
+	let mut data = compress::entry_data(find_entry(&initrd, matches.value_of("name").unwrap()));
+
+	if let Some(length) = matches.value_of("length").map(|length| parse_size("--length", length)) {
+		data.truncate(usize::try_from(length).unwrap_or(usize::MAX));
+	}
+
+	hex_dump(&data);
+}
+
+fn list_types() {
+	for typ in EntryType::ALL.iter() {
+		println!("{:<16} {}", typ.name(), *typ as u64);
+	}
+}
+
+fn main() {
+	let matches = clap_app!(("gen-initrd") =>
+		(version: "0.1.0")
+		(about: "Simple utility to generate initrd image for the aurora kernel")
+		(after_help: "EXIT CODES:\n    0      Success, including a build skipped by -n\n    1      Invalid arguments, or a failure not covered by another code\n    2      An input file could not be read\n    3      An output could not be written\n    4      A check failed, such as validation of entries, --fail-on-warning, verify-hashes, or match\n    5      An image read by a command or given as an input is malformed\n    130    Interrupted by ctrl-c")
+		(@setting SubcommandsNegateReqs)
+		(@arg color: --color +takes_value +global possible_values(&["auto", "always", "never"]) "When to color diagnostic output, auto colors it when writing to a terminal and NO_COLOR is not set, defaults to auto")
+		(@arg watch: --watch conflicts_with("fd") "Build the initrd, then rebuild it whenever one of the input files changes until interrupted")
+		(@arg ("check-newer"): -n "Check if any files to be included in initrd are newer than the output initrd image, if they are not do not build initrd")
+		(@arg manifest: --manifest +takes_value "Toml manifest describing the files to include in initrd, required files given as arguments take priority over the manifest")
+		(@arg ("json-stdin"): --("json-stdin") conflicts_with_all(&["manifest", "watch"]) "Read a json array of objects with a path and optional name, type, and align describing the files to include in initrd from stdin, like the files of a manifest")
+		(@arg ("from-json-layout"): --("from-json-layout") +takes_value value_name("FILE") conflicts_with_all(&["manifest", "json-stdin", "early-init", "part-list", "fs-server", "hwaccess-server", "files", "files-csv", "files-env", "from-image", "fd", "add-cmd", "entry-order-file", "entry"]) "Build the initrd from a json or toml layout with the fields written by --emit-layout, such as a layout written by it, reading each entry from its path or name in the order given, and fail without writing any output if the generated initrd does not match the layout exactly")
+		(@arg ("early-init"): -i --init +takes_value value_name("EXECUTABLE") required_unless_one(&["manifest", "json-stdin", "from-json-layout", "entry"]) "First executable spawned by kernel which is responsible for mounting the root filesytem and spawning the init process")
+		(@arg ("part-list"): -p --("part-list") +takes_value value_name("FILE") required_unless_one(&["manifest", "json-stdin", "from-json-layout", "entry"]) "File read by early-init which describes which filesytem drivers to use for which partitions and where to mount them")
+		(@arg ("fs-server"): -f --fs +takes_value value_name("EXECUTABLE") required_unless_one(&["manifest", "json-stdin", "from-json-layout", "entry"]) "Filesystem serveri binary")
+		(@arg ("hwaccess-server"): -a --hwaccess +takes_value value_name("EXECUTABLE") required_unless_one(&["manifest", "json-stdin", "from-json-layout", "entry"]) "Hwacess server which drivers will use to interface with hardware")
+		(@arg ("early-init-name"): --("early-init-name") +takes_value value_name("NAME") "Name to store the early-init entry under instead of its path")
+		(@arg ("part-list-name"): --("part-list-name") +takes_value value_name("NAME") "Name to store the part-list entry under instead of its path")
+		(@arg ("fs-server-name"): --("fs-server-name") +takes_value value_name("NAME") "Name to store the fs-server entry under instead of its path")
+		(@arg ("hwaccess-server-name"): --("hwaccess-server-name") +takes_value value_name("NAME") "Name to store the hwaccess-server entry under instead of its path")
+		(@arg entry: --entry +takes_value +multiple number_of_values(1) value_name("TYPE:PATH[:NAME]") "Include the file at PATH as an entry of type TYPE called NAME, which defaults to PATH, the required entries can be given this way instead of by their flags, paths can not contain :, can be repeated")
+		(@arg out: -o +takes_value value_name("FILE") required_unless("embed-in") "Output file to save initrd to, or - to write it to stdout")
+		(@arg ("create-dirs"): --("create-dirs") requires("out") "Create the directory -o is written to and any missing directories above it before writing the initrd")
+		(@arg ("embed-in"): --("embed-in") +takes_value value_name("ELF") "Store the initrd in a section of this elf file, instead of or as well as writing it to -o")
+		(@arg ("embed-section"): --("embed-section") +takes_value value_name("NAME") requires("embed-in") "Name of the section --embed-in stores the initrd in, defaults to .initrd")
+		(@arg format: --format +takes_value possible_values(&["raw", "c-header"]) requires("out") "Format to write -o in, c-header writes a c header defining the initrd as an array, defaults to raw")
+		(@arg ("c-symbol"): --("c-symbol") +takes_value value_name("NAME") "Name of the array written by --format c-header, its length is NAME_len, defaults to initrd")
+		(@arg split: --split +takes_value value_name("SIZE") requires("out") conflicts_with_all(&["verify-after-write", "format"]) "Write the initrd to numbered files FILE.000, FILE.001, and so on of SIZE bytes each instead of to FILE, the last may be shorter, K, M, and G suffixes are allowed")
+		(@arg ("output-mode"): --("output-mode") +takes_value requires("out") "Octal permissions to set on the output file after it is written, such as 0644")
+		(@arg ("touch-output"): --("touch-output") "Update the modified time of the output file when -n skips initrd generation")
+		(@arg ("verify-after-write"): --("verify-after-write") requires("out") "Read the output file back after writing it and check it is a valid initrd identical to the one generated")
+		(@arg ("emit-hashes"): --("emit-hashes") +takes_value value_name("FILE") "Write the name and sha256 of the data of every entry to FILE sorted by name, in the format read by verify-hashes")
+		(@arg ("report-waste"): --("report-waste") "Print how many bytes of the initrd are spent on alignment padding")
+		(@arg ("files-env"): --("files-env") +takes_value value_name("VAR") "Environment variable containing a newline separated list of additional files to include in initrd")
+		(@arg ("from-image"): --("from-image") +takes_value +multiple number_of_values(1) value_name("IMAGE") "Include the entries of an existing initrd image other than its required entries as additional files, can be repeated")
+		(@arg ("only-types"): --("only-types") +takes_value value_name("TYPES") requires("from-image") "Comma separated list of the only entry types to include from --from-image, unknown types can be given as numbers")
+		(@arg ("exclude-types"): --("exclude-types") +takes_value value_name("TYPES") requires("from-image") "Comma separated list of entry types not to include from --from-image, unknown types can be given as numbers")
+		(@arg ("files-csv"): --("files-csv") +takes_value "Csv file with rows of path,type,name describing additional files to include in initrd, or tab separated if it ends in .tsv, a blank name is the file name of the path, or the path relative to --relative-to")
+		(@arg ("relative-to"): --("relative-to") +takes_value value_name("DIR") "Name additional files which are not given a name by their path relative to DIR instead of the path as given, files which are not under DIR keep their usual name")
+		(@arg base: --base +takes_value "Previously built initrd to copy the data of unchanged files from instead of reading them again")
+		(@arg ("entry-order-file"): --("entry-order-file") +takes_value "File listing entry names one per line in the order they should be placed after the required entries, unlisted entries follow sorted by name")
+		(@arg ("validate-part-list"): --("validate-part-list") "Check that every filesystem driver referenced by the part-list is included in the initrd")
+		(@arg ("store-ownership"): --("store-ownership") "Store the uid and gid owning each file in the initrd")
+		(@arg owner: --owner +takes_value "Store this uid as the owner of every entry instead of the uid owning the file, implies --store-ownership")
+		(@arg group: --group +takes_value "Store this gid as the group of every entry instead of the gid owning the file, implies --store-ownership")
+		(@arg ("trailer-magic"): --("trailer-magic") "End the initrd with the magic number and its total length, so truncated images can be detected")
+		(@arg ("table-at-end"): --("table-at-end") "Place the entry table after the data of every entry and store its offset in the header, for writers which only know the final offsets once the data is written")
+		(@arg merkle: --merkle "Append a merkle tree of the sha256 of every block of the initrd and store its root in the header, so a loader can check each block as it is read, checked by verify")
+		(@arg ("merkle-block-size"): --("merkle-block-size") +takes_value value_name("N") requires("merkle") "Size of the blocks hashed by --merkle, a power of two which is at least 512, K, M, and G suffixes are allowed, defaults to 4096")
+		(@arg checksum: --checksum "Store a checksum of the whole initrd in the header, so corruption can be detected, checked by verify")
+		(@arg ("checksum-algo"): --("checksum-algo") +takes_value possible_values(&["crc32", "xxhash", "sha256"]) "Algorithm of the checksum stored by --checksum, which is recorded in the header, implies --checksum, defaults to crc32")
+		(@arg ("relative-offsets"): --("relative-offsets") "Store entry offsets relative to the start of the data region, so the initrd can be embedded in other data without being rewritten")
+		(@arg comment: --comment +takes_value value_name("TEXT") "Store TEXT, such as the commit the initrd was built from, in an unnamed comment entry shown by verify, up to 4096 bytes")
+		(@arg ("init-args"): --("init-args") +takes_value value_name("ARGS") "Store ARGS as-is in an unnamed init-args entry, which early-init reads its arguments from, shown by verify")
+		(@arg ("record-source-paths"): --("record-source-paths") "Store the path every file was read from in an unnamed source-paths entry keyed by entry index, shown by verify --decode, so names can be shortened without losing where entries came from")
+		(@arg tag: --tag +takes_value +multiple number_of_values(1) value_name("NAME:KEY=VALUE") "Tag the entry called NAME with KEY=VALUE in an unnamed tags entry keyed by entry index, shown by verify --decode, so loaders can select entries by tag, can be repeated")
+		(@arg ("omit-special-names"): --("omit-special-names") "Store the required entries with empty names, since they are found by their type")
+		(@arg ("rename-map"): --("rename-map") +takes_value value_name("FILE") "File of source-name=image-name lines renaming entries after their names are computed")
+		(@arg ("strict-rename-map"): --("strict-rename-map") requires("rename-map") "Fail instead of warning when a name in the rename map is not the name of an entry")
+		(@arg ("normalize-names"): --("normalize-names") "Convert backslashes in entry names to forward slashes and remove redundant separators and . components")
+		(@arg ("entry-limit-per-type"): --("entry-limit-per-type") +takes_value +multiple number_of_values(1) value_name("TYPE=N") "Fail if there are more than N entries of type TYPE, types which are not given can have any number of entries, can be repeated")
+		(@arg ("no-validate"): --("no-validate") "Do not check the data of entries of types with a required format, that the part-list can be read, and with --require-elf that early-init is an elf executable the kernel can start and fs-server is an elf file, checks enabled by other options are still run")
+		(@arg ("validate-entry-point"): --("validate-entry-point") "Fail if early-init is an elf file which is not a static executable with an entry point in one of its load segments")
+		(@arg ("require-elf"): --("require-elf") conflicts_with("no-validate") "Fail if early-init is not an elf executable the kernel can start or fs-server is not an elf file, instead of treating them as raw binaries")
+		(@arg ("error-on-empty"): --("error-on-empty") "Fail instead of warning when an entry of a required type has no data")
+		(@arg ("max-total-name-bytes"): --("max-total-name-bytes") +takes_value value_name("N") "Fail if the names of all entries, each padded to --align-names, take more than N bytes, K, M, and G suffixes are allowed")
+		(@arg ("ascii-names"): --("ascii-names") "Fail if any entry name contains a byte which is not ascii, for kernels which only support ascii names")
+		(@arg ("case-insensitive-names"): --("case-insensitive-names") "Fail if two entries have names which only differ in case, so the initrd can be read by a case insensitive loader")
+		(@arg ("base-offset"): --("base-offset") +takes_value value_name("N") conflicts_with("relative-offsets") "Offset the initrd will be placed at in a larger file, which is added to every entry offset and stored in the header, K, M, and G suffixes are allowed")
+		(@arg ("align-names"): --("align-names") +takes_value value_name("N") "Start every entry name at a multiple of N, a power of two, names do not need the alignment of data so this can be smaller to waste less padding, recorded in the header, defaults to 8")
+		(@arg ("align-data"): --("align-data") +takes_value value_name("N") "Start the data of every entry at a multiple of N, a power of two which is at least 8, entries needing a larger alignment keep it, recorded in the header, K, M, and G suffixes are allowed, defaults to 8")
+		(@arg ("dma-align"): --("dma-align") +takes_value value_name("N") "Start the data of every entry at a multiple of N and pad it with zeros to a multiple of N, so it can be transferred by dma straight from the initrd, K, M, and G suffixes are allowed")
+		(@arg ("stream-format"): --("stream-format") conflicts_with_all(&["compact", "align-names", "align-data", "store-ownership", "owner", "group", "trailer-magic", "relative-offsets", "trim-trailing-zeros", "compress", "auto-compress", "base-offset", "dma-align", "table-at-end", "merkle", "checksum", "checksum-algo", "layout", "hot-first", "base", "verify-after-write", "check-elf-alignment", "print-offset", "emit-layout", "pin-layout", "from-json-layout", "symbol-map", "report-waste"]) "Write every entry directly followed by its name and data instead of an entry table, so the initrd can be read front to back without seeking, entry alignments are not kept")
+		(@arg compress: --compress +takes_value possible_values(&["gzip", "zstd", "xz"]) conflicts_with("auto-compress") "Compress the data of every entry with this algorithm, even entries it makes larger, loaders have to be able to decompress it, the gzip, zstd, or xz command is used so it has to be installed")
+		(@arg ("compress-level"): --("compress-level") +takes_value requires("compress") "Level --compress compresses at, gzip has levels 0 to 9, zstd 1 to 22, and xz 0 to 9, defaults to the default level of the command")
+		(@arg ("auto-compress"): --("auto-compress") "Compress the data of every entry of at least 512 bytes with whichever of gzip, zstd, and xz makes it smallest, or store it uncompressed if none of them make it smaller, the commands have to be installed")
+		(@arg verbose: -v --verbose "Print what is decided for each entry while building, such as the compression chosen by --auto-compress")
+		(@arg ("trim-trailing-zeros"): --("trim-trailing-zeros") "Do not store trailing zeros of entry data, the original length is stored so readers can zero extend the data")
+		(@arg ("progress-json"): --("progress-json") +takes_value value_name("FD") "Write newline delimited json events describing the progress of the build to inherited file descriptor FD")
+		(@arg preprocess: --preprocess +takes_value +multiple number_of_values(1) value_name("TYPE=CMD") "Pipe the data of every entry of type TYPE through the shell command CMD and store its output instead, can be repeated")
+		(@arg ("include-debug-link"): --("include-debug-link") +takes_value value_name("DIR") "Write every elf file included in initrd as it was read to DIR/NAME.debug, where NAME is the file name of its entry, and add a .gnu_debuglink section referring to it to the entry after --preprocess, so a debugger can find debug info removed by a command such as strip")
+		(@arg ("add-cmd"): --("add-cmd") +takes_value +multiple number_of_values(1) value_name("NAME:CMD") "Include what the shell command CMD writes to stdout as an additional file called NAME, the initrd is only reproducible if CMD is, can be repeated")
+		(@arg fd: --fd +takes_value +multiple number_of_values(1) "NAME=N, include everything read from inherited file descriptor N as an additional file called NAME, can be repeated")
+		(@arg ("dedupe-names"): --("dedupe-names") +takes_value possible_values(&["first-wins", "last-wins"]) "Keep only the first or last given of the additional entries with the same name, so later files can override earlier ones")
+		(@arg layout: --layout +takes_value possible_values(&["arg-order", "name", "size", "size-desc"]) "Order to place the names and data of entries in, the entry table always stays in argument order")
+		(@arg ("fail-on-warning"): --("fail-on-warning") "Exit with an error if any warnings are printed while building the initrd, the output is not written if the warning is printed before it")
+		(@arg ("hot-first"): --("hot-first") conflicts_with("layout") "Place every name directly after the entry table followed by the data of entries smallest first, so small frequently read entries share pages with the table")
+		(@arg ("warn-large"): --("warn-large") +takes_value "Print a warning for every entry with more than this many bytes of data, K, M, and G suffixes are allowed")
+		(@arg ("emit-layout"): --("emit-layout") +takes_value value_name("FILE") "Write a toml file describing the type, name, offset, and length of every entry in the generated initrd")
+		(@arg ("pin-layout"): --("pin-layout") +takes_value value_name("FILE") "Fail if any entry in a layout written by --emit-layout is missing or at different offsets in the generated initrd")
+		(@arg ("allow-layout-drift"): --("allow-layout-drift") requires("pin-layout") "Warn instead of failing when an entry pinned by --pin-layout has moved")
+		(@arg ("symbol-map"): --("symbol-map") +takes_value value_name("FILE") "Write a text file with the offset, length, and name of every entry in the generated initrd sorted by offset, for debuggers to find the entry containing an offset")
+		(@arg ("print-offset"): --("print-offset") +takes_value value_name("NAME") "Print the offset and length of the data of the entry called NAME in the generated initrd")
+		(@arg ("self-check-reproducible"): --("self-check-reproducible") "Generate the initrd a second time before writing it and fail if the two images differ, to catch nondeterminism in the generator")
+		(@arg ("check-elf-alignment"): --("check-elf-alignment") "Check that every elf entry is placed at an offset compatible with the alignment of its load segments, so it can be mapped directly from the initrd")
+		(@arg ("audit-log"): --("audit-log") +takes_value value_name("FILE") "Append the time, output path, sha256 of the initrd, number of entries, and version of gen-initrd to FILE after every successful build")
+		(@arg ("audit-strict"): --("audit-strict") requires("audit-log") "Fail the build if the audit log can not be written, instead of warning")
+		(@arg ("write-lock"): --("write-lock") +takes_value value_name("FILE") "Write a lock file recording the sha256 of every entry and of the generated initrd")
+		(@arg ("verify-lock"): --("verify-lock") +takes_value value_name("FILE") "Fail without writing any output if the rebuilt initrd or any of its entries do not match this lock file, -n is ignored since the initrd has to be rebuilt")
+		(@arg compact: --compact "Store offsets and lengths in the entry table as 32 bit integers to make the table smaller")
+		(@arg sign: --sign +takes_value requires("out") "Pem encoded ed25519 private key used to write a detached signature of the initrd to the output file with .sig appended, or of every chunk written by --split to the chunk with .sig appended")
+		(@arg ("time-budget"): --("time-budget") +takes_value value_name("SECONDS") "Fail if the initrd has not been generated after this many seconds, such as when reading a file hangs, outputs are always written completely once writing starts")
+		(@arg ("read-retries"): --("read-retries") +takes_value value_name("N") "Retry reading a file up to N times with a short backoff if it fails with an error other than not existing or permission denied, for flaky network filesystems, defaults to 0")
+		(@arg sysroot: --sysroot +takes_value value_name("DIR") "Read every file included in initrd from under DIR, including files given as absolute paths, entry names are not changed")
+		(@arg minimal: --minimal conflicts_with("newer-than") "Only include the required entries and entries of other types than any, ignoring every additional file, for a small recovery initrd built from the same options")
+		(@arg ("newer-than"): --("newer-than") +takes_value value_name("TIME") "Only include the additional files modified after TIME, which is seconds since the unix epoch or a file whose modified time is used, the required files are always included")
+		(@arg ("on-missing"): --("on-missing") +takes_value possible_values(&["error", "skip", "warn"]) "What to do when an additional file does not exist, defaults to error")
+		(@arg files: [FILE] ... "additional files to include in initrd")
+		(@subcommand verify =>
+			(about: "Check that an existing initrd image is well formed")
+			(@arg ("only-types"): --("only-types") +takes_value value_name("TYPES") "Comma separated list of the only entry types to list, unknown types can be given as numbers")
+			(@arg ("exclude-types"): --("exclude-types") +takes_value value_name("TYPES") "Comma separated list of entry types not to list, unknown types can be given as numbers")
+			(@arg ("min-size"): --("min-size") +takes_value value_name("N") "Only list entries with at least N bytes of data, K, M, and G suffixes are allowed")
+			(@arg ("max-size"): --("max-size") +takes_value value_name("N") "Only list entries with at most N bytes of data, K, M, and G suffixes are allowed")
+			(@arg decode: --decode conflicts_with("print-tree") "Describe the contents of entries which are recognized, such as the architecture of elf files")
+			(@arg ("print-tree"): --("print-tree") "List entries as a directory tree built from the / separated components of their names")
+			(@arg streaming: --streaming conflicts_with_all(&["decode", "print-tree"]) "Read the image a piece at a time instead of loading it into memory, for images too large to fit in memory, stream format images can not be read this way and comments and init args are only listed as entries")
+			(@arg image: <IMAGE> "Initrd image to verify")
+		)
+		(@subcommand merge =>
+			(about: "Combine the entries of several initrd images into one image, the required entries of every image must be the same and are only kept once")
+			(@arg out: -o <FILE> "Output file to save the merged initrd to")
+			(@arg ("only-types"): --("only-types") +takes_value value_name("TYPES") "Comma separated list of the only entry types to merge, unknown types can be given as numbers")
+			(@arg ("exclude-types"): --("exclude-types") +takes_value value_name("TYPES") "Comma separated list of entry types not to merge, unknown types can be given as numbers")
+			(@arg ("min-size"): --("min-size") +takes_value value_name("N") "Only merge entries with at least N bytes of data, K, M, and G suffixes are allowed")
+			(@arg ("max-size"): --("max-size") +takes_value value_name("N") "Only merge entries with at most N bytes of data, K, M, and G suffixes are allowed")
+			(@arg ("allow-duplicates"): --("allow-duplicates") "Keep entries with the same name from different images instead of failing")
+			(@arg images: <IMAGE> ... "Initrd images to merge, entries are kept in the order given")
+		)
+		(@subcommand realign =>
+			(about: "Write a copy of an existing initrd image with the data of every entry aligned to a new alignment, keeping its types, names, and data")
+			(@arg align: --align <N> "Alignment of the data of every entry, a power of two which is at least 8, K, M, and G suffixes are allowed")
+			(@arg out: -o <FILE> "Output file to save the realigned initrd to")
+			(@arg image: <IMAGE> "Initrd image to realign")
+		)
+		(@subcommand join =>
+			(about: "Concatenate the numbered files written by --split back into one initrd")
+			(@arg out: -o <FILE> "Output file to save the joined initrd to")
+			(@arg prefix: <PREFIX> "File given to -o when the initrd was split, the chunks are PREFIX.000, PREFIX.001, and so on")
+		)
+		(@subcommand sign =>
+			(about: "Write a detached ed25519 signature of an existing initrd image to the image path with .sig appended")
+			(@arg key: --key <KEY> "Pem encoded ed25519 private key")
+			(@arg image: <IMAGE> "Initrd image to sign")
+		)
+	// clap_app can not name subcommands with a dash
+	).subcommand(SubCommand::with_name("gen-manifest")
+		.about("Generate a manifest including every file in a directory, files named after a required entry are used as that entry")
+		.arg(Arg::with_name("out").short("o").value_name("FILE")
+			.help("File to write the manifest to, defaults to stdout"))
+		.arg(Arg::with_name("dir").value_name("DIR").required(true)
+			.help("Directory to scan"))
+	).subcommand(SubCommand::with_name("print-offset")
+		.about("Print the offset and length of the data of an entry in an existing initrd image")
+		.arg(Arg::with_name("image").value_name("IMAGE").required(true)
+			.help("Initrd image to read"))
+		.arg(Arg::with_name("name").value_name("NAME").required(true)
+			.help("Name of the entry"))
+	).subcommand(SubCommand::with_name("dump-hex")
+		.about("Print the data of an entry in an existing initrd image as a hex dump")
+		.arg(Arg::with_name("length").long("length").value_name("N")
+			.help("Only print the first N bytes, K, M, and G suffixes are allowed"))
+		.arg(Arg::with_name("image").value_name("IMAGE").required(true)
+			.help("Initrd image to read"))
+		.arg(Arg::with_name("name").value_name("NAME").required(true)
+			.help("Name of the entry"))
+	// match is a keyword so clap_app can not name a subcommand with it
+	).subcommand(SubCommand::with_name("match")
+		.about("Check that an existing initrd image contains exactly the entries described by a manifest, by name and type")
+		.arg(Arg::with_name("manifest").long("manifest").value_name("FILE").required(true)
+			.help("Toml manifest the image should match"))
+		.arg(Arg::with_name("image").value_name("IMAGE").required(true)
+			.help("Initrd image to check"))
+	).subcommand(SubCommand::with_name("verify-hashes")
+		.about("Check that the data of every entry of an existing initrd image has the expected sha256, and that no entries are missing or extra")
+		.arg(Arg::with_name("expected").long("expected").value_name("FILE").required(true)
+			.help("File of lines with an entry name followed by the lowercase hex sha256 of its data"))
+		.arg(Arg::with_name("image").value_name("IMAGE").required(true)
+			.help("Initrd image to check"))
+	).subcommand(SubCommand::with_name("size-estimate")
+		.about("Build the initrd given by the other arguments in memory with the data of every entry compressed by each candidate, and print its size and how long compressing took, without writing any output")
+		.arg(Arg::with_name("try").long("try").value_name("LIST").required(true)
+			.help("Comma separated candidates of the form ALGORITHM or ALGORITHM:LEVEL, such as gzip,zstd:19,xz, a missing level is the default level of the command"))
+	).subcommand(SubCommand::with_name("list-types")
+		.about("Print the name and numeric value of every entry type")
+	).subcommand(SubCommand::with_name("verify-signature")
+		.about("Check the detached ed25519 signature of an initrd image")
+		.arg(Arg::with_name("pubkey").long("pubkey").value_name("KEY").required(true)
+			.help("Pem encoded ed25519 public key"))
+		.arg(Arg::with_name("signature").long("signature").value_name("FILE")
+			.help("Signature file, defaults to the image path with .sig appended"))
+		.arg(Arg::with_name("image").value_name("IMAGE").required(true)
+			.help("Initrd image to check"))
+	).get_matches();
+
+	// global arguments given after a subcommand are only set in the subcommand matches
+	let color = matches.value_of("color")
+		.or_else(|| matches.subcommand().1.and_then(|sub_matches| sub_matches.value_of("color")));
+	color::init(color.unwrap_or("auto"));
+	interrupt::install();
+
+	match matches.subcommand() {
+		("verify", Some(sub_matches)) => return verify(sub_matches),
+		("merge", Some(sub_matches)) => return merge(sub_matches),
+		("realign", Some(sub_matches)) => return realign(sub_matches),
+		("join", Some(sub_matches)) => return join(sub_matches),
+		("sign", Some(sub_matches)) => return sign_image(sub_matches),
+		("verify-signature", Some(sub_matches)) => return verify_signature(sub_matches),
+		("print-offset", Some(sub_matches)) => return print_offset(sub_matches),
+		("dump-hex", Some(sub_matches)) => return dump_hex(sub_matches),
+		("match", Some(sub_matches)) => return match_manifest(sub_matches),
+		("verify-hashes", Some(sub_matches)) => return verify_hashes(sub_matches),
+		("list-types", Some(_)) => return list_types(),
+		("gen-manifest", Some(sub_matches)) => return gen_manifest(sub_matches),
+		_ => (),
+	}
+
+	let verbose = matches.is_present("verbose");
+
+	let planned = matches.value_of("from-json-layout").map(|path| (path, layout::load_planned(path)));
+
+	let manifest = if matches.is_present("json-stdin") {
+		Some(manifest::from_json_stdin())
+	} else if let Some((path, planned)) = &planned {
+		Some(planned.to_manifest(path))
+	} else {
+		matches.value_of("manifest").map(manifest::load)
+	};
+	let from_manifest = |path: fn(&manifest::Manifest) -> &Option<String>| {
+		manifest.as_ref().and_then(|manifest| path(manifest).as_deref())
+	};
+
+	// the required entry flags are shorthands for --entry, so both are handled the same way
+	let required_flags = [
+		(EntryType::EarlyInit, "early-init", "early-init-name"),
+		(EntryType::PartList, "part-list", "part-list-name"),
+		(EntryType::FsSever, "fs-server", "fs-server-name"),
+		(EntryType::HwAccessServer, "hwaccess-server", "hwaccess-server-name"),
+	];
+	let mut entry_args: Vec<EntryArg> = required_flags.iter()
+		.filter_map(|(typ, arg, name_arg)| Some(EntryArg {
+			typ: *typ,
+			path: matches.value_of(arg)?,
+			name: matches.value_of(name_arg),
+		}))
+		.collect();
+	entry_args.extend(matches.values_of("entry").into_iter().flatten().map(parse_entry_arg));
+
+	for typ in EntryType::ALL.iter().copied().filter(EntryType::is_required) {
+		if entry_args.iter().filter(|entry| entry.typ == typ).count() > 1 {
+			error!("The {} is given more than once by its flag or --entry", typ.name());
+			exit(1);
+		}
+	}
+	let required_arg = |typ| entry_args.iter().find(|entry| entry.typ == typ);
+
+	let early_init = required_path(required_arg(EntryType::EarlyInit).map(|entry| entry.path), from_manifest(|m| &m.early_init), "early-init");
+	let part_list = required_path(required_arg(EntryType::PartList).map(|entry| entry.path), from_manifest(|m| &m.part_list), "part-list");
+	let fs_server = required_path(required_arg(EntryType::FsSever).map(|entry| entry.path), from_manifest(|m| &m.fs_server), "fs-server");
+	let hwaccess_server = required_path(required_arg(EntryType::HwAccessServer).map(|entry| entry.path), from_manifest(|m| &m.hwaccess_server), "hwaccess-server");
+	let on_missing = matches.value_of("on-missing").unwrap_or("error");
+	let sysroot = matches.value_of("sysroot");
+	if let Some(sysroot) = sysroot {
+		if !Path::new(sysroot).is_dir() {
+			error!("Invalid value for --sysroot: {} is not a directory", sysroot);
+			exit(1);
+		}
+	}
+	let debug_dir = matches.value_of("include-debug-link");
+	if let Some(dir) = debug_dir {
+		if !Path::new(dir).is_dir() {
+			error!("Invalid value for --include-debug-link: {} is not a directory", dir);
+			exit(1);
+		}
+	}
+	let env_files = matches.value_of("files-env").map(read_files_env);
+	// environment variables can not contain nul bytes, so unlike --fd the list is newline separated
+	let env_file_lines = env_files.iter().flat_map(|files| files.lines()).filter(|line| !line.is_empty());
+	let mut other_files: Vec<&str> = matches.values_of("files").into_iter().flatten()
+		.chain(env_file_lines)
+		.filter(|file| {
+			// a missing file with the error policy is reported when it is read
+			let path = in_sysroot(sysroot, file);
+			let missing = matches!(metadata(&path), Err(ref err) if err.kind() == io::ErrorKind::NotFound);
+			if missing && on_missing == "warn" {
+				warning!("skipping missing file {}", path);
+			}
+
+			!missing || on_missing == "error"
+		})
+		.collect();
+
+	let relative_to = matches.value_of("relative-to");
+	let csv_path = matches.value_of("files-csv");
+	let csv_text = csv_path.map(read_text_file);
+	let mut fd_args: Vec<(&str, i32)> = matches.values_of("fd").into_iter().flatten().map(parse_fd_arg).collect();
+	for (i, (_, fd)) in fd_args.iter().enumerate() {
+		if fd_args[..i].iter().any(|(_, other)| other == fd) {
+			error!("File descriptor {} is passed to --fd more than once", fd);
+			exit(1);
+		}
+	}
+
+	let type_limits: Vec<(EntryType, usize)> = matches.values_of("entry-limit-per-type").into_iter().flatten().map(parse_type_limit).collect();
+	for (i, (typ, _)) in type_limits.iter().enumerate() {
+		if type_limits[..i].iter().any(|(other, _)| other == typ) {
+			error!("Entry type {} is passed to --entry-limit-per-type more than once", typ.name());
+			exit(1);
+		}
+	}
+
+	let mut add_cmd_args: Vec<(&str, &str)> = matches.values_of("add-cmd").into_iter().flatten().map(preprocess::parse_add_arg).collect();
+	let tag_args: Vec<(&str, &str, &str)> = matches.values_of("tag").into_iter().flatten().map(tags::parse_arg).collect();
+
+	let order_path = matches.value_of("entry-order-file");
+	let order_text = order_path.map(read_text_file);
+	let rename_path = matches.value_of("rename-map");
+	let rename_text = rename_path.map(read_text_file);
+
+	// additional entries given on the command line come before those listed in files
+	let mut listed_files: Vec<ListedFile> = entry_args.iter()
+		.filter(|entry| !entry.typ.is_required())
+		.map(|entry| ListedFile {
+			path: entry.path,
+			typ: entry.typ,
+			name: entry.name.or_else(|| relative_name(entry.path, relative_to)).unwrap_or(entry.path),
+			align: ALIGN,
+			tags: Vec::new(),
+		})
+		.collect();
+
+	if let (Some(path), Some(text)) = (csv_path, &csv_text) {
+		listed_files.extend(parse_files_csv(path, text, relative_to));
+	}
+
+	if let Some(manifest) = &manifest {
+		listed_files.extend(manifest.files.iter().map(|file| ListedFile {
+			path: &file.path,
+			// types are checked when the manifest is loaded
+			typ: file.typ.as_deref().and_then(EntryType::from_name).unwrap_or(EntryType::Any),
+			name: file.name(),
+			align: file.align.unwrap_or(ALIGN),
+			tags: file.tags.iter().map(|(key, value)| (key.as_str(), value.as_str())).collect(),
+		}));
+	}
+
+	// the files are dropped before anything is read, so a missing additional file can not fail a minimal build
+	if matches.is_present("minimal") {
+		let listed_len = listed_files.len();
+		listed_files.retain(|file| file.typ != EntryType::Any);
+		let dropped = other_files.len() + listed_len - listed_files.len() + fd_args.len() + add_cmd_args.len();
+
+		other_files.clear();
+		fd_args.clear();
+		add_cmd_args.clear();
+
+		if verbose {
+			eprintln!("--minimal dropped {} additional entries", dropped);
+		}
+	}
+
+	// required entries are always included, so a delta image can still be booted
+	if let Some(time) = matches.value_of("newer-than").map(parse_newer_than) {
+		let is_newer = |path: &str| get_file_modify_time(&in_sysroot(sysroot, path)) > time;
+		other_files.retain(|file| is_newer(file));
+		listed_files.retain(|file| file.typ.is_required() || is_newer(file.path));
+
+		if other_files.is_empty() && listed_files.iter().all(|file| file.typ.is_required()) {
+			warning!("no additional files are newer than --newer-than {}, the initrd only has the required entries", matches.value_of("newer-than").unwrap());
+		}
+	}
+
+	let out_path = matches.value_of("out");
+	let embed_path = matches.value_of("embed-in");
+	let embed_section = matches.value_of("embed-section").unwrap_or(".initrd");
+
+	// size-estimate builds the initrd without writing it, so it needs neither -o nor --embed-in
+	let size_estimate = matches.subcommand_matches("size-estimate")
+		.map(|sub_matches| compress::parse_candidates(sub_matches.value_of("try").unwrap()));
+	if size_estimate.is_some() && matches.is_present("stream-format") {
+		error!("size-estimate can not be used with --stream-format, which does not store compressed entries");
+		exit(1);
+	}
+	if size_estimate.is_some() && debug_dir.is_some() {
+		error!("size-estimate writes no output, so it can not be used with --include-debug-link");
+		exit(1);
+	}
+
+	// the file checked by -n, since -o is optional with --embed-in, there is none for size-estimate
+	let target_path = out_path.or(embed_path);
+
+	// -o - writes the initrd to stdout, where there is no file to split, sign, or check, and nothing else can be printed
+	let to_stdout = out_path == Some("-");
+	if to_stdout {
+		let stdout_conflicts = [
+			("split", "--split"),
+			("sign", "--sign"),
+			("verify-after-write", "--verify-after-write"),
+			("output-mode", "--output-mode"),
+			("print-offset", "--print-offset"),
+			("report-waste", "--report-waste"),
+		];
+		if let Some((_, flag)) = stdout_conflicts.iter().find(|(arg, _)| matches.is_present(arg)) {
+			error!("-o - writes the initrd to stdout, so it can not be used with {}", flag);
+			exit(1);
+		}
+	}
+
+	let output_mode = matches.value_of("output-mode").map(parse_mode);
+	let split = matches.value_of("split").map(|size| parse_size("--split", size));
+	if split == Some(0) {
+		error!("Invalid value for --split: chunks can not be empty");
+		exit(1);
+	}
+	let c_symbol = matches.value_of("c-symbol").unwrap_or("initrd");
+	if !c_header::valid_symbol(c_symbol) {
+		error!("Invalid value for --c-symbol: {} is not a valid c identifier", c_symbol);
+		exit(1);
+	}
+	let warn_large = matches.value_of("warn-large").map(|size| parse_size("--warn-large", size));
+
+	let name_align = matches.value_of("align-names").map_or(ALIGN, |align| parse_size("--align-names", align));
+	if !name_align.is_power_of_two() {
+		error!("Invalid value for --align-names: {} is not a power of two", name_align);
+		exit(1);
+	}
+	let data_align = matches.value_of("align-data").map_or(ALIGN, |align| parse_size("--align-data", align));
+	if !valid_alignment(data_align) {
+		error!("Invalid value for --align-data: {} is not a power of two which is at least {}", data_align, ALIGN);
+		exit(1);
+	}
+	// checked before any input is read, so an invalid level fails before the commands compressing data are run
+	let compress_level = matches.value_of("compress-level").map(|level| {
+		let algorithm = Compression::from_name(matches.value_of("compress").unwrap()).unwrap();
+		let checked = level.parse::<u32>()
+			.map_err(|_| format!("{} is not a number", level))
+			.and_then(|level| compress::check_level(algorithm, level).map(|_| level));
+
+		match checked {
+			Ok(level) => level,
+			Err(err) => {
+				error!("Invalid value for --compress-level: {}", err);
+				exit(1);
+			},
+		}
+	});
+
+	let locked = matches.value_of("verify-lock").map(|path| (path, lock::load(path)));
+	let pinned = matches.value_of("pin-layout").map(|path| (path, layout::load_pinned(path)));
+
+	// load the key before doing any work so a bad key fails fast
+	let signing_key = matches.value_of("sign").map(sign::load_signing_key);
+
+	// where every entry is read from, which differs from the path giving its name with --sysroot
+	let entry_paths: Vec<String> = [early_init, part_list, fs_server, hwaccess_server].iter()
+		.chain(other_files.iter())
+		.copied()
+		.chain(listed_files.iter().map(|file| file.path))
+		.map(|path| in_sysroot(sysroot, path))
+		.collect();
+
+	let mut input_paths: Vec<&str> = entry_paths.iter().map(String::as_str).collect();
+	let from_image_paths: Vec<&str> = matches.values_of("from-image").into_iter().flatten().collect();
+	input_paths.extend(from_image_paths.iter());
+	input_paths.extend(csv_path);
+	input_paths.extend(rename_path);
+	input_paths.extend(manifest.iter().flat_map(|manifest| manifest.sources.iter().map(String::as_str)));
+	for path in out_path.iter().filter(|_| !to_stdout).chain(embed_path.iter()) {
+		check_output_not_input(path, &input_paths);
+	}
+
+	if matches.is_present("watch") {
+		watch::run(&input_paths);
+	}
+
+	// started after watch::run so the budget applies to each rebuild rather than to watching
+	if let Some(budget) = matches.value_of("time-budget") {
+		start_time_budget(budget);
+	}
+
+	// opened after watch::run so every rebuild inherits the fd and reports its own events
+	let progress_fd = matches.value_of("progress-json");
+	if let Some((_, fd)) = fd_args.iter().find(|(_, fd)| Some(fd.to_string().as_str()) == progress_fd) {
+		error!("File descriptor {} is passed to both --fd and --progress-json", fd);
+		exit(1);
+	}
+	let progress = progress::Progress::new(progress_fd);
+	progress.start();
+
+	// data read from file descriptors has no modified time, so it always has to be rebuilt
+	// a lock can only be verified by rebuilding
+	// stdout has no previous initrd to compare against
+	let check_newer = matches.is_present("check-newer") && fd_args.is_empty() && locked.is_none() && !to_stdout;
+	if let (true, Some(target_path)) = (check_newer, target_path) {
+		// with --split the initrd is only written to the chunks, and is up to date if the oldest of them is
+		let outputs = match (out_path, split) {
+			(Some(out_path), Some(chunk_size)) => existing_chunks(out_path, chunk_size).unwrap_or_default(),
+			_ => vec![target_path.to_owned()],
+		};
+		let initrd_time = outputs.iter()
+			.map(|path| metadata(path).map(|metadata| metadata.modified().unwrap()))
+			.collect::<Result<Vec<_>, _>>()
+			.ok()
+			.and_then(|times| times.into_iter().min());
+
+		if let Some(initrd_time) = initrd_time {
+
+			// there are always the required entries, so there is a latest time
+			let latest_time = entry_paths.iter().map(String::as_str)
+				.chain(manifest.iter().flat_map(|manifest| manifest.sources.iter().map(String::as_str)))
+				.chain(from_image_paths.iter().copied())
+				.map(get_file_modify_time)
+				.max()
+				.unwrap();
+
+			if initrd_time > latest_time {
+				eprintln!("Skipping initrd generation, no files have changed");
+
+				if matches.is_present("touch-output") {
+					for path in outputs.iter() {
+						let touched = OpenOptions::new()
+							.write(true)
+							.open(path)
+							.and_then(|file| file.set_modified(SystemTime::now()));
+
+						if let Err(err) = touched {
+							error!("Could not update modified time of output file {}: {}", path, err);
+							exit(exit_code::WRITE_FAILED);
+						}
+					}
+				}
+
+				progress.skipped();
+				exit(0);
+			}
+		}
+	}
+
+	// check if file any files are newer than the initrd, don't create it if they are not
+
+	let base_path = matches.value_of("base");
+	let base_bytes = base_path.map(read_file);
+	let base = match (base_path, &base_bytes) {
+		(Some(path), Some(bytes)) => Some((parse_or_exit(path, bytes), get_file_modify_time(path))),
+		_ => None,
+	};
+
+	let from_image_bytes: Vec<Vec<u8>> = from_image_paths.iter().map(|path| read_file(path)).collect();
+	let from_image_filter = EntryFilter::new(&matches);
+
+	let read_retries = matches.value_of("read-retries").map_or(0, |retries| match retries.parse::<u32>() {
+		Ok(retries) => retries,
+		Err(_) => {
+			error!("Invalid --read-retries {}, expected a number", retries);
+			exit(1);
+		},
+	});
+
+	let preprocessors: Vec<(EntryType, &str)> = matches.values_of("preprocess").into_iter().flatten()
+		.map(preprocess::parse_arg)
+		.collect();
+	// commands for the same type are run in the order they are given
+	let apply_preprocessors = |entry: &mut Entry| {
+		let typ = entry.typ;
+		for (_, command) in preprocessors.iter().filter(|(command_typ, _)| *command_typ == typ) {
+			entry.data = preprocess::run(command, entry.name, std::mem::take(&mut entry.data));
+		}
+	};
+
+	// entries reused from the base were already preprocessed and linked to their debug files when it was built
+	let mk_named_entry = |typ, path, name| {
+		let path = &in_sysroot(sysroot, path);
+
+		if let Some((base, base_time)) = &base {
+			if let Some(entry) = reuse_base_entry(base, *base_time, typ, path, name) {
+				return entry;
+			}
+		}
+
+		let mut entry = read_entry(typ, path, name, read_retries);
+		progress.read_file(path, entry.data.len());
+		let original = debug_dir.map(|_| entry.data.clone());
+		apply_preprocessors(&mut entry);
+		if let (Some(dir), Some(original)) = (debug_dir, original) {
+			add_debug_link(&mut entry, &original, dir);
+		}
+		entry
+	};
+	// the source path is the path as given, not the one under --sysroot, so it matches the build tree
+	let mk_named_entry = |typ, path, name| Entry {
+		source: Some(path),
+		..mk_named_entry(typ, path, name)
+	};
+	let mk_entry = |typ, path| mk_named_entry(typ, path, relative_name(path, relative_to).unwrap_or(path));
+
+	// the names the kernel looks the required entries up by do not have to match where they were built
+	let mk_required_entry = |typ, path, name_arg| {
+		let arg_name = required_arg(typ).and_then(|entry| entry.name).or_else(|| matches.value_of(name_arg));
+		let planned_name = planned.as_ref().and_then(|(_, planned)| planned.required_name(typ));
+		mk_named_entry(typ, path, arg_name.or(planned_name).unwrap_or(path))
+	};
+
+	let mut entries = vec![
+		mk_required_entry(EntryType::EarlyInit, early_init, "early-init-name"),
+		mk_required_entry(EntryType::PartList, part_list, "part-list-name"),
+		mk_required_entry(EntryType::FsSever, fs_server, "fs-server-name"),
+		mk_required_entry(EntryType::HwAccessServer, hwaccess_server, "hwaccess-server-name"),
+	];
+
+	for file in other_files.iter() {
+		entries.push(mk_entry(EntryType::Any, file));
+	}
+
+	for file in listed_files.iter() {
+		let mut entry = mk_named_entry(file.typ, file.path, file.name);
+		entry.align = file.align;
+		entry.tags = file.tags.clone();
+		entries.push(entry);
+	}
+
+	// the required entries always come from this build's inputs, and source paths and tags are keyed by the
+	// indexes of the image they were read from, so none of them are taken from an image
+	let minimal = matches.is_present("minimal");
+	let taken_from_image = |entry: &&parse::ParsedEntry| {
+		let typ = EntryType::from_u64(entry.typ);
+		from_image_filter.includes(entry)
+			&& !typ.is_some_and(|typ| typ.is_required() || matches!(typ, EntryType::SourcePaths | EntryType::Tags) || (minimal && typ == EntryType::Any))
+	};
+
+	for (path, bytes) in from_image_paths.iter().zip(from_image_bytes.iter()) {
+		let initrd = parse_or_exit(path, bytes);
+		check_rewritable(path, &initrd);
+
+		for entry in initrd.entries.iter().filter(taken_from_image) {
+			entries.push(owned_entry(path, entry));
+		}
+	}
+
+	for (name, fd) in fd_args.iter() {
+		let mut entry = Entry {
+			typ: EntryType::Any,
+			name,
+			data: read_fd(*fd),
+			uid: 0,
+			gid: 0,
+			align: ALIGN,
+			source: None,
+			tags: Vec::new(),
+			compressed: None,
+		};
+		apply_preprocessors(&mut entry);
+		entries.push(entry);
+	}
+
+	for (name, command) in add_cmd_args.iter() {
+		let mut entry = Entry {
+			typ: EntryType::Any,
+			name,
+			data: preprocess::generate(command, name),
+			uid: 0,
+			gid: 0,
+			align: ALIGN,
+			source: None,
+			tags: Vec::new(),
+			compressed: None,
+		};
+		progress.run_command(name, command, entry.data.len());
+		if verbose {
+			eprintln!("entry {} generated by {}", name, command);
+		}
+		apply_preprocessors(&mut entry);
+		entries.push(entry);
+	}
+
+	let normalized_names: Vec<String> = if matches.is_present("normalize-names") {
+		entries.iter().map(|entry| normalize_name(entry.name)).collect()
+	} else {
+		Vec::new()
+	};
+
+	for (entry, name) in entries.iter_mut().zip(normalized_names.iter()) {
+		entry.name = name;
+	}
+
+	if let (Some(path), Some(text)) = (rename_path, &rename_text) {
+		apply_rename_map(&mut entries, path, text, matches.is_present("strict-rename-map"));
+	}
+
+	// names are only compared once they are final
+	match matches.value_of("dedupe-names") {
+		Some("first-wins") => dedupe_names(&mut entries, false, verbose),
+		Some("last-wins") => dedupe_names(&mut entries, true, verbose),
+		_ => (),
+	}
+
+	tags::apply_args(&mut entries, &tag_args);
+
+	if let Some(comment) = matches.value_of("comment") {
+		if comment.len() > MAX_COMMENT_LEN {
+			error!("Comment is {} bytes, which is longer than the limit of {} bytes", comment.len(), MAX_COMMENT_LEN);
+			exit(1);
+		}
+
+		entries.push(Entry {
+			typ: EntryType::Comment,
+			name: "",
+			data: comment.as_bytes().to_vec(),
+			uid: 0,
+			gid: 0,
+			align: ALIGN,
+			source: None,
+			tags: Vec::new(),
+			compressed: None,
+		});
+	}
+
+	if let Some(init_args) = matches.value_of("init-args") {
+		entries.push(Entry {
+			typ: EntryType::InitArgs,
+			name: "",
+			data: init_args.as_bytes().to_vec(),
+			uid: 0,
+			gid: 0,
+			align: ALIGN,
+			source: None,
+			tags: Vec::new(),
+			compressed: None,
+		});
+	}
+
+	if let (Some(path), Some(text)) = (order_path, &order_text) {
+		apply_entry_order(&mut entries, path, text);
+	}
+
+	// added once the order is final, since the paths and tags are keyed by entry index
+	if matches.is_present("record-source-paths") {
+		let data = source_paths::encode(&entries);
+		entries.push(Entry {
+			typ: EntryType::SourcePaths,
+			name: "",
+			data,
+			uid: 0,
+			gid: 0,
+			align: ALIGN,
+			source: None,
+			tags: Vec::new(),
+			compressed: None,
+		});
+	}
+
+	if entries.iter().any(|entry| !entry.tags.is_empty()) {
+		let data = tags::encode(&entries);
+		entries.push(Entry {
+			typ: EntryType::Tags,
+			name: "",
+			data,
+			uid: 0,
+			gid: 0,
+			align: ALIGN,
+			source: None,
+			tags: Vec::new(),
+			compressed: None,
+		});
+	}
+
+	if let Some(uid) = matches.value_of("owner").map(|uid| parse_id("--owner", uid)) {
+		for entry in entries.iter_mut() {
+			entry.uid = uid;
+		}
+	}
+
+	if let Some(gid) = matches.value_of("group").map(|gid| parse_id("--group", gid)) {
+		for entry in entries.iter_mut() {
+			entry.gid = gid;
+		}
+	}
+
+	if matches.is_present("validate-part-list") {
+		validate::check_drivers(&entries);
+	}
+
+	if let Some(threshold) = warn_large {
+		for entry in entries.iter().filter(|entry| entry.data.len() as u64 > threshold) {
+			warning!("entry {} is {} bytes, which is larger than --warn-large {}", entry.name, entry.data.len(), threshold);
+		}
+	}
+
+	check_empty_entries(&entries, matches.is_present("error-on-empty"));
+
+	check_type_limits(&entries, &type_limits);
+
+	if !matches.is_present("no-validate") {
+		validate::run(&entries, matches.is_present("require-elf"));
+	}
+
+	if matches.is_present("validate-entry-point") {
+		validate::check_entry_point(&entries[0], matches.is_present("require-elf"));
+	}
+
+	if matches.is_present("omit-special-names") {
+		for entry in entries.iter_mut().filter(|entry| entry.typ.is_required()) {
+			entry.name = "";
+		}
+	}
+
+	if matches.is_present("ascii-names") {
+		check_ascii_names(&entries);
+	}
+
+	if matches.is_present("case-insensitive-names") {
+		check_case_collisions(&entries);
+	}
+
+	if let Some(max) = matches.value_of("max-total-name-bytes").map(|max| parse_size("--max-total-name-bytes", max)) {
+		check_name_budget(&entries, max, name_align);
+	}
+
+	let mut flags = 0;
+	if matches.is_present("compact") {
+		flags |= FLAG_COMPACT;
+	}
+
+	if matches.is_present("store-ownership") || matches.is_present("owner") || matches.is_present("group") {
+		flags |= FLAG_OWNERSHIP;
+	}
+
+	if matches.is_present("trailer-magic") {
+		flags |= FLAG_TRAILER;
+	}
+
+	if matches.is_present("table-at-end") {
+		flags |= FLAG_TABLE_AT_END;
+	}
+
+	if matches.is_present("relative-offsets") {
+		flags |= FLAG_RELATIVE;
+	}
+
+	if matches.is_present("trim-trailing-zeros") {
+		flags |= FLAG_TRIM_ZEROS;
+	}
+
+	let base_offset = matches.value_of("base-offset").map(|offset| parse_size("--base-offset", offset));
+	if base_offset.is_some() {
+		flags |= FLAG_BASE_OFFSET;
+	}
+
+	let data_order = match matches.value_of("layout") {
+		_ if matches.is_present("hot-first") => DataOrder::HotFirst,
+		Some("name") => DataOrder::Name,
+		Some("size") => DataOrder::Size,
+		Some("size-desc") => DataOrder::SizeDesc,
+		_ => DataOrder::Entries,
+	};
+
+	let dma_align = matches.value_of("dma-align").map(|align| parse_size("--dma-align", align));
+	if let Some(align) = dma_align {
+		if !valid_alignment(align) {
+			error!("Invalid value for --dma-align: {} is not a power of two which is at least {}", align, ALIGN);
+			exit(1);
+		}
+		flags |= FLAG_DMA_ALIGN;
+	}
+
+	let merkle_block_size = matches.value_of("merkle-block-size").map_or(4096, |size| parse_size("--merkle-block-size", size));
+	if !merkle::valid_block_size(merkle_block_size) {
+		error!("Invalid value for --merkle-block-size: {} is not a power of two which is at least {}", merkle_block_size, merkle::MIN_BLOCK_SIZE);
+		exit(1);
+	}
+	if matches.is_present("merkle") {
+		flags |= FLAG_MERKLE;
+	}
+
+	if matches.is_present("align-names") || matches.is_present("align-data") {
+		flags |= FLAG_ALIGNMENTS;
+	}
+
+	let checksum_algorithm = matches.value_of("checksum-algo").and_then(ChecksumAlgorithm::from_name);
+	if matches.is_present("checksum") || checksum_algorithm.is_some() {
+		flags |= FLAG_CHECKSUM;
+	}
+
+	let mut options = InitrdOptions {
+		flags,
+		data_order,
+		base_offset: base_offset.unwrap_or(0),
+		dma_align: dma_align.unwrap_or(0),
+		merkle_block_size,
+		name_align,
+		data_align,
+		checksum_algorithm: checksum_algorithm.unwrap_or(ChecksumAlgorithm::Crc32),
+	};
+
+	if let Some(candidates) = &size_estimate {
+		compress::estimate(&mut entries, &options, candidates);
+		progress.skipped();
+		return;
+	}
+
+	// compressed once every check of the data has been run on the uncompressed data
+	if let Some(algorithm) = matches.value_of("compress").and_then(Compression::from_name) {
+		compress::all(&mut entries, algorithm, compress_level);
+	} else if matches.is_present("auto-compress") {
+		compress::auto(&mut entries, verbose);
+	}
+
+	// images are only unreadable by loaders without decompression if an entry actually is compressed
+	if entries.iter().any(|entry| entry.compressed.is_some()) {
+		options.flags |= FLAG_COMPRESSED;
+	}
+
+	let stream_format = matches.is_present("stream-format");
+	let encode = || if stream_format {
+		Ok(to_stream(&entries))
+	} else {
+		to_initrd(&entries, &options)
+	};
+
+	let initrd_vec = match encode() {
+		Ok(initrd) => initrd,
+		Err(err) => {
+			error!("Could not generate initrd: {}", err);
+			exit(1);
+		},
+	};
+
+	// encoding already succeeded once with the same entries and options
+	if matches.is_present("self-check-reproducible") && encode().unwrap() != initrd_vec {
+		error!("Generating the initrd twice from the same entries gave different images, the output is not reproducible");
+		exit(exit_code::VALIDATION_FAILED);
+	}
+
+	if matches.is_present("check-elf-alignment") {
+		check_elf_alignment(&initrd_vec);
+	}
+
+	if let Some(name) = matches.value_of("print-offset") {
+		print_entry_offset("generated initrd", &initrd_vec, name);
+	}
+
+	if let Some((path, planned)) = &planned {
+		layout::check_planned(path, planned, &initrd_vec, &parse_or_exit("generated initrd", &initrd_vec));
+	}
+
+	if let Some((path, pinned)) = &pinned {
+		layout::check_pinned(path, pinned, &initrd_vec, &parse_or_exit("generated initrd", &initrd_vec), matches.is_present("allow-layout-drift"));
+	}
+
+	// warnings are checked before writing anything so a failed build leaves no output behind
+	let fail_on_warning = matches.is_present("fail-on-warning");
+	check_warnings(fail_on_warning);
+
+	let lock_path = matches.value_of("write-lock");
+	let built_lock = if locked.is_some() || lock_path.is_some() {
+		Some(lock::from_build(&entries, &initrd_vec))
+	} else {
+		None
+	};
+
+	if let (Some((path, locked)), Some(built_lock)) = (&locked, &built_lock) {
+		if !lock::check(path, locked, built_lock) {
+			exit(exit_code::VALIDATION_FAILED);
+		}
+	}
+
+	let c_header = if matches.value_of("format") == Some("c-header") {
+		let max_align = entries.iter().map(|entry| entry.align).chain(dma_align).chain(Some(data_align)).max().unwrap_or(ALIGN);
+		Some(c_header::generate(&initrd_vec, c_symbol, max_align).into_bytes())
+	} else {
+		None
+	};
+	let out_bytes = c_header.as_deref().unwrap_or(&initrd_vec);
+
+	*WRITING_OUTPUT.lock().unwrap() = true;
+
+	// only created once the initrd is ready to be written, so a failed build does not leave directories behind
+	if let (Some(out_path), true) = (out_path, matches.is_present("create-dirs")) {
+		create_output_dir(out_path);
+	}
+
+	if let (Some(out_path), Some(chunk_size)) = (out_path, split) {
+		for (i, (path, len)) in write_split(out_path, out_bytes, chunk_size).into_iter().enumerate() {
+			if let Some(mode) = output_mode {
+				set_output_mode(&path, mode);
+			}
+
+			// there is no file at -o to sign, so every chunk is signed and can be checked before joining them
+			if let Some(key) = &signing_key {
+				let start = i * chunk_size as usize;
+				sign::write_signature(key, &out_bytes[start..start + len], &sign::signature_path(&path));
+			}
+
+			progress.write(&path, len);
+		}
+	} else if to_stdout {
+		// written directly, since stdout can not be replaced like a file
+		let mut stdout = io::stdout().lock();
+		if let Err(err) = stdout.write_all(out_bytes).and_then(|_| stdout.flush()) {
+			error!("Could not write initrd to stdout: {}", err);
+			exit(exit_code::WRITE_FAILED);
+		}
+
+		progress.write("-", out_bytes.len());
+	} else if let Some(out_path) = out_path {
+		let mut out_file = create_output(out_path);
+		write_output(&mut out_file, out_path, out_bytes);
+
+		if matches.is_present("verify-after-write") {
+			if let Err(err) = out_file.file.sync_all() {
+				out_file.discard();
+				error!("Could not flush output file {}: {}", out_path, err);
+				exit(exit_code::WRITE_FAILED);
+			}
+		}
+
+		persist_output(out_file, out_path);
+
+		if matches.is_present("verify-after-write") {
+			verify_written(out_path, out_bytes, c_header.is_none());
+		}
+
+		if let Some(mode) = output_mode {
+			set_output_mode(out_path, mode);
+		}
+
+		progress.write(out_path, out_bytes.len());
+	}
+
+	// the signature is of the file written to -o, so verify-signature can check it as it is
+	if let (Some(out_path), Some(key), None) = (out_path, &signing_key, split) {
+		sign::write_signature(key, out_bytes, &sign::signature_path(out_path));
+	}
+
+	if let Some(elf_path) = embed_path {
+		embed_in_elf(elf_path, embed_section, &initrd_vec);
+		progress.write(elf_path, initrd_vec.len());
+	}
+
+	if let (Some(path), Some(built_lock)) = (lock_path, &built_lock) {
+		lock::write(path, built_lock);
+	}
+
+	// the layout is read back from the generated initrd so it always matches what was written
+	if let Some(path) = matches.value_of("emit-layout") {
+		layout::write(path, &initrd_vec, &parse_or_exit("generated initrd", &initrd_vec));
+	}
+
+	if let Some(path) = matches.value_of("symbol-map") {
+		layout::write_symbol_map(path, &initrd_vec, &parse_or_exit("generated initrd", &initrd_vec));
+	}
+
+	if let Some(path) = matches.value_of("emit-hashes") {
+		hashes::write(path, entries.iter().map(hashes::from_entry).collect());
+	}
+
+	if matches.is_present("report-waste") {
+		// to_initrd already succeeded with the same entries and options
+		let (name_waste, data_waste) = padding_waste(&entries, &options).unwrap();
+		let total = initrd_vec.len() as u64;
+
+		println!("name padding: {} bytes ({:.2}%)", name_waste, percent(name_waste, total));
+		println!("data padding: {} bytes ({:.2}%)", data_waste, percent(data_waste, total));
+		println!("total padding: {} of {} bytes ({:.2}%)", name_waste + data_waste, total, percent(name_waste + data_waste, total));
+	}
+
+	if let Some(path) = matches.value_of("audit-log") {
+		audit::record(path, target_path.unwrap(), &initrd_vec, entries.len(), matches.is_present("audit-strict"));
+	}
+
+	// some warnings, such as an unsupported --output-mode, can only be printed once the output is written
+	check_warnings(fail_on_warning);
+
+	progress.done(initrd_vec.len());
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn entry<'a>(typ: EntryType, name: &'a str, data: &[u8]) -> Entry<'a> {
+		Entry {
+			typ,
+			name,
+			data: data.to_vec(),
+			uid: 0,
+			gid: 0,
+			align: ALIGN,
+			source: None,
+			tags: Vec::new(),
+			compressed: None,
+		}
+	}
+
+	fn entries() -> Vec<Entry<'static>> {
+		// the required entries share a name with each other and with an additional entry
+		vec![
+			entry(EntryType::EarlyInit, "init", b"early-init"),
+			entry(EntryType::PartList, "init", b"part-list"),
+			entry(EntryType::FsSever, "fs", b"fs-server"),
+			entry(EntryType::HwAccessServer, "hw", b"hwaccess-server"),
+			entry(EntryType::Any, "driver", b"first driver"),
+			entry(EntryType::Any, "init", b"other init"),
+			entry(EntryType::Any, "driver", b"second driver"),
+			entry(EntryType::Any, "other", b"other"),
+			entry(EntryType::Any, "driver", b"third driver"),
+		]
+	}
+
+	fn data<'a>(entries: &'a [Entry]) -> Vec<&'a [u8]> {
+		entries.iter().map(|entry| &entry.data[..]).collect()
+	}
+
+	#[test]
+	fn dedupe_first_wins() {
+		let mut entries = entries();
+		dedupe_names(&mut entries, false, false);
+		assert_eq!(data(&entries), [
+			&b"early-init"[..], b"part-list", b"fs-server", b"hwaccess-server", b"first driver", b"other init", b"other",
+		]);
+	}
+
+	#[test]
+	fn dedupe_last_wins() {
+		let mut entries = entries();
+		dedupe_names(&mut entries, true, false);
+		assert_eq!(data(&entries), [
+			&b"early-init"[..], b"part-list", b"fs-server", b"hwaccess-server", b"other init", b"other", b"third driver",
+		]);
 	}
 }
@@ -0,0 +1,419 @@
+// toml manifest describing every file to include in the initrd, as an alternative to passing them all as arguments
+//
+//	early-init = "build/early-init"
+//	part-list = "build/part-list"
+//	fs-server = "build/fs-server"
+//	hwaccess-server = "build/hwaccess-server"
+//
+//	[[file]]
+//	path = "build/drivers/ext2"
+//	name = "drivers/ext2"
+//	type = "any"
+//	align = 4096
+//	tags = { role = "driver" }
+//
+// name defaults to the path, type defaults to any, align defaults to 8, and tags defaults to none
+//
+// a manifest can include other manifests with
+//
+//	include = ["drivers.toml"]
+//
+// which are resolved relative to the directory of the including manifest, their files are added after the
+// including manifest's files, and a required entry can only be given by one of them
+//
+// --json-stdin reads the same files as a json array, where the required entries are the objects with their type
+//
+//	[{"path": "build/early-init", "type": "early-init"}, {"path": "build/drivers/ext2", "name": "drivers/ext2"}]
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::process::exit;
+
+use serde::{Deserialize, Serialize};
+
+use gen_initrd::{valid_alignment, EntryType, ALIGN};
+
+use crate::{exit_code, tags};
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Manifest {
+	#[serde(default, skip_serializing_if = "Vec::is_empty")]
+	pub include: Vec<String>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub early_init: Option<String>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub part_list: Option<String>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub fs_server: Option<String>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub hwaccess_server: Option<String>,
+	#[serde(default, rename = "file", skip_serializing_if = "Vec::is_empty")]
+	pub files: Vec<ManifestFile>,
+	// path of this manifest and every manifest it includes
+	#[serde(skip)]
+	pub sources: Vec<String>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct ManifestFile {
+	pub path: String,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub name: Option<String>,
+	#[serde(rename = "type", skip_serializing_if = "Option::is_none")]
+	pub typ: Option<String>,
+	// alignment of the entry's data in the initrd
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub align: Option<u64>,
+	// key=value tags stored in the tags entry
+	#[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+	pub tags: BTreeMap<String, String>,
+}
+
+impl Manifest {
+	fn required_path_mut(&mut self, typ: EntryType) -> Option<&mut Option<String>> {
+		match typ {
+			EntryType::EarlyInit => Some(&mut self.early_init),
+			EntryType::PartList => Some(&mut self.part_list),
+			EntryType::FsSever => Some(&mut self.fs_server),
+			EntryType::HwAccessServer => Some(&mut self.hwaccess_server),
+			EntryType::Any | EntryType::Comment | EntryType::InitArgs | EntryType::SourcePaths | EntryType::Tags => None,
+		}
+	}
+}
+
+impl ManifestFile {
+	pub fn name(&self) -> &str {
+		self.name.as_deref().unwrap_or(&self.path)
+	}
+}
+
+fn load_one(path: &str) -> Manifest {
+	let text = match fs::read_to_string(path) {
+		Ok(text) => text,
+		Err(err) => {
+			error!("Could not read from file {}: {}", path, err);
+			exit(exit_code::MISSING_INPUT);
+		},
+	};
+
+	let manifest: Manifest = match toml::from_str(&text) {
+		Ok(manifest) => manifest,
+		Err(err) => {
+			error!("Invalid manifest {}: {}", path, err);
+			exit(1);
+		},
+	};
+
+	validate_files(&manifest.files, path);
+
+	manifest
+}
+
+// exits if any of files has an unknown type or alignment, or has the same name as another,
+// source describes where the files were read from
+fn validate_files(files: &[ManifestFile], source: &str) {
+	for (i, file) in files.iter().enumerate() {
+		if let Some(typ) = &file.typ {
+			if EntryType::from_name(typ).is_none() {
+				error!("Invalid manifest {}: file {} has unknown entry type {}", source, file.path, typ);
+				exit(1);
+			}
+		}
+
+		if let Some(align) = file.align {
+			if !valid_alignment(align) {
+				error!("Invalid manifest {}: file {} has alignment {}, which is not a power of two which is at least {}", source, file.path, align, ALIGN);
+				exit(1);
+			}
+		}
+
+		for (key, value) in file.tags.iter() {
+			if let Err(problem) = tags::check(key, value) {
+				error!("Invalid manifest {}: file {} has tag {}={}, which can not be stored since {}", source, file.path, key, value, problem);
+				exit(1);
+			}
+		}
+
+		if let Some(other) = files[..i].iter().find(|other| other.name() == file.name()) {
+			error!("Invalid manifest {}: files {} and {} are both called {}", source, other.path, file.path, file.name());
+			exit(1);
+		}
+	}
+}
+
+// reads a json array of files from stdin, objects with the type of a required entry are used as that entry
+pub fn from_json_stdin() -> Manifest {
+	let mut text = String::new();
+	if let Err(err) = io::stdin().read_to_string(&mut text) {
+		error!("Could not read from stdin: {}", err);
+		exit(1);
+	}
+
+	// serde_json errors include the line and column
+	let files: Vec<ManifestFile> = match serde_json::from_str(&text) {
+		Ok(files) => files,
+		Err(err) => {
+			error!("Invalid manifest stdin: {}", err);
+			exit(1);
+		},
+	};
+
+	from_files(files, "stdin")
+}
+
+// creates a manifest from a list of files, files with the type of a required entry are used as that entry,
+// source describes where the files were read from
+pub fn from_files(files: Vec<ManifestFile>, source: &str) -> Manifest {
+	validate_files(&files, source);
+
+	let mut manifest = Manifest::default();
+
+	for file in files {
+		// types were checked by validate_files
+		let typ = file.typ.as_deref().and_then(EntryType::from_name).unwrap_or(EntryType::Any);
+
+		let required = match manifest.required_path_mut(typ) {
+			Some(required) => required,
+			None => {
+				manifest.files.push(file);
+				continue;
+			},
+		};
+
+		if required.is_some() {
+			error!("Invalid manifest {}: more than one file has type {}", source, typ.name());
+			exit(1);
+		}
+
+		// required entries are always named by their path, use the default alignment, and have no tags
+		if file.name.is_some() || file.align.is_some() || !file.tags.is_empty() {
+			error!("Invalid manifest {}: {} is the {}, which can not be given a name, alignment, or tags", source, file.path, typ.name());
+			exit(1);
+		}
+
+		*required = Some(file.path);
+	}
+
+	manifest
+}
+
+// loads the manifest at path and everything it includes, stack holds the manifests currently being loaded
+fn load_included(path: &str, stack: &mut Vec<PathBuf>) -> Manifest {
+	let canonical = match fs::canonicalize(path) {
+		Ok(canonical) => canonical,
+		Err(err) => {
+			error!("Could not read from file {}: {}", path, err);
+			exit(exit_code::MISSING_INPUT);
+		},
+	};
+
+	if stack.contains(&canonical) {
+		error!("Manifest {} includes itself", path);
+		exit(1);
+	}
+
+	let mut manifest = load_one(path);
+	manifest.sources.push(path.to_owned());
+
+	let dir = Path::new(path).parent().unwrap_or_else(|| Path::new(""));
+
+	stack.push(canonical);
+
+	for include in std::mem::take(&mut manifest.include) {
+		let include_path = dir.join(&include);
+		let include_path = match include_path.to_str() {
+			Some(include_path) => include_path.to_owned(),
+			None => {
+				error!("Invalid manifest {}: include {} is not valid utf-8", path, include);
+				exit(1);
+			},
+		};
+
+		let mut included = load_included(&include_path, stack);
+
+		for typ in EntryType::ALL.iter().copied().filter(EntryType::is_required) {
+			let included_required = included.required_path_mut(typ).unwrap().take();
+			let required = manifest.required_path_mut(typ).unwrap();
+
+			if included_required.is_some() {
+				if required.is_some() {
+					error!("Manifest {} and its include {} both give {}", path, include_path, typ.name());
+					exit(1);
+				}
+
+				*required = included_required;
+			}
+		}
+
+		manifest.files.extend(included.files);
+		manifest.sources.extend(included.sources);
+	}
+
+	stack.pop();
+
+	manifest
+}
+
+// reads the manifest at path and every manifest it includes, exiting if any can not be read or are not valid
+pub fn load(path: &str) -> Manifest {
+	load_included(path, &mut Vec::new())
+}
+
+// every file under dir, sorted so the generated manifest is deterministic
+fn walk_dir(dir: &Path, out: &mut Vec<String>) {
+	let mut children: Vec<_> = match fs::read_dir(dir).and_then(|entries| entries.collect::<Result<Vec<_>, _>>()) {
+		Ok(children) => children.into_iter().map(|child| child.path()).collect(),
+		Err(err) => {
+			error!("Could not read directory {}: {}", dir.display(), err);
+			exit(exit_code::MISSING_INPUT);
+		},
+	};
+	children.sort();
+
+	for child in children {
+		if child.is_dir() {
+			walk_dir(&child, out);
+		} else {
+			match child.to_str() {
+				Some(path) => out.push(path.to_owned()),
+				None => warning!("skipping {}, its path is not valid utf-8", child.display()),
+			}
+		}
+	}
+}
+
+// creates a manifest including every file under dir, named by their path relative to dir,
+// files named after one of the required entry types are used as that entry
+pub fn from_dir(dir: &str) -> Manifest {
+	let mut paths = Vec::new();
+	walk_dir(Path::new(dir), &mut paths);
+
+	let mut manifest = Manifest::default();
+
+	for path in paths {
+		let file_name = Path::new(&path).file_name().and_then(|name| name.to_str()).unwrap_or("");
+
+		// only the first file with a required entry's name is used as that entry
+		let special_type = EntryType::ALL.iter().copied()
+			.find(|typ| typ.is_required() && typ.name() == file_name);
+
+		if let Some(special) = special_type.and_then(|typ| manifest.required_path_mut(typ)) {
+			if special.is_none() {
+				*special = Some(path);
+				continue;
+			}
+		}
+
+		let name = Path::new(&path).strip_prefix(dir).ok()
+			.and_then(|name| name.to_str())
+			.map(str::to_owned);
+
+		manifest.files.push(ManifestFile {
+			path,
+			name,
+			typ: None,
+			align: None,
+			tags: BTreeMap::new(),
+		});
+	}
+
+	manifest
+}
+
+pub fn to_toml(manifest: &Manifest) -> String {
+	match toml::to_string(manifest) {
+		Ok(text) => text,
+		Err(err) => {
+			error!("Could not serialize manifest: {}", err);
+			exit(1);
+		},
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::env;
+	use std::process::{self, Command};
+
+	// writes each (path, text) under a new temporary directory and returns the directory
+	fn write_manifests(test: &str, manifests: &[(&str, &str)]) -> PathBuf {
+		let dir = env::temp_dir().join(format!("gen-initrd-{}-{}", test, process::id()));
+		for (path, text) in manifests.iter() {
+			let path = dir.join(path);
+			fs::create_dir_all(path.parent().unwrap()).unwrap();
+			fs::write(path, text).unwrap();
+		}
+		dir
+	}
+
+	// load exits on an invalid manifest, so the test binary runs only the given test again, which loads main
+	// in the directory given by GEN_INITRD_TEST_MANIFESTS, and this returns the directory with the exit code and
+	// stderr of that run
+	fn load_in_child(test: &str, manifests: &[(&str, &str)], main: &str) -> (PathBuf, Option<i32>, String) {
+		if let Some(dir) = env::var_os("GEN_INITRD_TEST_MANIFESTS") {
+			load(Path::new(&dir).join(main).to_str().unwrap());
+			process::exit(0);
+		}
+
+		let dir = write_manifests(test, manifests);
+		let output = Command::new(env::current_exe().unwrap())
+			.args([test, "--exact", "--nocapture"])
+			.env("GEN_INITRD_TEST_MANIFESTS", &dir)
+			.output()
+			.unwrap();
+		fs::remove_dir_all(&dir).unwrap();
+
+		(dir, output.status.code(), String::from_utf8_lossy(&output.stderr).into_owned())
+	}
+
+	#[test]
+	fn relative_includes() {
+		let dir = write_manifests("relative-includes", &[
+			("main.toml", "include = [\"sub/drivers.toml\"]\nearly-init = \"early-init\"\n[[file]]\npath = \"main-file\"\n"),
+			("sub/drivers.toml", "include = [\"more.toml\"]\npart-list = \"part-list\"\n[[file]]\npath = \"driver\"\n"),
+			("sub/more.toml", "fs-server = \"fs-server\"\n[[file]]\npath = \"more-file\"\n"),
+		]);
+		let main = dir.join("main.toml");
+		let manifest = load(main.to_str().unwrap());
+		fs::remove_dir_all(&dir).unwrap();
+
+		assert_eq!(manifest.early_init.as_deref(), Some("early-init"));
+		assert_eq!(manifest.part_list.as_deref(), Some("part-list"));
+		assert_eq!(manifest.fs_server.as_deref(), Some("fs-server"));
+		assert_eq!(manifest.hwaccess_server, None);
+		assert_eq!(manifest.files.iter().map(ManifestFile::name).collect::<Vec<_>>(), ["main-file", "driver", "more-file"]);
+
+		let sources: Vec<_> = ["main.toml", "sub/drivers.toml", "sub/more.toml"].iter()
+			.map(|path| dir.join(path).to_str().unwrap().to_owned())
+			.collect();
+		assert_eq!(manifest.sources, sources);
+	}
+
+	#[test]
+	fn include_cycle() {
+		let (dir, code, stderr) = load_in_child("manifest::tests::include_cycle", &[
+			("a.toml", "include = [\"b.toml\"]\n"),
+			("b.toml", "include = [\"a.toml\"]\n"),
+		], "a.toml");
+
+		assert_eq!(code, Some(1));
+		assert!(stderr.contains(&format!("Manifest {} includes itself", dir.join("a.toml").display())), "{}", stderr);
+	}
+
+	#[test]
+	fn required_entry_given_twice() {
+		let (dir, code, stderr) = load_in_child("manifest::tests::required_entry_given_twice", &[
+			("main.toml", "include = [\"other.toml\"]\npart-list = \"part-list\"\n"),
+			("other.toml", "part-list = \"other-part-list\"\n"),
+		], "main.toml");
+
+		assert_eq!(code, Some(1));
+		let expected = format!("Manifest {} and its include {} both give part-list", dir.join("main.toml").display(), dir.join("other.toml").display());
+		assert!(stderr.contains(&expected), "{}", stderr);
+	}
+}
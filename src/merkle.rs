@@ -0,0 +1,209 @@
+// merkle tree over the fixed size blocks of an image, written when FLAG_MERKLE is set
+//
+// every block is hashed with sha256, the last block being padded with zeros, and every level above is the sha256
+// of each pair of hashes below it, a hash without a pair is carried up unchanged, until one hash is left, the root
+//
+// the tree is stored level by level starting with the block hashes, and covers every byte of the image before it
+// with merkle_root in the extended header read as zero, so the header can hold the root of the tree it points to
+
+use std::cmp;
+use std::convert::{TryFrom, TryInto};
+use std::fmt;
+use std::io::{self, Cursor, Read, Seek, SeekFrom};
+use std::mem::size_of;
+
+use sha2::{Digest, Sha256};
+
+use crate::{Hash, HeaderExt};
+
+// the smallest block size, the size of a disk sector
+pub const MIN_BLOCK_SIZE: u64 = 512;
+
+// returns true if the blocks of a tree can be block_size bytes long
+pub fn valid_block_size(block_size: u64) -> bool {
+	block_size.is_power_of_two() && block_size >= MIN_BLOCK_SIZE
+}
+
+// where the tree of an image is and its root, read from the extended header
+#[derive(Debug, Clone, Copy)]
+pub struct MerkleHeader {
+	pub block_size: u64,
+	// offset of the tree in the image, which is also the number of bytes it covers
+	pub offset: u64,
+	pub root: Hash,
+	// the image has a checksum, which is written after the tree so it is read as zero like the root
+	pub checksum: bool,
+}
+
+impl MerkleHeader {
+	pub fn block_count(&self) -> u64 {
+		if self.block_size == 0 { 0 } else { self.offset.div_ceil(self.block_size) }
+	}
+}
+
+fn hash_block(block: &[u8], block_size: usize) -> Hash {
+	let mut hasher = Sha256::new();
+	hasher.update(block);
+	hasher.update(vec![0; block_size - block.len()]);
+	Hash(hasher.finalize().into())
+}
+
+fn hash_pair(left: &Hash, right: &Hash) -> Hash {
+	let mut hasher = Sha256::new();
+	hasher.update(left.0);
+	hasher.update(right.0);
+	Hash(hasher.finalize().into())
+}
+
+// the level above level
+fn parent_level(level: &[Hash]) -> Vec<Hash> {
+	level.chunks(2)
+		.map(|pair| match pair {
+			[left, right] => hash_pair(left, right),
+			[single] => *single,
+			_ => unreachable!(),
+		})
+		.collect()
+}
+
+// hashes of the blocks of image, with the root in the first block read as zero
+fn block_hashes(image: &[u8], block_size: usize) -> Vec<Hash> {
+	let root = HeaderExt::MERKLE_ROOT_OFFSET..HeaderExt::MERKLE_ROOT_OFFSET + size_of::<Hash>();
+
+	image.chunks(block_size)
+		.enumerate()
+		.map(|(i, block)| if i == 0 {
+			let mut block = block.to_vec();
+			block[root.clone()].fill(0);
+			hash_block(&block, block_size)
+		} else {
+			hash_block(block, block_size)
+		})
+		.collect()
+}
+
+// every level of the tree over image, starting with the block hashes and ending with the root
+pub fn levels(image: &[u8], block_size: usize) -> Vec<Vec<Hash>> {
+	let mut levels = vec![block_hashes(image, block_size)];
+
+	while levels.last().unwrap().len() > 1 {
+		let parent = parent_level(levels.last().unwrap());
+		levels.push(parent);
+	}
+
+	levels
+}
+
+pub fn root(levels: &[Vec<Hash>]) -> Hash {
+	levels.last().unwrap()[0]
+}
+
+pub fn encode(levels: &[Vec<Hash>]) -> Vec<u8> {
+	levels.iter().flatten().flat_map(|hash| hash.0).collect()
+}
+
+// number of hashes in every level of a tree over block_count blocks
+fn level_lens(block_count: usize) -> Vec<usize> {
+	let mut lens = vec![block_count];
+	while *lens.last().unwrap() > 1 {
+		let len = lens.last().unwrap().div_ceil(2);
+		lens.push(len);
+	}
+	lens
+}
+
+#[derive(Debug)]
+pub enum MerkleError {
+	BadBlockSize(u64),
+	// the tree goes past the end of the image
+	Truncated,
+	// the hash of a block does not match the hash stored for it
+	BlockMismatch(usize),
+	// a hash in the level above the blocks is not the hash of the pair below it
+	NodeMismatch { level: usize, index: usize },
+	// the top of the tree is not the root stored in the header
+	RootMismatch,
+	// the image could not be read
+	Io(io::Error),
+}
+
+impl fmt::Display for MerkleError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			Self::BadBlockSize(size) => write!(f, "merkle block size {} is not a power of two which is at least {}", size, MIN_BLOCK_SIZE),
+			Self::Truncated => write!(f, "merkle tree goes past the end of the image"),
+			Self::BlockMismatch(i) => write!(f, "block {} does not match its hash in the merkle tree", i),
+			Self::NodeMismatch { level, index } => write!(f, "hash {} of level {} of the merkle tree does not match the hashes below it", index, level),
+			Self::RootMismatch => write!(f, "merkle tree root does not match the root in the header"),
+			Self::Io(err) => write!(f, "could not read the image: {}", err),
+		}
+	}
+}
+
+// checks the tree stored in image against the blocks it covers and the root in the header
+pub fn check(image: &[u8], header: &MerkleHeader) -> Result<(), MerkleError> {
+	check_reader(&mut Cursor::new(image), image.len() as u64, header)
+}
+
+// checks the tree stored in an image image_len bytes long read from reader, reading one block at a time
+pub fn check_reader<R: Read + Seek>(reader: &mut R, image_len: u64, header: &MerkleHeader) -> Result<(), MerkleError> {
+	if !valid_block_size(header.block_size) {
+		return Err(MerkleError::BadBlockSize(header.block_size));
+	}
+
+	let block_size = usize::try_from(header.block_size).map_err(|_| MerkleError::BadBlockSize(header.block_size))?;
+	// the covered bytes always include the extended header holding the root, and the checksum if there is one
+	let header_end = if header.checksum { HeaderExt::CHECKSUM_OFFSET } else { HeaderExt::MERKLE_ROOT_OFFSET } + size_of::<Hash>();
+	if header.offset < header_end as u64 || header.offset > image_len {
+		return Err(MerkleError::Truncated);
+	}
+	let block_count = usize::try_from(header.block_count()).map_err(|_| MerkleError::Truncated)?;
+
+	let tree_len = level_lens(block_count).iter().sum::<usize>() * size_of::<Hash>();
+	if header.offset.checked_add(tree_len as u64).is_none_or(|end| end > image_len) {
+		return Err(MerkleError::Truncated);
+	}
+
+	let mut tree = vec![0; tree_len];
+	reader.seek(SeekFrom::Start(header.offset)).and_then(|_| reader.read_exact(&mut tree)).map_err(MerkleError::Io)?;
+
+	let mut stored = tree.chunks(size_of::<Hash>()).map(|hash| Hash(hash.try_into().unwrap()));
+	let stored_levels: Vec<Vec<Hash>> = level_lens(block_count).into_iter()
+		.map(|len| stored.by_ref().take(len).collect())
+		.collect();
+
+	reader.seek(SeekFrom::Start(0)).map_err(MerkleError::Io)?;
+	let root_range = HeaderExt::MERKLE_ROOT_OFFSET..HeaderExt::MERKLE_ROOT_OFFSET + size_of::<Hash>();
+	let mut block = vec![0; block_size];
+
+	for (i, stored_hash) in stored_levels[0].iter().enumerate() {
+		let len = cmp::min(header.block_size, header.offset - i as u64 * header.block_size) as usize;
+		reader.read_exact(&mut block[..len]).map_err(MerkleError::Io)?;
+
+		// the root is read as zero, which is the first block hashed by block_hashes, and so is the checksum,
+		// which is always in the covered bytes if the image has one
+		if i == 0 {
+			block[root_range.clone()].fill(0);
+			if header.checksum {
+				block[HeaderExt::CHECKSUM_OFFSET..HeaderExt::CHECKSUM_OFFSET + size_of::<Hash>()].fill(0);
+			}
+		}
+
+		if hash_block(&block[..len], block_size) != *stored_hash {
+			return Err(MerkleError::BlockMismatch(i));
+		}
+	}
+
+	for (level, pair) in stored_levels.windows(2).enumerate() {
+		let expected = parent_level(&pair[0]);
+		if let Some(index) = expected.iter().zip(pair[1].iter()).position(|(expected, stored)| expected != stored) {
+			return Err(MerkleError::NodeMismatch { level: level + 1, index });
+		}
+	}
+
+	if root(&stored_levels) != header.root {
+		return Err(MerkleError::RootMismatch);
+	}
+
+	Ok(())
+}
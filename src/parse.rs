@@ -0,0 +1,465 @@
+// reads an initrd image back into its entries
+
+use std::cmp;
+use std::convert::{TryFrom, TryInto};
+use std::fmt;
+use std::mem::size_of;
+use std::str;
+
+use crate::checksum::ChecksumHeader;
+use crate::merkle::MerkleHeader;
+use crate::{data_start, table_entry_size, Compression, Header, HeaderExt, EntryRaw, EntryRawCompact, EntryOwnership, EntryTrim, EntryCompression, Trailer};
+use crate::{ALIGN, MAGIC, MAGIC_EXT, MAGIC_STREAM, FLAG_ALIGNMENTS, FLAG_BASE_OFFSET, FLAG_CHECKSUM, FLAG_COMPACT, FLAG_COMPRESSED, FLAG_DMA_ALIGN, FLAG_MERKLE, FLAG_OWNERSHIP, FLAG_RELATIVE, FLAG_TABLE_AT_END, FLAG_TRAILER, FLAG_TRIM_ZEROS, KNOWN_FLAGS, MAX_TRIMMED_ZEROS, OPTIONAL_FLAGS};
+
+#[derive(Debug)]
+pub struct ParsedEntry<'a> {
+	pub typ: u64,
+	pub name: &'a str,
+	pub data: &'a [u8],
+	// only present if FLAG_OWNERSHIP is set
+	pub ownership: Option<EntryOwnership>,
+	// length of the entry's data, which is longer than data if FLAG_TRIM_ZEROS trimmed trailing zeros from it,
+	// or the length once decompressed if it is compressed
+	pub len: u64,
+	// only present if FLAG_COMPRESSED is set and data is compressed
+	pub compression: Option<Compression>,
+}
+
+impl ParsedEntry<'_> {
+	// the entry's data including any trimmed trailing zeros, or the compressed data if it is compressed,
+	// since this crate does not decompress it
+	pub fn to_vec(&self) -> Vec<u8> {
+		let mut data = self.data.to_vec();
+		if self.compression.is_none() {
+			data.resize(self.len as usize, 0);
+		}
+		data
+	}
+}
+
+#[derive(Debug)]
+pub struct Initrd<'a> {
+	pub flags: u64,
+	// offset of the image in the file containing it, only non zero if FLAG_BASE_OFFSET is set
+	pub base_offset: u64,
+	// bytes of the extended header after the fields this version knows, written by a newer version
+	pub unknown_header_len: u64,
+	// alignment of entry data and its padded length, only non zero if FLAG_DMA_ALIGN is set
+	pub dma_align: u64,
+	// only present if FLAG_MERKLE is set, the tree is not checked by parse, see merkle::check
+	pub merkle: Option<MerkleHeader>,
+	// alignment names and data were placed with, which is ALIGN for both unless FLAG_ALIGNMENTS is set
+	pub name_align: u64,
+	pub data_align: u64,
+	// only present if FLAG_CHECKSUM is set, the checksum is not checked by parse, see checksum::check
+	pub checksum: Option<ChecksumHeader>,
+	pub entries: Vec<ParsedEntry<'a>>,
+}
+
+#[derive(Debug)]
+pub enum ParseError {
+	// the header or entry table goes past the end of the image
+	Truncated,
+	BadMagic(u64),
+	// the image is in the stream format, which has no entry table and is read by the stream module instead
+	StreamFormat,
+	UnknownFlags(u64),
+	// FLAG_TRAILER is set but the image does not end with a matching trailer
+	BadTrailer,
+	NameOutOfBounds(usize),
+	DataOutOfBounds(usize),
+	NameNotUtf8(usize),
+	// the original length of an entry's trimmed data is shorter than the data stored, or more than
+	// MAX_TRIMMED_ZEROS longer, or is not the length stored for compressed data
+	BadTrimLength(usize),
+	// an entry is compressed with an unknown algorithm, or the length of uncompressed data does not match it
+	BadCompression(usize),
+}
+
+impl fmt::Display for ParseError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			Self::Truncated => write!(f, "image is too short to contain its header and entry table"),
+			Self::BadMagic(magic) => write!(f, "bad magic number {:#x}", magic),
+			Self::StreamFormat => write!(f, "image is in the stream format, which has no entry table"),
+			Self::UnknownFlags(flags) => write!(f, "image uses unknown format flags {:#x}", flags),
+			Self::BadTrailer => write!(f, "trailer is missing or does not match the image length, the image is probably truncated"),
+			Self::NameOutOfBounds(i) => write!(f, "name of entry {} is out of bounds", i),
+			Self::DataOutOfBounds(i) => write!(f, "data of entry {} is out of bounds", i),
+			Self::NameNotUtf8(i) => write!(f, "name of entry {} is not valid utf-8", i),
+			Self::BadTrimLength(i) => write!(f, "original length of entry {} is shorter than its data or more than {} bytes longer", i, MAX_TRIMMED_ZEROS),
+			Self::BadCompression(i) => write!(f, "entry {} is compressed with an unknown algorithm or has the wrong uncompressed length", i),
+		}
+	}
+}
+
+// returns the part of bytes at offset with length len, if it is in bounds
+fn region(bytes: &[u8], offset: u64, len: u64) -> Option<&[u8]> {
+	let start = usize::try_from(offset).ok()?;
+	let end = start.checked_add(usize::try_from(len).ok()?)?;
+	bytes.get(start..end)
+}
+
+// where the entry table of an image is, read from its header
+#[derive(Debug, Clone, Copy)]
+pub struct Layout {
+	pub flags: u64,
+	// number of entries
+	pub len: u64,
+	// offset of the entry table
+	pub(crate) table: usize,
+	pub base_offset: u64,
+	pub unknown_header_len: u64,
+	pub dma_align: u64,
+	pub merkle: Option<MerkleHeader>,
+	pub name_align: u64,
+	pub data_align: u64,
+	pub checksum: Option<ChecksumHeader>,
+	// offsets in the table are relative to this if FLAG_RELATIVE is set
+	base: u64,
+}
+
+impl Layout {
+	// position in the image of an offset stored in the entry table
+	pub(crate) fn position(&self, offset: u64) -> Option<u64> {
+		if self.flags & FLAG_RELATIVE != 0 {
+			offset.checked_add(self.base)
+		} else {
+			offset.checked_sub(self.base_offset)
+		}
+	}
+}
+
+// reads the header of an image, without reading any entries
+pub fn parse_layout(bytes: &[u8]) -> Result<Layout, ParseError> {
+	let trailer = bytes.len().checked_sub(Trailer::SIZE).map(|trailer_offset| &bytes[trailer_offset..]);
+	parse_header(bytes, bytes.len() as u64, trailer)
+}
+
+// reads the header of an image image_len bytes long from bytes, which only has to hold the start of the image up
+// to the end of the known fields of the extended header, trailer is the last Trailer::SIZE bytes of the image
+pub(crate) fn parse_header(bytes: &[u8], image_len: u64, trailer: Option<&[u8]>) -> Result<Layout, ParseError> {
+	let header = Header::from_bytes(bytes)?;
+	let mut offset = Header::SIZE;
+
+	let (flags, base_offset, unknown_header_len, dma_align, table_offset, merkle, (name_align, data_align), checksum) = match header.magic {
+		MAGIC => (0, 0, 0, 0, None, None, (ALIGN, ALIGN), None),
+		MAGIC_EXT => {
+			let size = bytes.get(offset..offset + size_of::<u64> ())
+				.map(|size| u64::from_le_bytes(size.try_into().unwrap()))
+				.ok_or(ParseError::Truncated)?;
+			if size < HeaderExt::MIN_SIZE {
+				return Err(ParseError::Truncated);
+			}
+
+			// fields added after the image was written are left as 0
+			let mut ext_bytes = [0; HeaderExt::SIZE];
+			let known_len = cmp::min(size, ext_bytes.len() as u64) as usize;
+			ext_bytes[..known_len].copy_from_slice(bytes.get(offset..offset + known_len).ok_or(ParseError::Truncated)?);
+			let ext = HeaderExt::from_bytes(&ext_bytes).unwrap();
+
+			let unknown_required = ext.flags & !KNOWN_FLAGS & !OPTIONAL_FLAGS;
+			if unknown_required != 0 {
+				return Err(ParseError::UnknownFlags(unknown_required));
+			}
+
+			offset = usize::try_from(ext.size).ok()
+				.and_then(|size| offset.checked_add(size))
+				.ok_or(ParseError::Truncated)?;
+
+			let base_offset = if ext.flags & FLAG_BASE_OFFSET != 0 { ext.base_offset } else { 0 };
+			let dma_align = if ext.flags & FLAG_DMA_ALIGN != 0 { ext.dma_align } else { 0 };
+			let table_offset = if ext.flags & FLAG_TABLE_AT_END != 0 { Some(ext.table_offset) } else { None };
+			let merkle = if ext.flags & FLAG_MERKLE != 0 {
+				Some(MerkleHeader {
+					block_size: ext.merkle_block_size,
+					offset: ext.merkle_offset,
+					root: ext.merkle_root,
+					checksum: ext.flags & FLAG_CHECKSUM != 0,
+				})
+			} else {
+				None
+			};
+			let alignments = if ext.flags & FLAG_ALIGNMENTS != 0 { (ext.name_align, ext.data_align) } else { (ALIGN, ALIGN) };
+			let checksum = if ext.flags & FLAG_CHECKSUM != 0 {
+				Some(ChecksumHeader {
+					algorithm: ext.checksum_algorithm,
+					checksum: ext.checksum,
+				})
+			} else {
+				None
+			};
+			(ext.flags, base_offset, ext.size.saturating_sub(HeaderExt::SIZE as u64), dma_align, table_offset, merkle, alignments, checksum)
+		},
+		MAGIC_STREAM => return Err(ParseError::StreamFormat),
+		magic => return Err(ParseError::BadMagic(magic)),
+	};
+
+	if flags & FLAG_TRAILER != 0 {
+		let trailer = trailer
+			.and_then(|trailer| Trailer::from_bytes(trailer).ok())
+			.ok_or(ParseError::BadTrailer)?;
+
+		if trailer.magic != MAGIC || trailer.len != image_len {
+			return Err(ParseError::BadTrailer);
+		}
+	}
+
+	// the data region starts after the header when the table is at the end, and after the table otherwise
+	let header_end = offset;
+	let table = match table_offset {
+		Some(table_offset) => usize::try_from(table_offset).map_err(|_| ParseError::Truncated)?,
+		None => header_end,
+	};
+
+	let table_end = usize::try_from(header.len).ok()
+		.and_then(|len| len.checked_mul(table_entry_size(flags)))
+		.and_then(|table_len| table_len.checked_add(table))
+		.ok_or(ParseError::Truncated)?;
+
+	if table_end as u64 > image_len {
+		return Err(ParseError::Truncated);
+	}
+
+	let base = data_start(header_end, header.len as usize, flags).ok_or(ParseError::Truncated)?;
+
+	Ok(Layout {
+		flags,
+		base_offset,
+		unknown_header_len,
+		dma_align,
+		merkle,
+		name_align,
+		data_align,
+		checksum,
+		len: header.len,
+		table,
+		base,
+	})
+}
+
+// an entry in the entry table and the optional fields following it
+pub(crate) struct TableEntry {
+	pub(crate) raw: EntryRaw,
+	pub(crate) ownership: Option<EntryOwnership>,
+	pub(crate) trim: Option<EntryTrim>,
+	pub(crate) compression: Option<EntryCompression>,
+}
+
+impl TableEntry {
+	// length of the data of entry i once zero extended or decompressed, and the algorithm it is compressed with
+	pub(crate) fn data_len(&self, i: usize) -> Result<(u64, Option<Compression>), ParseError> {
+		let data_len = self.raw.data_len;
+
+		let compression = match self.compression {
+			Some(compression) if compression.algorithm != 0 => {
+				Some((Compression::from_u64(compression.algorithm).ok_or(ParseError::BadCompression(i))?, compression.len))
+			},
+			Some(compression) if compression.len != data_len => return Err(ParseError::BadCompression(i)),
+			_ => None,
+		};
+
+		match (self.trim, compression) {
+			// compressed data is never trimmed
+			(Some(trim), Some(_)) if trim.original_len != data_len => Err(ParseError::BadTrimLength(i)),
+			(_, Some((algorithm, len))) => Ok((len, Some(algorithm))),
+			(Some(trim), None) if trim.original_len < data_len || trim.original_len - data_len > MAX_TRIMMED_ZEROS => Err(ParseError::BadTrimLength(i)),
+			(Some(trim), None) => Ok((trim.original_len, None)),
+			(None, None) => Ok((data_len, None)),
+		}
+	}
+}
+
+// reads the entry at the start of bytes in an entry table of an image with the given flags, and the optional
+// fields following it
+pub(crate) fn parse_table_entry(bytes: &[u8], flags: u64) -> Result<TableEntry, ParseError> {
+	let raw = if flags & FLAG_COMPACT != 0 {
+		EntryRawCompact::from_bytes(bytes).map(|raw| raw.to_raw())
+	} else {
+		EntryRaw::from_bytes(bytes)
+	}?;
+
+	// offset of the optional fields following the entry
+	let mut field_offset = if flags & FLAG_COMPACT != 0 {
+		EntryRawCompact::SIZE
+	} else {
+		EntryRaw::SIZE
+	};
+
+	let ownership = if flags & FLAG_OWNERSHIP != 0 {
+		let ownership_bytes = bytes.get(field_offset..).ok_or(ParseError::Truncated)?;
+		field_offset += EntryOwnership::SIZE;
+		Some(EntryOwnership::from_bytes(ownership_bytes)?)
+	} else {
+		None
+	};
+
+	let trim = if flags & FLAG_TRIM_ZEROS != 0 {
+		let trim_bytes = bytes.get(field_offset..).ok_or(ParseError::Truncated)?;
+		field_offset += EntryTrim::SIZE;
+		Some(EntryTrim::from_bytes(trim_bytes)?)
+	} else {
+		None
+	};
+
+	let compression = if flags & FLAG_COMPRESSED != 0 {
+		let compression_bytes = bytes.get(field_offset..).ok_or(ParseError::Truncated)?;
+		Some(EntryCompression::from_bytes(compression_bytes)?)
+	} else {
+		None
+	};
+
+	Ok(TableEntry {
+		raw,
+		ownership,
+		trim,
+		compression,
+	})
+}
+
+// reads entry i of an image with the given layout, i must be less than layout.len
+pub fn parse_entry<'a>(bytes: &'a [u8], layout: &Layout, i: usize) -> Result<ParsedEntry<'a>, ParseError> {
+	let offset = layout.table + i * table_entry_size(layout.flags);
+	let table_entry = parse_table_entry(bytes.get(offset..).ok_or(ParseError::Truncated)?, layout.flags)?;
+	let raw = &table_entry.raw;
+
+	let name = layout.position(raw.name)
+		.and_then(|name| region(bytes, name, raw.name_len))
+		.ok_or(ParseError::NameOutOfBounds(i))?;
+	let name = str::from_utf8(name).map_err(|_| ParseError::NameNotUtf8(i))?;
+	let data = layout.position(raw.data)
+		.and_then(|data| region(bytes, data, raw.data_len))
+		.ok_or(ParseError::DataOutOfBounds(i))?;
+
+	let (len, compression) = table_entry.data_len(i)?;
+
+	Ok(ParsedEntry {
+		typ: raw.typ,
+		name,
+		data,
+		ownership: table_entry.ownership,
+		len,
+		compression,
+	})
+}
+
+pub fn parse(bytes: &[u8]) -> Result<Initrd<'_>, ParseError> {
+	let layout = parse_layout(bytes)?;
+
+	let entries = (0..layout.len as usize)
+		.map(|i| parse_entry(bytes, &layout, i))
+		.collect::<Result<_, _>>()?;
+
+	Ok(Initrd {
+		flags: layout.flags,
+		base_offset: layout.base_offset,
+		unknown_header_len: layout.unknown_header_len,
+		dma_align: layout.dma_align,
+		merkle: layout.merkle,
+		name_align: layout.name_align,
+		data_align: layout.data_align,
+		checksum: layout.checksum,
+		entries,
+	})
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::{to_initrd, Entry, EntryType, InitrdOptions, FLAG_OWNERSHIP, FLAG_TRAILER, FLAG_TRIM_ZEROS};
+
+	fn build(flags: u64) -> Vec<u8> {
+		let entries: Vec<Entry> = [("init", &b"early init"[..]), ("fs", &b"fs server\0\0"[..])].iter()
+			.map(|(name, data)| Entry {
+				typ: EntryType::Any,
+				name,
+				data: data.to_vec(),
+				uid: 1,
+				gid: 2,
+				align: ALIGN,
+				source: None,
+				tags: Vec::new(),
+				compressed: None,
+			})
+			.collect();
+
+		let options = InitrdOptions {
+			flags,
+			..InitrdOptions::default()
+		};
+		to_initrd(&entries, &options).unwrap()
+	}
+
+	#[test]
+	fn short_raw_structs() {
+		let bytes: Vec<u8> = (1..=EntryRaw::SIZE as u8).collect();
+
+		for len in 0..EntryRaw::SIZE {
+			assert!(matches!(EntryRaw::from_bytes(&bytes[..len]), Err(ParseError::Truncated)));
+		}
+		for len in 0..EntryRawCompact::SIZE {
+			assert!(matches!(EntryRawCompact::from_bytes(&bytes[..len]), Err(ParseError::Truncated)));
+		}
+		for len in 0..Header::SIZE {
+			assert!(matches!(Header::from_bytes(&bytes[..len]), Err(ParseError::Truncated)));
+		}
+		for len in 0..Trailer::SIZE {
+			assert!(matches!(Trailer::from_bytes(&bytes[..len]), Err(ParseError::Truncated)));
+		}
+
+		// every field is read little endian from its own offset
+		let raw = EntryRaw::from_bytes(&bytes).unwrap();
+		assert_eq!(raw.typ, 0x0807060504030201);
+		assert_eq!(raw.data_len, 0x2827262524232221);
+		let compact = EntryRawCompact::from_bytes(&bytes).unwrap();
+		assert_eq!(compact.typ, 0x04030201);
+		assert_eq!(compact.data_len, 0x14131211);
+	}
+
+	#[test]
+	fn short_table_entries() {
+		let flags = FLAG_OWNERSHIP | FLAG_TRIM_ZEROS | FLAG_COMPRESSED;
+		let image = build(flags);
+		let layout = parse_layout(&image).unwrap();
+		let entry = &image[layout.table..layout.table + table_entry_size(flags)];
+
+		// cut off in the entry, its ownership, its trim, and its compression
+		for len in 0..entry.len() {
+			assert!(matches!(parse_table_entry(&entry[..len], flags), Err(ParseError::Truncated)), "{} bytes", len);
+		}
+		assert!(parse_table_entry(entry, flags).is_ok());
+	}
+
+	#[test]
+	fn truncated_images() {
+		let flag_sets = [0, FLAG_COMPACT, FLAG_OWNERSHIP | FLAG_TRIM_ZEROS | FLAG_COMPRESSED, FLAG_TABLE_AT_END, FLAG_TRAILER];
+
+		for flags in flag_sets.iter().copied() {
+			let image = build(flags);
+			assert!(parse(&image).is_ok());
+
+			for len in 0..image.len() {
+				assert!(parse(&image[..len]).is_err(), "flags {:#x} cut to {} of {} bytes", flags, len, image.len());
+			}
+		}
+	}
+
+	#[test]
+	fn truncated_headers() {
+		let image = build(FLAG_TRAILER);
+
+		// cut in the header, the extended header, and the entry table
+		assert!(matches!(parse_layout(&image[..Header::SIZE - 1]), Err(ParseError::Truncated)));
+		assert!(matches!(parse_layout(&image[..Header::SIZE + 4]), Err(ParseError::Truncated)));
+		assert!(matches!(parse_layout(&image[..Header::SIZE + HeaderExt::MIN_SIZE as usize - 1]), Err(ParseError::Truncated)));
+
+		// the trailer no longer matches the length
+		assert!(matches!(parse_layout(&image[..image.len() - 1]), Err(ParseError::BadTrailer)));
+		assert!(matches!(parse_layout(&image[..image.len() - Trailer::SIZE]), Err(ParseError::BadTrailer)));
+
+		// an entry table longer than the image
+		let mut long_table = build(0);
+		long_table[8..16].copy_from_slice(&u64::MAX.to_le_bytes());
+		assert!(matches!(parse_layout(&long_table), Err(ParseError::Truncated)));
+	}
+}
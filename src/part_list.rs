@@ -0,0 +1,34 @@
+// minimal reader for the part-list file, used to check that it only references drivers in the initrd
+//
+// every line that is not blank or a comment starting with # has the form
+//	<partition> <filesystem driver> <mount point> [options...]
+
+pub struct PartListError {
+	pub line: usize,
+	pub message: &'static str,
+}
+
+// returns each filesystem driver referenced by the part-list, in order
+pub fn referenced_drivers(text: &str) -> Result<Vec<&str>, PartListError> {
+	let mut drivers = Vec::new();
+
+	for (i, line) in text.lines().enumerate() {
+		let line = line.trim();
+		if line.is_empty() || line.starts_with('#') {
+			continue;
+		}
+
+		let mut fields = line.split_whitespace();
+		fields.next();
+
+		match (fields.next(), fields.next()) {
+			(Some(driver), Some(_)) => drivers.push(driver),
+			_ => return Err(PartListError {
+				line: i + 1,
+				message: "expected a partition, filesystem driver, and mount point",
+			}),
+		}
+	}
+
+	Ok(drivers)
+}
@@ -0,0 +1,93 @@
+// --preprocess, which pipes the data of entries of a type through a shell command before adding them to the initrd,
+// and --add-cmd, which adds an entry containing the output of a shell command
+//
+// the output is only reproducible if the commands are, and the whole build fails if a command does
+
+use std::io::Write;
+use std::process::{exit, Command, Stdio};
+use std::thread;
+
+use gen_initrd::EntryType;
+
+// parses the TYPE=CMD value of --preprocess
+pub fn parse_arg(arg: &str) -> (EntryType, &str) {
+	let parsed = arg.split_once('=')
+		.and_then(|(typ, command)| Some((EntryType::from_name(typ)?, command)))
+		.filter(|(_, command)| !command.is_empty());
+
+	match parsed {
+		Some(parsed) => parsed,
+		None => {
+			error!("Invalid value for --preprocess: expected TYPE=CMD where TYPE is an entry type, got {}", arg);
+			exit(1);
+		},
+	}
+}
+
+// parses the NAME:CMD value of --add-cmd
+pub fn parse_add_arg(arg: &str) -> (&str, &str) {
+	let parsed = arg.split_once(':')
+		.filter(|(name, command)| !name.is_empty() && !command.is_empty());
+
+	match parsed {
+		Some(parsed) => parsed,
+		None => {
+			error!("Invalid value for --add-cmd: expected NAME:CMD, got {}", arg);
+			exit(1);
+		},
+	}
+}
+
+// returns what command writes to stdout when given data on stdin, name is the entry being preprocessed
+pub fn run(command: &str, name: &str, data: Vec<u8>) -> Vec<u8> {
+	run_command("Preprocess", command, name, Some(data))
+}
+
+// returns what command writes to stdout, name is the entry it generates
+pub fn generate(command: &str, name: &str) -> Vec<u8> {
+	run_command("Generating", command, name, None)
+}
+
+// kind describes what the command is for in errors, stdin is closed if data is None
+fn run_command(kind: &str, command: &str, name: &str, data: Option<Vec<u8>>) -> Vec<u8> {
+	let child = Command::new("sh")
+		.arg("-c")
+		.arg(command)
+		.stdin(if data.is_some() { Stdio::piped() } else { Stdio::null() })
+		.stdout(Stdio::piped())
+		.spawn();
+
+	let mut child = match child {
+		Ok(child) => child,
+		Err(err) => {
+			error!("Could not run {} command {} for entry {}: {}", kind.to_lowercase(), command, name, err);
+			exit(1);
+		},
+	};
+
+	// stdin is written from another thread so a command that writes before reading everything can not deadlock
+	let writer = data.map(|data| {
+		let mut stdin = child.stdin.take().unwrap();
+		thread::spawn(move || stdin.write_all(&data))
+	});
+
+	let output = match child.wait_with_output() {
+		Ok(output) => output,
+		Err(err) => {
+			error!("Could not run {} command {} for entry {}: {}", kind.to_lowercase(), command, name, err);
+			exit(1);
+		},
+	};
+
+	if !output.status.success() {
+		error!("{} command {} failed for entry {}: {}", kind, command, name, output.status);
+		exit(1);
+	}
+
+	// a command which exits successfully without reading all of its input is fine
+	if let Some(writer) = writer {
+		let _ = writer.join();
+	}
+
+	output.stdout
+}
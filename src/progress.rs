@@ -0,0 +1,92 @@
+// --progress-json, which writes newline delimited json events describing the build to an inherited file descriptor
+//
+//	{"elapsed-ms":0,"event":"start"}
+//	{"bytes":2048,"elapsed-ms":1,"event":"read-file","path":"build/early-init"}
+//	{"bytes":6,"command":"git describe","elapsed-ms":2,"event":"run-command","name":"version"}
+//	{"bytes":4096,"elapsed-ms":3,"event":"write","path":"initrd.img"}
+//	{"bytes":4096,"elapsed-ms":3,"event":"done"}
+//
+// a build skipped by -n ends with a skipped event instead of done, and a build that fails ends without either
+
+use std::fs::File;
+use std::io::Write;
+use std::process::exit;
+use std::time::Instant;
+
+use serde_json::{json, Value};
+
+pub struct Progress {
+	// None if --progress-json was not given, so every event is ignored
+	file: Option<File>,
+	start: Instant,
+}
+
+impl Progress {
+	// fd is the value of --progress-json
+	pub fn new(fd: Option<&str>) -> Self {
+		let file = fd.map(|fd| match fd.parse::<i32>() {
+			Ok(fd) if fd >= 0 => open_fd(fd),
+			_ => {
+				error!("Invalid value for --progress-json: {} is not a file descriptor", fd);
+				exit(1);
+			},
+		});
+
+		Progress {
+			file,
+			start: Instant::now(),
+		}
+	}
+
+	fn emit(&self, event: &str, mut fields: Value) {
+		let mut file = match &self.file {
+			Some(file) => file,
+			None => return,
+		};
+
+		fields["event"] = json!(event);
+		fields["elapsed-ms"] = json!(self.start.elapsed().as_millis() as u64);
+
+		// progress is only informational, so a reader that went away does not fail the build
+		let _ = writeln!(file, "{}", fields);
+	}
+
+	pub fn start(&self) {
+		self.emit("start", json!({}));
+	}
+
+	pub fn read_file(&self, path: &str, bytes: usize) {
+		self.emit("read-file", json!({ "path": path, "bytes": bytes }));
+	}
+
+	// an entry added by --add-cmd, which is only reproducible if its command is
+	pub fn run_command(&self, name: &str, command: &str, bytes: usize) {
+		self.emit("run-command", json!({ "name": name, "command": command, "bytes": bytes }));
+	}
+
+	pub fn write(&self, path: &str, bytes: usize) {
+		self.emit("write", json!({ "path": path, "bytes": bytes }));
+	}
+
+	pub fn skipped(&self) {
+		self.emit("skipped", json!({}));
+	}
+
+	pub fn done(&self, bytes: usize) {
+		self.emit("done", json!({ "bytes": bytes }));
+	}
+}
+
+#[cfg(unix)]
+fn open_fd(fd: i32) -> File {
+	use std::os::unix::io::FromRawFd;
+
+	// the caller makes sure the fd is not also passed to --fd, so nothing else owns it
+	unsafe { File::from_raw_fd(fd) }
+}
+
+#[cfg(not(unix))]
+fn open_fd(_fd: i32) -> File {
+	error!("--progress-json is only supported on unix platforms");
+	exit(1);
+}
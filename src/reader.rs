@@ -0,0 +1,229 @@
+// reads and checks an image from a Read + Seek without loading it into memory, for images too large to read whole
+//
+// the header and entry table are read first, then the name of every entry, the data of entries is only checked
+// to be in bounds, and if the image has a merkle tree every block is read one at a time and checked against it
+
+use std::cmp;
+use std::fmt;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::str;
+
+use crate::checksum::{self, ChecksumError};
+use crate::merkle::{self, MerkleError};
+use crate::parse::{parse_header, parse_table_entry, Layout, ParseError};
+use crate::{table_entry_size, Compression, EntryOwnership, Header, HeaderExt, Trailer};
+
+#[derive(Debug)]
+pub struct ReadEntry {
+	pub typ: u64,
+	pub name: String,
+	// position of the entry's data in the image and the number of bytes stored there
+	pub data_offset: u64,
+	pub data_len: u64,
+	// only present if FLAG_OWNERSHIP is set
+	pub ownership: Option<EntryOwnership>,
+	// length of the entry's data including any trimmed trailing zeros, or once decompressed if it is compressed
+	pub len: u64,
+	// only present if FLAG_COMPRESSED is set and the data is compressed
+	pub compression: Option<Compression>,
+}
+
+#[derive(Debug)]
+pub struct ReadInitrd {
+	pub layout: Layout,
+	pub entries: Vec<ReadEntry>,
+}
+
+#[derive(Debug)]
+pub enum ReadError {
+	Io(io::Error),
+	// the header or entry table is invalid
+	Parse(ParseError),
+	// the name or data of entry index is invalid, name is only known once the name has been read,
+	// and offset is where the invalid region starts in the image
+	Entry { index: usize, name: Option<String>, offset: u64, error: ParseError },
+	Merkle(MerkleError),
+	Checksum(ChecksumError),
+}
+
+impl fmt::Display for ReadError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			Self::Io(err) => write!(f, "could not read the image: {}", err),
+			Self::Parse(err) => write!(f, "{}", err),
+			Self::Entry { name: Some(name), offset, error, .. } => write!(f, "{} (entry {} at offset {:#x})", error, name, offset),
+			Self::Entry { name: None, offset, error, .. } => write!(f, "{} (at offset {:#x})", error, offset),
+			Self::Merkle(err) => write!(f, "{}", err),
+			Self::Checksum(err) => write!(f, "{}", err),
+		}
+	}
+}
+
+impl From<io::Error> for ReadError {
+	fn from(err: io::Error) -> Self {
+		Self::Io(err)
+	}
+}
+
+impl From<ParseError> for ReadError {
+	fn from(err: ParseError) -> Self {
+		Self::Parse(err)
+	}
+}
+
+// reads len bytes at offset, which must be in bounds
+fn read_at<R: Read + Seek>(reader: &mut R, offset: u64, len: usize) -> io::Result<Vec<u8>> {
+	let mut bytes = vec![0; len];
+	reader.seek(SeekFrom::Start(offset))?;
+	reader.read_exact(&mut bytes)?;
+	Ok(bytes)
+}
+
+// position in an image image_len bytes long of a region stored in the entry table, if it is in bounds
+fn region(layout: &Layout, image_len: u64, offset: u64, len: u64) -> Option<u64> {
+	let start = layout.position(offset)?;
+	start.checked_add(len).filter(|end| *end <= image_len)?;
+	Some(start)
+}
+
+// reads the header, entry table, and entry names of an image, and checks it like parse, merkle::check, and checksum::check,
+// returning the first problem found
+pub fn read<R: Read + Seek>(reader: &mut R) -> Result<ReadInitrd, ReadError> {
+	let image_len = reader.seek(SeekFrom::End(0))?;
+
+	// parse_header only needs the fields this version knows, so the header is read up to them
+	let prefix_len = cmp::min(image_len, (Header::SIZE + HeaderExt::SIZE) as u64);
+	let prefix = read_at(reader, 0, prefix_len as usize)?;
+	let trailer = match image_len.checked_sub(Trailer::SIZE as u64) {
+		Some(trailer_offset) => Some(read_at(reader, trailer_offset, Trailer::SIZE)?),
+		None => None,
+	};
+
+	let layout = parse_header(&prefix, image_len, trailer.as_deref())?;
+
+	// parse_header checked the table is in bounds
+	let entry_size = table_entry_size(layout.flags);
+	let table = read_at(reader, layout.table as u64, layout.len as usize * entry_size)?;
+
+	let mut entries = Vec::new();
+
+	for (i, table_entry) in table.chunks(entry_size).enumerate() {
+		let table_entry = parse_table_entry(table_entry, layout.flags)?;
+		let raw = &table_entry.raw;
+		let entry_error = |name: Option<&str>, offset, error| ReadError::Entry { index: i, name: name.map(str::to_owned), offset, error };
+
+		let name_offset = region(&layout, image_len, raw.name, raw.name_len)
+			.ok_or_else(|| entry_error(None, raw.name, ParseError::NameOutOfBounds(i)))?;
+		let name = String::from_utf8(read_at(reader, name_offset, raw.name_len as usize)?)
+			.map_err(|_| entry_error(None, name_offset, ParseError::NameNotUtf8(i)))?;
+
+		let data_offset = region(&layout, image_len, raw.data, raw.data_len)
+			.ok_or_else(|| entry_error(Some(&name), raw.data, ParseError::DataOutOfBounds(i)))?;
+
+		let (len, compression) = table_entry.data_len(i).map_err(|error| entry_error(Some(&name), data_offset, error))?;
+
+		entries.push(ReadEntry {
+			typ: raw.typ,
+			name,
+			data_offset,
+			data_len: raw.data_len,
+			ownership: table_entry.ownership,
+			len,
+			compression,
+		});
+	}
+
+	if let Some(tree) = layout.merkle {
+		merkle::check_reader(reader, image_len, &tree).map_err(ReadError::Merkle)?;
+	}
+
+	// a checksum with an algorithm from a newer version is ignored like other optional information
+	if let Some(header) = layout.checksum {
+		match checksum::check_reader(reader, image_len, &header) {
+			Ok(()) | Err(ChecksumError::UnknownAlgorithm(_)) => (),
+			Err(err) => return Err(ReadError::Checksum(err)),
+		}
+	}
+
+	Ok(ReadInitrd {
+		layout,
+		entries,
+	})
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::io::Cursor;
+
+	use crate::parse::parse;
+	use crate::{merkle, to_initrd, Entry, EntryType, InitrdOptions, ALIGN, FLAG_CHECKSUM, FLAG_MERKLE};
+
+	fn entry<'a>(name: &'a str, data: &[u8]) -> Entry<'a> {
+		Entry {
+			typ: EntryType::Any,
+			name,
+			data: data.to_vec(),
+			uid: 0,
+			gid: 0,
+			align: ALIGN,
+			source: None,
+			tags: Vec::new(),
+			compressed: None,
+		}
+	}
+
+	fn entries() -> Vec<Entry<'static>> {
+		vec![entry("init", &[1; 3000]), entry("fs", b"fs server")]
+	}
+
+	#[test]
+	fn matches_parse() {
+		let image = to_initrd(&entries(), &InitrdOptions::default()).unwrap();
+		let initrd = read(&mut Cursor::new(&image)).unwrap();
+		let parsed = parse(&image).unwrap();
+
+		assert_eq!(initrd.entries.len(), parsed.entries.len());
+		for (read, parsed) in initrd.entries.iter().zip(parsed.entries.iter()) {
+			assert_eq!(read.name, parsed.name);
+			let start = read.data_offset as usize;
+			assert_eq!(&image[start..start + read.data_len as usize], parsed.data);
+		}
+	}
+
+	#[test]
+	fn truncated() {
+		let image = to_initrd(&entries(), &InitrdOptions::default()).unwrap();
+
+		// the data of the last entry ends the image
+		let err = read(&mut Cursor::new(&image[..image.len() - 1])).unwrap_err();
+		assert!(matches!(err, ReadError::Entry { index: 1, name: Some(ref name), error: ParseError::DataOutOfBounds(1), .. } if name == "fs"), "{}", err);
+
+		let err = read(&mut Cursor::new(&image[..Header::SIZE])).unwrap_err();
+		assert!(matches!(err, ReadError::Parse(ParseError::Truncated)), "{}", err);
+	}
+
+	#[test]
+	fn corrupt_entry_data() {
+		let checksum = InitrdOptions {
+			flags: FLAG_CHECKSUM,
+			..InitrdOptions::default()
+		};
+		let mut image = to_initrd(&entries(), &checksum).unwrap();
+		let offset = read(&mut Cursor::new(&image)).unwrap().entries[1].data_offset as usize;
+		image[offset] ^= 1;
+		let err = read(&mut Cursor::new(&image)).unwrap_err();
+		assert!(matches!(err, ReadError::Checksum(ChecksumError::Mismatch { .. })), "{}", err);
+
+		let tree = InitrdOptions {
+			flags: FLAG_MERKLE,
+			merkle_block_size: merkle::MIN_BLOCK_SIZE,
+			..InitrdOptions::default()
+		};
+		let mut image = to_initrd(&entries(), &tree).unwrap();
+		let offset = read(&mut Cursor::new(&image)).unwrap().entries[0].data_offset as usize;
+		image[offset] ^= 1;
+		let err = read(&mut Cursor::new(&image)).unwrap_err();
+		assert!(matches!(err, ReadError::Merkle(MerkleError::BlockMismatch(_))), "{}", err);
+	}
+}
@@ -0,0 +1,74 @@
+// detached ed25519 signatures over initrd images
+//
+// keys are read as pem files, as generated by `openssl genpkey -algorithm ed25519`
+// and `openssl pkey -pubout`, and signatures are stored as the raw 64 signature bytes
+
+use std::convert::TryFrom;
+use std::fs;
+use std::process::exit;
+
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+use ed25519_dalek::pkcs8::{DecodePrivateKey, DecodePublicKey};
+
+use crate::exit_code;
+use crate::interrupt;
+
+// path the signature of the image at image_path is written to
+pub fn signature_path(image_path: &str) -> String {
+	format!("{}.sig", image_path)
+}
+
+fn read_key_file(path: &str) -> String {
+	match fs::read_to_string(path) {
+		Ok(pem) => pem,
+		Err(err) => {
+			error!("Could not read from key file {}: {}", path, err);
+			exit(exit_code::MISSING_INPUT);
+		},
+	}
+}
+
+pub fn load_signing_key(path: &str) -> SigningKey {
+	match SigningKey::from_pkcs8_pem(&read_key_file(path)) {
+		Ok(key) => key,
+		Err(err) => {
+			error!("Could not parse private key {}: {}", path, err);
+			exit(1);
+		},
+	}
+}
+
+pub fn load_verifying_key(path: &str) -> VerifyingKey {
+	match VerifyingKey::from_public_key_pem(&read_key_file(path)) {
+		Ok(key) => key,
+		Err(err) => {
+			error!("Could not parse public key {}: {}", path, err);
+			exit(1);
+		},
+	}
+}
+
+pub fn write_signature(key: &SigningKey, image: &[u8], sig_path: &str) {
+	let signature = key.sign(image);
+
+	if let Err(err) = interrupt::write(sig_path, signature.to_bytes()) {
+		error!("Could not write signature to {}: {}", sig_path, err);
+		exit(exit_code::WRITE_FAILED);
+	}
+}
+
+// returns true if the signature at sig_path is a valid signature of image
+pub fn check_signature(key: &VerifyingKey, image: &[u8], sig_path: &str) -> bool {
+	let sig_bytes = match fs::read(sig_path) {
+		Ok(bytes) => bytes,
+		Err(err) => {
+			error!("Could not read from signature file {}: {}", sig_path, err);
+			exit(exit_code::MISSING_INPUT);
+		},
+	};
+
+	match Signature::try_from(&sig_bytes[..]) {
+		Ok(signature) => key.verify(image, &signature).is_ok(),
+		Err(_) => false,
+	}
+}
@@ -0,0 +1,55 @@
+// --record-source-paths, which stores the path every entry read from a file was read from in an unnamed
+// source-paths entry, so names can be shortened for the kernel without losing where each entry came from
+//
+// the entry has a line for every entry with a source path, made of the index of the entry in the entry table,
+// a space, and the path
+//
+//	0 build/early-init
+//	4 build/drivers/ext2
+
+use std::path::Path;
+use std::process::exit;
+
+use gen_initrd::Entry;
+
+// returns the data of the source-paths entry for entries, which must be in the order they are stored
+pub fn encode(entries: &[Entry]) -> Vec<u8> {
+	let mut text = String::new();
+
+	for (i, entry) in entries.iter().enumerate() {
+		let source = match entry.source {
+			Some(source) => source,
+			None => continue,
+		};
+
+		if source.contains('\n') {
+			error!("Source path of entry {} contains a newline, so it can not be recorded", entry.name);
+			exit(1);
+		}
+
+		text += &format!("{} {}\n", i, source);
+	}
+
+	// the build machine's directory layout ends up in the image
+	if let Some(absolute) = entries.iter().filter_map(|entry| entry.source).find(|source| Path::new(source).is_absolute()) {
+		warning!("--record-source-paths is recording absolute paths such as {}, so the initrd is not reproducible in another directory", absolute);
+	}
+
+	text.into_bytes()
+}
+
+// returns the index and source path of every entry in the data of a source-paths entry, lines which can not
+// be read are skipped since the paths are only informational
+pub fn decode(data: &[u8]) -> Vec<(usize, &str)> {
+	let text = match std::str::from_utf8(data) {
+		Ok(text) => text,
+		Err(_) => return Vec::new(),
+	};
+
+	text.lines()
+		.filter_map(|line| {
+			let (index, path) = line.split_once(' ')?;
+			Some((index.parse().ok()?, path))
+		})
+		.collect()
+}
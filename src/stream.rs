@@ -0,0 +1,205 @@
+// reads an image in the stream format written by to_stream front to back, without seeking,
+// so it can be read as it is decompressed or received
+
+use std::convert::TryFrom;
+use std::fmt;
+use std::io::{self, Read};
+
+use crate::parse::ParsedEntry;
+use crate::{align_up, Header, StreamEntryRaw, ALIGN, MAGIC_STREAM};
+
+// returns true if bytes starts with the header of an image in the stream format
+pub fn is_stream(bytes: &[u8]) -> bool {
+	Header::from_bytes(bytes).is_ok_and(|header| header.magic == MAGIC_STREAM)
+}
+
+#[derive(Debug)]
+pub struct StreamEntry {
+	pub typ: u64,
+	pub name: String,
+	pub data: Vec<u8>,
+}
+
+impl StreamEntry {
+	// the entry as if it were read from an entry table, so it can be shown like other entries
+	pub fn as_parsed(&self) -> ParsedEntry<'_> {
+		ParsedEntry {
+			typ: self.typ,
+			name: &self.name,
+			data: &self.data,
+			ownership: None,
+			len: self.data.len() as u64,
+			compression: None,
+		}
+	}
+}
+
+#[derive(Debug)]
+pub enum StreamError {
+	Io(io::Error),
+	// the stream ends before the header or an entry does
+	Truncated,
+	BadMagic(u64),
+	NameNotUtf8(usize),
+}
+
+impl fmt::Display for StreamError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			Self::Io(err) => write!(f, "{}", err),
+			Self::Truncated => write!(f, "stream ends before the last entry"),
+			Self::BadMagic(magic) => write!(f, "bad magic number {:#x} for a stream image", magic),
+			Self::NameNotUtf8(i) => write!(f, "name of entry {} is not valid utf-8", i),
+		}
+	}
+}
+
+impl From<io::Error> for StreamError {
+	fn from(err: io::Error) -> Self {
+		if err.kind() == io::ErrorKind::UnexpectedEof {
+			Self::Truncated
+		} else {
+			Self::Io(err)
+		}
+	}
+}
+
+// iterates over the entries of a stream image in the order they are stored
+pub struct StreamReader<R> {
+	reader: R,
+	// number of entries in the stream
+	len: u64,
+	// index of the next entry to read
+	next: u64,
+}
+
+impl<R: Read> StreamReader<R> {
+	// reads the header of the stream
+	pub fn new(mut reader: R) -> Result<Self, StreamError> {
+		let mut header_bytes = [0; Header::SIZE];
+		reader.read_exact(&mut header_bytes)?;
+		let header = Header::from_bytes(&header_bytes).unwrap();
+
+		if header.magic != MAGIC_STREAM {
+			return Err(StreamError::BadMagic(header.magic));
+		}
+
+		Ok(StreamReader {
+			reader,
+			len: header.len,
+			next: 0,
+		})
+	}
+
+	pub fn len(&self) -> u64 {
+		self.len
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.len == 0
+	}
+
+	// reads len bytes followed by the padding up to the next multiple of ALIGN
+	fn read_padded(&mut self, len: u64) -> Result<Vec<u8>, StreamError> {
+		// a corrupt length fails once the stream ends instead of allocating all of it up front
+		let mut bytes = Vec::new();
+		(&mut self.reader).take(len).read_to_end(&mut bytes)?;
+		if (bytes.len() as u64) < len {
+			return Err(StreamError::Truncated);
+		}
+
+		let padding = align_up(len, ALIGN) - len;
+		io::copy(&mut (&mut self.reader).take(padding), &mut io::sink())?;
+
+		Ok(bytes)
+	}
+
+	fn read_entry(&mut self) -> Result<StreamEntry, StreamError> {
+		let mut raw_bytes = [0; StreamEntryRaw::SIZE];
+		self.reader.read_exact(&mut raw_bytes)?;
+		let raw = StreamEntryRaw::from_bytes(&raw_bytes).unwrap();
+
+		let i = usize::try_from(self.next).unwrap_or(usize::MAX);
+		let name = String::from_utf8(self.read_padded(raw.name_len)?).map_err(|_| StreamError::NameNotUtf8(i))?;
+		let data = self.read_padded(raw.data_len)?;
+
+		Ok(StreamEntry {
+			typ: raw.typ,
+			name,
+			data,
+		})
+	}
+}
+
+impl<R: Read> Iterator for StreamReader<R> {
+	type Item = Result<StreamEntry, StreamError>;
+
+	fn next(&mut self) -> Option<Self::Item> {
+		if self.next == self.len {
+			return None;
+		}
+
+		let entry = self.read_entry();
+		// nothing after an error can be read, since where the next entry starts is unknown
+		self.next = if entry.is_ok() { self.next + 1 } else { self.len };
+
+		Some(entry)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::{to_stream, Entry, EntryType};
+
+	fn entry<'a>(typ: EntryType, name: &'a str, data: &[u8]) -> Entry<'a> {
+		Entry {
+			typ,
+			name,
+			data: data.to_vec(),
+			uid: 0,
+			gid: 0,
+			align: ALIGN,
+			source: None,
+			tags: Vec::new(),
+			compressed: None,
+		}
+	}
+
+	#[test]
+	fn round_trip() {
+		// lengths around ALIGN so some names and data are padded and some are not
+		let entries = vec![
+			entry(EntryType::EarlyInit, "early-init", &[1; 7]),
+			entry(EntryType::PartList, "part-list-", &[2; 8]),
+			entry(EntryType::Any, "", &[]),
+			entry(EntryType::Any, "drivers/ext2", &[3; 9]),
+		];
+		let image = to_stream(&entries);
+		assert!(is_stream(&image));
+
+		let reader = StreamReader::new(&image[..]).unwrap();
+		assert_eq!(reader.len(), 4);
+		let read: Vec<StreamEntry> = reader.collect::<Result<_, _>>().unwrap();
+
+		assert_eq!(read.len(), entries.len());
+		for (read, entry) in read.iter().zip(entries.iter()) {
+			assert_eq!(read.typ, entry.typ as u64);
+			assert_eq!(read.name, entry.name);
+			assert_eq!(read.data, entry.data);
+		}
+	}
+
+	#[test]
+	fn truncated() {
+		let image = to_stream(&[entry(EntryType::Any, "a", b"data"), entry(EntryType::Any, "b", b"more data")]);
+
+		assert!(matches!(StreamReader::new(&image[..Header::SIZE - 1]), Err(StreamError::Truncated)));
+
+		// the first entry can still be read, and nothing is returned after the error
+		let mut reader = StreamReader::new(&image[..image.len() - ALIGN as usize]).unwrap();
+		assert_eq!(reader.next().unwrap().unwrap().data, b"data");
+		assert!(matches!(reader.next(), Some(Err(StreamError::Truncated))));
+		assert!(reader.next().is_none());
+	}
+}
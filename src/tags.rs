@@ -0,0 +1,96 @@
+// key=value tags classifying entries, such as role=driver, given by the tags of a manifest file or by
+// --tag NAME:KEY=VALUE, which are stored in an unnamed tags entry so loaders can select entries by tag
+// without the image format or the closed set of entry types changing
+//
+// the entry has a line for every tag, made of the index of the entry in the entry table, a space, and the tag
+//
+//	4 role=driver
+//	4 optional=true
+
+use std::process::exit;
+
+use gen_initrd::Entry;
+
+// returns why key and value can not be stored as a tag, keys can not contain whitespace or = so every line
+// can be split at its first =, and values can contain anything other than a newline
+pub fn check(key: &str, value: &str) -> Result<(), &'static str> {
+	if key.is_empty() {
+		Err("the key is empty")
+	} else if key.contains(|c: char| c.is_whitespace() || c == '=') {
+		Err("the key contains whitespace or =")
+	} else if value.contains('\n') {
+		Err("the value contains a newline")
+	} else {
+		Ok(())
+	}
+}
+
+// parses the NAME:KEY=VALUE value of --tag, names can contain : since the key can not
+pub fn parse_arg(arg: &str) -> (&str, &str, &str) {
+	let parsed = arg.split_once('=')
+		.and_then(|(name_key, value)| Some((name_key.rsplit_once(':')?, value)))
+		.filter(|((name, _), _)| !name.is_empty());
+
+	let ((name, key), value) = match parsed {
+		Some(parsed) => parsed,
+		None => {
+			error!("Invalid value for --tag: expected NAME:KEY=VALUE, got {}", arg);
+			exit(1);
+		},
+	};
+
+	if let Err(problem) = check(key, value) {
+		error!("Invalid value for --tag {}: {}", arg, problem);
+		exit(1);
+	}
+
+	(name, key, value)
+}
+
+// adds every tag given by --tag to the entry it names, which must be the final name of an entry
+pub fn apply_args<'a>(entries: &mut [Entry<'a>], args: &[(&str, &'a str, &'a str)]) {
+	for (name, key, value) in args.iter() {
+		match entries.iter_mut().find(|entry| entry.name == *name) {
+			Some(entry) => entry.tags.push((key, value)),
+			None => {
+				error!("Invalid value for --tag: there is no entry called {}", name);
+				exit(1);
+			},
+		}
+	}
+}
+
+// returns the data of the tags entry for entries, which must be in the order they are stored
+pub fn encode(entries: &[Entry]) -> Vec<u8> {
+	let mut text = String::new();
+
+	for (i, entry) in entries.iter().enumerate() {
+		for (j, (key, value)) in entry.tags.iter().enumerate() {
+			if entry.tags[..j].iter().any(|(other, _)| other == key) {
+				error!("Entry {} is given the tag {} more than once", entry.name, key);
+				exit(1);
+			}
+
+			text += &format!("{} {}={}\n", i, key, value);
+		}
+	}
+
+	text.into_bytes()
+}
+
+// returns the index, key, and value of every tag in the data of a tags entry, lines which can not be read
+// are skipped like those of source paths
+pub fn decode(data: &[u8]) -> Vec<(usize, &str, &str)> {
+	let text = match std::str::from_utf8(data) {
+		Ok(text) => text,
+		Err(_) => return Vec::new(),
+	};
+
+	text.lines()
+		.filter_map(|line| {
+			let (index, tag) = line.split_once(' ')?;
+			let (key, value) = tag.split_once('=')?;
+			Some((index.parse().ok()?, key, value))
+		})
+		.collect()
+}
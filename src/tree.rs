@@ -0,0 +1,59 @@
+// verify --print-tree, which shows the entries of an image as a directory tree built from the / separated
+// components of their names, with the size of each entry after its last component
+//
+//	bin/
+//		init: 2048 bytes
+//	drivers/
+//		ext2: 4096 bytes
+//	early-init: 8192 bytes
+
+use std::collections::BTreeMap;
+
+use gen_initrd::parse::ParsedEntry;
+use gen_initrd::type_name;
+
+#[derive(Default)]
+struct Node {
+	// sizes of the entries with exactly this name, more than one if merged images repeated a name
+	sizes: Vec<u64>,
+	children: BTreeMap<String, Node>,
+}
+
+impl Node {
+	fn print(&self, depth: usize) {
+		for (name, child) in self.children.iter() {
+			let indent = "\t".repeat(depth);
+
+			for size in child.sizes.iter() {
+				println!("{}{}: {} bytes", indent, name, size);
+			}
+
+			// a name can be both an entry and a directory of other entries
+			if !child.children.is_empty() {
+				println!("{}{}/", indent, name);
+				child.print(depth + 1);
+			}
+		}
+	}
+}
+
+pub fn print<'a>(entries: impl Iterator<Item = &'a ParsedEntry<'a>>) {
+	let mut root = Node::default();
+
+	for entry in entries {
+		let name = if entry.name.is_empty() { type_name(entry.typ) } else { entry.name.to_owned() };
+
+		// empty components from leading, trailing, or repeated separators do not add a level
+		let mut components: Vec<&str> = name.split('/').filter(|component| !component.is_empty()).collect();
+		if components.is_empty() {
+			components.push(&name);
+		}
+
+		let node = components.into_iter()
+			.fold(&mut root, |node, component| node.children.entry(component.to_owned()).or_default());
+
+		node.sizes.push(entry.len);
+	}
+
+	root.print(1);
+}
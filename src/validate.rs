@@ -0,0 +1,118 @@
+// checks of the data of entries, most of which depend on the type of the entry
+//
+// every build runs the validator of each entry's type unless --no-validate is given, the other checks
+// are only run when their option is given
+//
+// early-init and fs-server may be raw binaries, so their validators, which check they are elf files, are only
+// run with --require-elf
+
+use std::path::Path;
+use std::process::exit;
+
+use gen_initrd::{Entry, EntryType};
+
+use crate::{elf, exit_code, part_list};
+
+// a check of the data of every entry of a type, which returns why an entry is invalid
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Validator {
+	// the part-list can be read
+	PartList,
+	// an elf file the kernel can start by jumping to its entry point
+	ElfExecutable,
+	// a 64 bit little endian elf file
+	Elf,
+}
+
+impl Validator {
+	// returns the validator run on entries of typ, types without one can hold any data, and the types which may
+	// be raw binaries only have one if require_elf is set
+	pub fn for_type(typ: EntryType, require_elf: bool) -> Option<Self> {
+		match typ {
+			EntryType::PartList => Some(Self::PartList),
+			EntryType::EarlyInit if require_elf => Some(Self::ElfExecutable),
+			EntryType::FsSever if require_elf => Some(Self::Elf),
+			EntryType::EarlyInit | EntryType::FsSever => None,
+			EntryType::Any | EntryType::HwAccessServer | EntryType::Comment | EntryType::InitArgs | EntryType::SourcePaths | EntryType::Tags => None,
+		}
+	}
+
+	pub fn check(&self, data: &[u8]) -> Result<(), String> {
+		match self {
+			Self::PartList => read_part_list(data).map(|_| ()),
+			Self::ElfExecutable => elf::entry_point_problem(data).map_or(Ok(()), |problem| Err(problem.to_owned())),
+			Self::Elf => elf::format_problem(data).map_or(Ok(()), |problem| Err(problem.to_owned())),
+		}
+	}
+}
+
+// returns each filesystem driver referenced by the part-list data, or why it can not be read
+fn read_part_list(data: &[u8]) -> Result<Vec<&str>, String> {
+	let text = std::str::from_utf8(data).map_err(|_| "it is not valid utf-8".to_owned())?;
+	part_list::referenced_drivers(text).map_err(|err| format!("line {}: {}", err.line, err.message))
+}
+
+// runs the validator of the type of every entry, and exits after reporting every invalid entry if there are any,
+// empty entries are left to check_empty_entries since they are usually left by a failed build
+pub fn run(entries: &[Entry], require_elf: bool) {
+	let mut valid = true;
+
+	for entry in entries.iter().filter(|entry| !entry.data.is_empty()) {
+		let validator = match Validator::for_type(entry.typ, require_elf) {
+			Some(validator) => validator,
+			None => continue,
+		};
+
+		if let Err(problem) = validator.check(&entry.data) {
+			error!("{} entry {} is invalid, {}", entry.typ.name(), entry.name, problem);
+			valid = false;
+		}
+	}
+
+	if !valid {
+		exit(exit_code::VALIDATION_FAILED);
+	}
+}
+
+// exits if the part-list references a filesystem driver which is not one of the entries,
+// drivers can be referenced by their full entry name or just the file name
+pub fn check_drivers(entries: &[Entry]) {
+	let part_list = match entries.iter().find(|entry| entry.typ == EntryType::PartList) {
+		Some(entry) => entry,
+		None => return,
+	};
+
+	let drivers = match read_part_list(&part_list.data) {
+		Ok(drivers) => drivers,
+		Err(problem) => {
+			error!("Part list {} can not be read, {}", part_list.name, problem);
+			exit(exit_code::VALIDATION_FAILED);
+		},
+	};
+
+	let is_included = |driver: &str| entries.iter().any(|entry| {
+		entry.name == driver || Path::new(entry.name).file_name().and_then(|name| name.to_str()) == Some(driver)
+	});
+
+	let mut missing: Vec<&str> = drivers.into_iter().filter(|driver| !is_included(driver)).collect();
+	missing.sort();
+	missing.dedup();
+
+	if !missing.is_empty() {
+		error!("Part list {} references filesystem drivers which are not included in the initrd: {}", part_list.name, missing.join(", "));
+		exit(exit_code::VALIDATION_FAILED);
+	}
+}
+
+// exits if early_init is an elf file the kernel can not start, other files are raw binaries which are allowed
+// unless require_elf is set
+pub fn check_entry_point(early_init: &Entry, require_elf: bool) {
+	if !require_elf && !elf::is_elf(&early_init.data) {
+		return;
+	}
+
+	if let Some(problem) = elf::entry_point_problem(&early_init.data) {
+		error!("Early-init {} can not be started by the kernel, {}", early_init.name, problem);
+		exit(exit_code::VALIDATION_FAILED);
+	}
+}
@@ -0,0 +1,122 @@
+// --watch, which rebuilds the initrd whenever one of its input files changes
+//
+// each build runs this executable again with the same arguments except --watch, so a failed build
+// only exits that process, and -n is added so changes that do not affect the output are skipped
+
+use std::collections::HashSet;
+use std::env;
+use std::ffi::OsString;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{exit, Command};
+use std::sync::mpsc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use notify_debouncer_mini::new_debouncer;
+use notify_debouncer_mini::notify::RecursiveMode;
+
+// changes closer together than this are treated as one, so a build does not start in the middle of a save
+const DEBOUNCE: Duration = Duration::from_millis(300);
+
+// current utc time of day as hh:mm:ss
+fn timestamp() -> String {
+	let secs = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |time| time.as_secs()) % 86400;
+	format!("{:02}:{:02}:{:02}", secs / 3600, secs / 60 % 60, secs % 60)
+}
+
+// absolute path of path, which does not have to exist as long as its directory does
+fn absolute_path(path: &str) -> PathBuf {
+	let path = Path::new(path);
+	let dir = match path.parent() {
+		Some(dir) if !dir.as_os_str().is_empty() => dir,
+		_ => Path::new("."),
+	};
+
+	match (fs::canonicalize(dir), path.file_name()) {
+		(Ok(dir), Some(name)) => dir.join(name),
+		_ => {
+			error!("Could not watch {}: its directory does not exist", path.display());
+			exit(1);
+		},
+	}
+}
+
+// modified time of every input, an input that can not be read has none
+fn modified_times(inputs: &HashSet<PathBuf>) -> Vec<Option<SystemTime>> {
+	let mut inputs: Vec<&PathBuf> = inputs.iter().collect();
+	inputs.sort();
+
+	inputs.iter()
+		.map(|input| fs::metadata(input).and_then(|metadata| metadata.modified()).ok())
+		.collect()
+}
+
+fn build(args: &[OsString]) {
+	let exe = match env::current_exe() {
+		Ok(exe) => exe,
+		Err(err) => {
+			error!("Could not find the gen-initrd executable: {}", err);
+			exit(1);
+		},
+	};
+
+	match Command::new(exe).args(args).status() {
+		Ok(status) if status.success() => println!("[{}] build finished", timestamp()),
+		Ok(_) => println!("[{}] build failed", timestamp()),
+		Err(err) => {
+			error!("Could not run build: {}", err);
+			exit(1);
+		},
+	}
+}
+
+// builds once, then rebuilds whenever one of the inputs changes, until interrupted
+pub fn run(inputs: &[&str]) -> ! {
+	let mut args: Vec<OsString> = env::args_os().skip(1).filter(|arg| arg != "--watch").collect();
+	if !args.iter().any(|arg| arg == "-n") {
+		args.push("-n".into());
+	}
+
+	let inputs: HashSet<PathBuf> = inputs.iter().map(|input| absolute_path(input)).collect();
+	// directories are watched instead of the files themselves, so files replaced by a rename are still seen
+	let dirs: HashSet<&Path> = inputs.iter().filter_map(|input| input.parent()).collect();
+
+	let (tx, rx) = mpsc::channel();
+	let mut debouncer = match new_debouncer(DEBOUNCE, tx) {
+		Ok(debouncer) => debouncer,
+		Err(err) => {
+			error!("Could not watch input files: {}", err);
+			exit(1);
+		},
+	};
+
+	for dir in dirs {
+		if let Err(err) = debouncer.watcher().watch(dir, RecursiveMode::NonRecursive) {
+			error!("Could not watch directory {}: {}", dir.display(), err);
+			exit(1);
+		}
+	}
+
+	build(&args);
+	let mut times = modified_times(&inputs);
+
+	for events in rx {
+		match events {
+			Ok(events) => {
+				if !events.iter().any(|event| inputs.contains(&event.path)) {
+					continue;
+				}
+
+				// building reads the inputs, which is also reported as an event
+				let new_times = modified_times(&inputs);
+				if new_times != times {
+					times = new_times;
+					build(&args);
+				}
+			},
+			Err(err) => warning!("error while watching input files: {}", err),
+		}
+	}
+
+	exit(0);
+}